@@ -1,16 +1,107 @@
 use std::{
-    fs::File,
-    io::Write,
+    collections::HashMap,
     path::{Path, PathBuf},
+    rc::Rc,
 };
 
-use crate::editor::{LogLevel, Notification};
+use crate::{
+    diagnostics::Diagnostics,
+    directory::Directory,
+    editor::{LogLevel, Notification},
+    encoding,
+    fileio::{self, FileStamp},
+    options::{self, OptionInfo, OptionScope, OptionValue, Options, SetOperation},
+    syntax::{Highlighter, Span, SyntaxDefinition},
+    util::{self, Position},
+};
+
+/// How many steps of edits can be undone
+const UNDO_LEVELS: usize = 1000;
+
+/// A single change of a buffer's contents: `removed` was replaced by `inserted` at `start`.
+/// Positions are given in characters.
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub start: Position<usize>,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    /// End of the changed text before the edit
+    pub fn old_end(&self) -> Position<usize> {
+        end_position(self.start, &self.removed)
+    }
+
+    /// End of the changed text after the edit
+    pub fn new_end(&self) -> Position<usize> {
+        end_position(self.start, &self.inserted)
+    }
+
+    /// Returns where a position ends up after the edit. Positions inside the replaced text move
+    /// to its start, positions at the start of the edit move along with inserted text.
+    pub fn transform(&self, position: Position<usize>) -> Position<usize> {
+        let old_end = self.old_end();
+        if (position.y, position.x) < (self.start.y, self.start.x) {
+            return position;
+        }
+        if (position.y, position.x) < (old_end.y, old_end.x) {
+            return self.start;
+        }
+
+        let new_end = self.new_end();
+        if position.y == old_end.y {
+            Position::new(new_end.x + position.x - old_end.x, new_end.y)
+        } else {
+            Position::new(position.x, position.y - old_end.y + new_end.y)
+        }
+    }
+}
+
+/// An edit together with the context needed to report it to language servers
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub edit: Edit,
+    /// The line the edit starts in as it was before the edit. Language servers may count columns
+    /// in other units than characters, which can't be computed from the edit alone.
+    pub line: String,
+}
+
+fn end_position(start: Position<usize>, text: &str) -> Position<usize> {
+    match text.rfind('\n') {
+        Some(index) => Position::new(
+            text[index + 1..].chars().count(),
+            start.y + text.matches('\n').count(),
+        ),
+        None => Position::new(start.x + text.chars().count(), start.y),
+    }
+}
 
 #[derive(Debug)]
 pub struct Buffer {
+    /// The buffer's contents. Changes have to go through [`Buffer::insert_text`] and
+    /// [`Buffer::remove_text`] so they can be tracked.
     pub lines: Vec<String>,
     pub path: Option<PathBuf>,
     pub changed: bool,
+    /// The file as it was when the buffer was read or written, or `None` if it didn't exist
+    pub stamp: Option<FileStamp>,
+    pub options: Options,
+    /// Incremented on every edit, used as document version for language servers
+    pub version: i32,
+    pub diagnostics: Diagnostics,
+    /// Set for buffers listing a directory, whose lines are the entries' names
+    pub directory: Option<Directory>,
+    /// The positions of the marks set with `m`, which move along with the text around them
+    pub marks: HashMap<char, Position<usize>>,
+    highlighter: Option<Highlighter>,
+    changes: Option<Vec<Change>>,
+    /// The steps [`Buffer::undo`] takes back, oldest first
+    undo_steps: Vec<Vec<Edit>>,
+    /// The steps that were undone, which [`Buffer::redo`] applies again
+    redo_steps: Vec<Vec<Edit>>,
+    /// The edits since the last step was finished
+    undo_step: Vec<Edit>,
 }
 
 impl Buffer {
@@ -19,61 +110,176 @@ impl Buffer {
     }
 
     pub fn new_with_path(path: Option<PathBuf>) -> Self {
+        // There is no file yet, so there is no line ending to keep. 'fixendofline' still adds
+        // one once there is text.
+        let mut options = Options::new_local(OptionScope::Buffer);
+        options.set(
+            options::find_option("endofline").unwrap(),
+            OptionValue::Bool(false),
+        );
+
         Self {
             lines: vec![String::new()],
             path,
             changed: false,
+            stamp: None,
+            options,
+            version: 0,
+            diagnostics: Diagnostics::default(),
+            directory: None,
+            marks: HashMap::new(),
+            highlighter: None,
+            changes: None,
+            undo_steps: Vec::new(),
+            redo_steps: Vec::new(),
+            undo_step: Vec::new(),
+        }
+    }
+
+    /// Lists the entries of a directory, see [`Directory::read`]
+    pub fn new_directory(path: &Path, hidden: bool, sort: &str) -> std::io::Result<Self> {
+        let directory = Directory::read(path, hidden, sort)?;
+        let mut buffer = Self::new_with_path(Some(path.to_path_buf()));
+        if !directory.entries().is_empty() {
+            buffer.lines = directory.entries().to_vec();
         }
+        // The listing is no file whose changes could be recovered
+        buffer.options.set(
+            options::find_option("swapfile").unwrap(),
+            OptionValue::Bool(false),
+        );
+        buffer.directory = Some(directory);
+        Ok(buffer)
     }
 
-    pub fn new_from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+    /// Reads a file, decoding it with the first of `encodings` that fits (see
+    /// [`encoding::decode_file`]).
+    pub fn new_from_file<P: AsRef<Path>, S: AsRef<str>>(
+        path: P,
+        encodings: &[S],
+    ) -> std::io::Result<Self> {
         let path = path.as_ref();
 
         if !path.exists() {
             return Ok(Self::new_with_path(Some(path.to_path_buf())));
         }
+        if path.is_dir() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::IsADirectory,
+                "is a directory",
+            ));
+        }
+        // Reading devices or pipes could block forever
         if !path.is_file() {
-            // TODO: Start the editor with the given directory as working directory once working
-            // directory is implemented
             return Ok(Buffer::new());
         }
 
-        // Rust's String.lines() doesn't seem to include a last empty line on a trailing newline,
-        // so .split('\n') has to be done by hand
-        let lines = std::fs::read_to_string(&path)?
-            .split('\n')
-            .map(|line| line.to_string().replace("\r", ""))
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(path, &bytes, encodings)
+    }
+
+    /// Creates a buffer for the file at `path` from its contents, decoding them with the first of
+    /// `encodings` that fits
+    pub fn from_bytes<S: AsRef<str>>(
+        path: &Path,
+        bytes: &[u8],
+        encodings: &[S],
+    ) -> std::io::Result<Self> {
+        let decoded = encoding::decode_file(bytes, encodings).ok_or_else(|| {
+            let encodings = encodings.iter().map(|e| e.as_ref()).collect::<Vec<_>>();
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("not valid in any of {}", encodings.join(",")),
+            )
+        })?;
+        let text = decoded.text;
+        let fileformat = fileio::detect_fileformat(&text);
+        let mut lines = text
+            .split(fileio::line_ending(fileformat))
+            .map(str::to_owned)
             .collect::<Vec<String>>();
-        let path = Some(path.to_path_buf());
+        // A line ending at the end of the file ends the last line instead of starting a new one
+        let endofline = lines.len() > 1 && lines.last().is_some_and(|line| line.is_empty());
+        if endofline {
+            lines.pop();
+        }
+
+        let mut options = Options::new_local(OptionScope::Buffer);
+        options.set(
+            options::find_option("fileformat").unwrap(),
+            OptionValue::String(fileformat.to_owned()),
+        );
+        options.set(
+            options::find_option("endofline").unwrap(),
+            OptionValue::Bool(endofline),
+        );
+        options.set(
+            options::find_option("fileencoding").unwrap(),
+            OptionValue::String(decoded.encoding.to_owned()),
+        );
+        options.set(
+            options::find_option("bomb").unwrap(),
+            OptionValue::Bool(decoded.bom),
+        );
 
         Ok(Self {
             lines,
-            path,
+            path: Some(path.to_path_buf()),
             changed: false,
+            stamp: FileStamp::read(path),
+            options,
+            version: 0,
+            diagnostics: Diagnostics::default(),
+            directory: None,
+            marks: HashMap::new(),
+            highlighter: None,
+            changes: None,
+            undo_steps: Vec::new(),
+            redo_steps: Vec::new(),
+            undo_step: Vec::new(),
         })
     }
 
+    /// Returns the contents to write to the buffer's file. Lines are separated by the line
+    /// ending of 'fileformat' and the last one is terminated by it if 'endofline' or
+    /// 'fixendofline' is set, except for an empty buffer, which is written as an empty file.
+    pub fn file_content(&self) -> String {
+        let line_ending = fileio::line_ending(self.options.get_string("fileformat"));
+        let mut content = self.lines.join(line_ending);
+        let is_empty = self.lines.len() == 1 && self.lines[0].is_empty();
+        if self.options.get_bool("endofline")
+            || (self.options.get_bool("fixendofline") && !is_empty)
+        {
+            content.push_str(line_ending);
+        }
+        content
+    }
+
     pub fn is_saved(&self) -> bool {
         !self.changed
     }
 
-    pub fn save(&mut self) -> std::io::Result<Notification> {
+    /// Writes the buffer to its file in 'fileencoding'. `global_options` decide how the file is
+    /// replaced and whether a backup is kept.
+    pub fn save(&mut self, global_options: &Options) -> std::io::Result<Notification> {
         if let Some(path) = &self.path {
-            let mut file = File::create(path)?;
-            for i in 0..(self.lines.len() - 1) {
-                file.write_all(&self.lines[i].as_bytes())?;
-                file.write_all("\n".as_bytes())?;
-            }
-            file.write_all(self.lines.last().unwrap().as_bytes())?;
+            let content = encoding::encode(
+                &self.file_content(),
+                self.options.get_string("fileencoding"),
+                self.options.get_bool("bomb"),
+            )
+            .map_err(|message| std::io::Error::new(std::io::ErrorKind::InvalidData, message))?;
+            fileio::write_file(path, &content, global_options)?;
 
             self.changed = false;
+            self.stamp = FileStamp::read(path);
 
-            let path = match path.to_str() {
-                Some(s) => s.to_owned(),
-                None => todo!(),
-            };
             return Ok(Notification::new(
-                format!("\"{}\" {}L written", path, self.lines.len()),
+                format!(
+                    "\"{}\" {}L written",
+                    util::display_path(path),
+                    self.lines.len()
+                ),
                 LogLevel::Info,
             ));
         } else {
@@ -84,7 +290,268 @@ impl Buffer {
         }
     }
 
+    /// Applies `:set` to one of the buffer's options. Like in vim, changing how the file is
+    /// written counts as a change of the buffer, but only if the value is actually different.
+    pub fn apply_option(
+        &mut self,
+        info: &OptionInfo,
+        operation: &SetOperation,
+    ) -> Result<OptionValue, String> {
+        let previous = self.options.get(info.name).clone();
+        let value = self.options.apply(info, operation)?;
+        let written_differently = matches!(
+            info.name,
+            "fileformat" | "endofline" | "fileencoding" | "bomb"
+        );
+        if written_differently && value != previous {
+            self.changed = true;
+        }
+        Ok(value)
+    }
+
     pub fn line_length(&self, index: usize) -> usize {
         self.lines[index].chars().count()
     }
+
+    /// Converts a position given in characters to the byte index in its line
+    pub fn byte_index(&self, position: Position<usize>) -> usize {
+        let line = &self.lines[position.y];
+        match line.char_indices().nth(position.x) {
+            Some((index, _)) => index,
+            None => line.len(),
+        }
+    }
+
+    /// Returns the text between two positions, lines being separated by `\n`
+    pub fn get_text(&self, start: Position<usize>, end: Position<usize>) -> String {
+        let start_index = self.byte_index(start);
+        let end_index = self.byte_index(end);
+        if start.y == end.y {
+            return self.lines[start.y][start_index..end_index].to_owned();
+        }
+
+        let mut text = self.lines[start.y][start_index..].to_owned();
+        for line in &self.lines[start.y + 1..end.y] {
+            text.push('\n');
+            text.push_str(line);
+        }
+        text.push('\n');
+        text.push_str(&self.lines[end.y][..end_index]);
+        text
+    }
+
+    /// Replaces the text between two positions. Both the replaced and the new text may span
+    /// multiple lines.
+    pub fn replace_text(&mut self, start: Position<usize>, end: Position<usize>, text: &str) {
+        let removed = self.get_text(start, end);
+        let old_line = self.changes.is_some().then(|| self.lines[start.y].clone());
+
+        let start_index = self.byte_index(start);
+        let end_index = self.byte_index(end);
+        let rest = self.lines[end.y][end_index..].to_owned();
+        self.lines[start.y].truncate(start_index);
+        self.lines.drain(start.y + 1..=end.y);
+
+        let mut parts = text.split('\n');
+        self.lines[start.y].push_str(parts.next().unwrap());
+        let mut new_lines = parts.map(|part| part.to_owned()).collect::<Vec<_>>();
+        match new_lines.last_mut() {
+            Some(last) => last.push_str(&rest),
+            None => self.lines[start.y].push_str(&rest),
+        }
+        self.lines.splice(start.y + 1..start.y + 1, new_lines);
+
+        let edit = Edit {
+            start,
+            removed,
+            inserted: text.to_owned(),
+        };
+        if let (Some(changes), Some(line)) = (&mut self.changes, old_line) {
+            changes.push(Change {
+                edit: edit.clone(),
+                line,
+            });
+        }
+        self.edited(edit);
+    }
+
+    /// Inserts text at the given position. The text may contain `\n` to insert line breaks.
+    pub fn insert_text(&mut self, position: Position<usize>, text: &str) {
+        self.replace_text(position, position, text);
+    }
+
+    /// Removes the text between two positions, joining lines if the range spans multiple lines.
+    pub fn remove_text(&mut self, start: Position<usize>, end: Position<usize>) {
+        self.replace_text(start, end, "");
+    }
+
+    /// Removes the lines from `first` to `last` together with their line breaks. Removing all
+    /// lines leaves a single empty one.
+    pub fn remove_lines(&mut self, first: usize, last: usize) {
+        let end = Position::new(self.line_length(last), last);
+        if last + 1 < self.lines.len() {
+            self.remove_text(Position::new(0, first), Position::new(0, last + 1));
+        } else if first > 0 {
+            let previous = Position::new(self.line_length(first - 1), first - 1);
+            self.remove_text(previous, end);
+        } else {
+            self.remove_text(Position::new(0, first), end);
+        }
+    }
+
+    /// Starts or stops recording the changes returned by [`Buffer::take_changes`]
+    pub fn record_changes(&mut self, record: bool) {
+        self.changes = record.then(Vec::new);
+    }
+
+    /// Returns the changes made since the last call, oldest first
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        match &mut self.changes {
+            Some(changes) => std::mem::take(changes),
+            None => Vec::new(),
+        }
+    }
+
+    /// Makes the edits since the last call a single step for [`Buffer::undo`]. The steps that
+    /// were undone can't be redone afterwards.
+    pub fn finish_undo_step(&mut self) {
+        if self.undo_step.is_empty() {
+            return;
+        }
+        self.undo_steps.push(std::mem::take(&mut self.undo_step));
+        self.redo_steps.clear();
+        if self.undo_steps.len() > UNDO_LEVELS {
+            self.undo_steps.remove(0);
+        }
+    }
+
+    /// Takes back the last step of edits and returns where it started, or `None` if there is
+    /// nothing to undo
+    pub fn undo(&mut self) -> Option<Position<usize>> {
+        self.finish_undo_step();
+        let step = self.undo_steps.pop()?;
+        let start = self.revert(&step);
+        // Reverting is recorded like any edit, which makes the step that redoes the undone one
+        let redo = std::mem::take(&mut self.undo_step);
+        self.redo_steps.push(redo);
+        Some(start)
+    }
+
+    /// Applies the step that was undone last again and returns where it started, or `None` if
+    /// there is nothing to redo
+    pub fn redo(&mut self) -> Option<Position<usize>> {
+        self.finish_undo_step();
+        let step = self.redo_steps.pop()?;
+        let start = self.revert(&step);
+        let undo = std::mem::take(&mut self.undo_step);
+        self.undo_steps.push(undo);
+        Some(start)
+    }
+
+    /// Reverts the edits of a step, last edit first, and returns where the first one started
+    fn revert(&mut self, step: &[Edit]) -> Position<usize> {
+        for edit in step.iter().rev() {
+            self.replace_text(edit.start, edit.new_end(), &edit.removed);
+        }
+        step[0].start
+    }
+
+    fn edited(&mut self, edit: Edit) {
+        self.changed = true;
+        self.version += 1;
+        self.diagnostics.edited(&edit);
+        for mark in self.marks.values_mut() {
+            *mark = edit.transform(*mark);
+        }
+        if let Some(highlighter) = &mut self.highlighter {
+            highlighter.edited(&edit);
+        }
+        self.undo_step.push(edit);
+    }
+
+    pub fn set_syntax(&mut self, syntax: Option<Rc<SyntaxDefinition>>) {
+        self.highlighter = syntax.map(|syntax| Highlighter::new(syntax, self.lines.len()));
+    }
+
+    /// Makes sure the highlighting of all lines up to and including `last_line` is up to date.
+    pub fn update_highlighting(&mut self, last_line: usize) {
+        if let Some(highlighter) = &mut self.highlighter {
+            highlighter.update(&self.lines, last_line);
+        }
+    }
+
+    /// Returns the highlighted parts of a line. Only valid after the line was updated with
+    /// [`Buffer::update_highlighting`].
+    pub fn highlight_spans(&self, line: usize) -> &[Span] {
+        match &self.highlighter {
+            Some(highlighter) => highlighter.spans(line),
+            None => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(buffer: &mut Buffer, argument: &str) {
+        let (info, operation) = options::parse_set_argument(argument).unwrap();
+        buffer.apply_option(info, &operation).unwrap();
+    }
+
+    #[test]
+    fn changing_the_file_format_changes_the_buffer() {
+        for (query, same, different) in [
+            ("ff?", "ff=unix", "ff=dos"),
+            ("eol?", "noeol", "eol"),
+            ("fenc?", "fenc=utf-8", "fenc=latin1"),
+            ("bomb?", "nobomb", "bomb"),
+        ] {
+            let mut buffer = Buffer::new();
+            apply(&mut buffer, query);
+            apply(&mut buffer, same);
+            assert!(!buffer.changed, "{} or {} changed the buffer", query, same);
+            apply(&mut buffer, different);
+            assert!(buffer.changed, "{} didn't change the buffer", different);
+        }
+    }
+
+    #[test]
+    fn marks_move_with_the_text() {
+        let mut buffer = Buffer::new();
+        buffer.insert_text(Position::new(0, 0), "one\ntwo\nthree");
+        buffer.marks.insert('a', Position::new(1, 2));
+        buffer.insert_text(Position::new(0, 0), "zero\n");
+        assert_eq!(buffer.marks[&'a'], Position::new(1, 3));
+        buffer.remove_lines(0, 1);
+        assert_eq!(buffer.lines, ["two", "three"]);
+        assert_eq!(buffer.marks[&'a'], Position::new(1, 1));
+    }
+
+    #[test]
+    fn undoes_and_redoes_steps() {
+        let mut buffer = Buffer::new();
+        buffer.insert_text(Position::new(0, 0), "one\ntwo");
+        buffer.finish_undo_step();
+        buffer.replace_text(Position::new(0, 1), Position::new(3, 1), "2");
+        buffer.insert_text(Position::new(0, 0), "zero\n");
+        assert_eq!(buffer.lines, ["zero", "one", "2"]);
+
+        assert_eq!(buffer.undo(), Some(Position::new(0, 1)));
+        assert_eq!(buffer.lines, ["one", "two"]);
+        assert_eq!(buffer.undo(), Some(Position::new(0, 0)));
+        assert_eq!(buffer.lines, [""]);
+        assert_eq!(buffer.undo(), None);
+
+        assert_eq!(buffer.redo(), Some(Position::new(0, 0)));
+        assert_eq!(buffer.redo(), Some(Position::new(0, 0)));
+        assert_eq!(buffer.lines, ["zero", "one", "2"]);
+        assert_eq!(buffer.redo(), None);
+
+        // A new change after undoing discards what could be redone
+        buffer.undo();
+        buffer.insert_text(Position::new(0, 0), "x");
+        assert_eq!(buffer.redo(), None);
+        assert_eq!(buffer.lines, ["xone", "two"]);
+    }
 }
@@ -0,0 +1,643 @@
+use std::{collections::HashMap, fs, io, path::PathBuf};
+
+use crate::{completion, config, options, picker};
+
+/// The commands offered by Tab completion of command names
+pub const COMMANDS: &[&str] = &[
+    "cc",
+    "cd",
+    "cdo",
+    "cfile",
+    "cfirst",
+    "checktime",
+    "clast",
+    "cmap",
+    "cnext",
+    "cnoremap",
+    "colorscheme",
+    "copen",
+    "cprevious",
+    "cunmap",
+    "diagnostics",
+    "edit",
+    "find",
+    "grep",
+    "highlight",
+    "imap",
+    "inoremap",
+    "iunmap",
+    "lcd",
+    "lsp",
+    "make",
+    "map",
+    "nmap",
+    "nnoremap",
+    "noremap",
+    "nunmap",
+    "pick",
+    "print",
+    "pwd",
+    "q",
+    "q!",
+    "read",
+    "set",
+    "setglobal",
+    "setlocal",
+    "source",
+    "tcd",
+    "unmap",
+    "update",
+    "view",
+    "vmap",
+    "vnoremap",
+    "vunmap",
+    "w",
+    "wq",
+];
+
+/// The commands whose arguments are completed as file names
+const FILE_COMMANDS: &[&str] = &[
+    "cf", "cfile", "e", "e!", "edit", "edit!", "r", "read", "so", "source", "vie", "view", "vie!",
+    "view!", "w", "wq",
+];
+
+/// The commands whose arguments are completed as directory names
+const DIRECTORY_COMMANDS: &[&str] = &["cd", "chdir", "lcd", "lchdir", "tcd", "tchdir"];
+
+const LSP_SUBCOMMANDS: &[&str] = &[
+    "codeaction",
+    "definition",
+    "format",
+    "hover",
+    "info",
+    "references",
+    "rename",
+    "restart",
+    "server",
+    "signature",
+    "stop",
+];
+
+/// The kinds of command lines, each with its own history
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CommandLineKind {
+    Command,
+    Search,
+}
+
+impl CommandLineKind {
+    pub fn prompt(self) -> char {
+        match self {
+            CommandLineKind::Command => ':',
+            CommandLineKind::Search => '/',
+        }
+    }
+
+    fn from_prompt(prompt: char) -> Option<Self> {
+        match prompt {
+            ':' => Some(CommandLineKind::Command),
+            '/' => Some(CommandLineKind::Search),
+            _ => None,
+        }
+    }
+}
+
+/// Previously executed command lines, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    entries: HashMap<CommandLineKind, Vec<String>>,
+}
+
+impl History {
+    /// Loads the history saved by [`History::save`]. A missing file results in an empty history.
+    pub fn load() -> Self {
+        let mut history = Self::default();
+        let Some(content) = history_file().and_then(|path| fs::read_to_string(path).ok()) else {
+            return history;
+        };
+        for line in content.lines() {
+            let mut chars = line.chars();
+            if let Some(kind) = chars.next().and_then(CommandLineKind::from_prompt) {
+                history
+                    .entries
+                    .entry(kind)
+                    .or_default()
+                    .push(chars.collect());
+            }
+        }
+        history
+    }
+
+    /// Writes the history to the state directory, one entry per line prefixed by its prompt
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = history_file() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut content = String::new();
+        for kind in [CommandLineKind::Command, CommandLineKind::Search] {
+            for entry in self.entries(kind) {
+                content.push(kind.prompt());
+                content.push_str(entry);
+                content.push('\n');
+            }
+        }
+        fs::write(path, content)
+    }
+
+    /// Adds an entry, moving it to the end if it's already there and forgetting the oldest
+    /// entries beyond `max`.
+    pub fn add(&mut self, kind: CommandLineKind, entry: &str, max: usize) {
+        if entry.trim().is_empty() {
+            return;
+        }
+        let entries = self.entries.entry(kind).or_default();
+        entries.retain(|existing| existing != entry);
+        entries.push(entry.to_owned());
+        if entries.len() > max {
+            entries.drain(..entries.len() - max);
+        }
+    }
+
+    pub fn entries(&self, kind: CommandLineKind) -> &[String] {
+        self.entries
+            .get(&kind)
+            .map_or(&[], |entries| entries.as_slice())
+    }
+
+    pub fn last(&self, kind: CommandLineKind) -> Option<&str> {
+        self.entries(kind).last().map(|entry| entry.as_str())
+    }
+}
+
+fn history_file() -> Option<PathBuf> {
+    Some(config::state_dir()?.join("history"))
+}
+
+/// The candidates of Tab completion, shown in the status line while cycling through them
+#[derive(Debug, Clone)]
+pub struct Wildmenu {
+    /// Where the completed word starts, in characters
+    start: usize,
+    pub candidates: Vec<String>,
+    pub selected: usize,
+}
+
+/// The text typed in command mode
+#[derive(Debug, Clone)]
+pub struct CommandLine {
+    pub kind: CommandLineKind,
+    text: String,
+    /// Position of the cursor in characters
+    cursor: usize,
+    /// Set by `Ctrl-R` until the name of the register to insert is typed
+    pub pending_register: bool,
+    /// The history entry shown and the text typed before browsing the history, which only
+    /// shows entries starting with it
+    history_position: Option<(usize, String)>,
+    pub wildmenu: Option<Wildmenu>,
+}
+
+impl CommandLine {
+    pub fn new(kind: CommandLineKind) -> Self {
+        Self {
+            kind,
+            text: String::new(),
+            cursor: 0,
+            pending_register: false,
+            history_position: None,
+            wildmenu: None,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    fn byte_index(&self, position: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(position)
+            .map_or(self.text.len(), |(index, _)| index)
+    }
+
+    /// Forgets the state of history browsing and completion after the text was changed in
+    /// another way
+    fn reset(&mut self) {
+        self.history_position = None;
+        self.wildmenu = None;
+    }
+
+    fn set_text(&mut self, text: String) {
+        self.cursor = text.chars().count();
+        self.text = text;
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        self.reset();
+        let index = self.byte_index(self.cursor);
+        self.text.insert_str(index, text);
+        self.cursor += text.chars().count();
+    }
+
+    /// Removes the character in front of the cursor
+    pub fn remove_char(&mut self) {
+        self.reset();
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.text.remove(self.byte_index(self.cursor));
+        }
+    }
+
+    /// Removes the character under the cursor
+    pub fn delete_char(&mut self) {
+        self.reset();
+        if self.cursor < self.text.chars().count() {
+            self.text.remove(self.byte_index(self.cursor));
+        }
+    }
+
+    /// Removes the word in front of the cursor along with the whitespace after it
+    pub fn remove_word(&mut self) {
+        self.reset();
+        let chars = self.text.chars().take(self.cursor).collect::<Vec<_>>();
+        let mut start = chars.len();
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        if let Some(&last) = chars[..start].last() {
+            let same_class = |c: char| !c.is_whitespace() && is_word(c) == is_word(last);
+            while start > 0 && same_class(chars[start - 1]) {
+                start -= 1;
+            }
+        }
+        self.remove_range(start, self.cursor);
+    }
+
+    /// Removes everything in front of the cursor
+    pub fn remove_to_start(&mut self) {
+        self.reset();
+        self.remove_range(0, self.cursor);
+    }
+
+    fn remove_range(&mut self, start: usize, end: usize) {
+        let range = self.byte_index(start)..self.byte_index(end);
+        self.text.replace_range(range, "");
+        self.cursor = start;
+    }
+
+    pub fn move_left(&mut self) {
+        self.reset();
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn move_right(&mut self) {
+        self.reset();
+        self.cursor = (self.cursor + 1).min(self.text.chars().count());
+    }
+
+    pub fn move_to_start(&mut self) {
+        self.reset();
+        self.cursor = 0;
+    }
+
+    pub fn move_to_end(&mut self) {
+        self.reset();
+        self.cursor = self.text.chars().count();
+    }
+
+    /// Replaces the text by the next older or newer history entry that starts with the text
+    /// typed before browsing. Going past the newest entry brings back the typed text.
+    pub fn browse_history(&mut self, history: &History, older: bool) {
+        self.wildmenu = None;
+        let entries = history.entries(self.kind);
+        let (position, prefix) = self
+            .history_position
+            .take()
+            .unwrap_or_else(|| (entries.len(), self.text.clone()));
+
+        let found = if older {
+            entries[..position]
+                .iter()
+                .rposition(|entry| entry.starts_with(&prefix))
+        } else {
+            entries
+                .iter()
+                .enumerate()
+                .skip(position + 1)
+                .find(|(_, entry)| entry.starts_with(&prefix))
+                .map(|(i, _)| i)
+        };
+
+        match found {
+            Some(i) => {
+                self.set_text(entries[i].clone());
+                self.history_position = Some((i, prefix));
+            }
+            None if older => {
+                // Stay at the oldest matching entry
+                self.history_position = Some((position, prefix));
+            }
+            None => self.set_text(prefix),
+        }
+    }
+
+    /// Completes the word in front of the cursor, or selects the next or previous candidate if
+    /// the wildmenu is already shown. Returns false if there's nothing to complete.
+    pub fn complete(&mut self, forward: bool) -> bool {
+        self.history_position = None;
+        if let Some(wildmenu) = &mut self.wildmenu {
+            let count = wildmenu.candidates.len();
+            wildmenu.selected = if forward {
+                (wildmenu.selected + 1) % count
+            } else {
+                (wildmenu.selected + count - 1) % count
+            };
+            let (start, candidate) = (
+                wildmenu.start,
+                wildmenu.candidates[wildmenu.selected].clone(),
+            );
+            self.replace_word(start, &candidate);
+            return true;
+        }
+
+        let before = self.text.chars().take(self.cursor).collect::<String>();
+        let (start, candidates) = match self.kind {
+            CommandLineKind::Command => complete_command_line(&before),
+            CommandLineKind::Search => return false,
+        };
+        match candidates.as_slice() {
+            [] => return false,
+            [candidate] => self.replace_word(start, candidate),
+            _ => {
+                let selected = if forward { 0 } else { candidates.len() - 1 };
+                self.replace_word(start, &candidates[selected]);
+                self.wildmenu = Some(Wildmenu {
+                    start,
+                    candidates,
+                    selected,
+                });
+            }
+        }
+        true
+    }
+
+    fn replace_word(&mut self, start: usize, word: &str) {
+        let range = self.byte_index(start)..self.byte_index(self.cursor);
+        self.text.replace_range(range, word);
+        self.cursor = start + word.chars().count();
+    }
+}
+
+/// Splits commands separated by `|`, e.g. the argument of `:cdo`. `\|` is a `|` that doesn't
+/// separate commands, like in a shell command.
+pub fn split_commands(text: &str) -> Vec<String> {
+    let mut commands = vec![String::new()];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                chars.next();
+                commands.last_mut().unwrap().push('|');
+            }
+            '|' => commands.push(String::new()),
+            c => commands.last_mut().unwrap().push(c),
+        }
+    }
+    commands
+        .into_iter()
+        .map(|command| command.trim().to_owned())
+        .filter(|command| !command.is_empty())
+        .collect()
+}
+
+/// Whether the command is `:!cmd` or `:{range}!cmd`. Those are the only commands that take a
+/// range, so other commands are never parsed as one, even if they start like an address.
+pub fn is_shell_command(command: &str) -> bool {
+    command
+        .trim_start_matches(|c: char| c.is_ascii_digit() || ".$%+-,".contains(c))
+        .starts_with('!')
+}
+
+/// The first and last line of a range, counted from 0
+pub type LineRange = (usize, usize);
+
+/// Splits the range off the front of a command line, e.g. `%`, `5`, `.,$` or `.-1,.+2`, and
+/// returns the lines it covers, counted from 0, and the rest of the command. `cursor` is the
+/// line `.` stands for. The range may go past the last line, which commands that take one check.
+pub fn parse_range(
+    command: &str,
+    cursor: usize,
+    line_count: usize,
+) -> Result<(Option<LineRange>, &str), String> {
+    if let Some(rest) = command.strip_prefix('%') {
+        return Ok((Some((0, line_count.saturating_sub(1))), rest));
+    }
+    let (first, rest) = parse_address(command, cursor, line_count)?;
+    let Some(first) = first else {
+        return Ok((None, command));
+    };
+    let (last, rest) = match rest.strip_prefix(',') {
+        Some(rest) => match parse_address(rest, cursor, line_count)? {
+            (Some(last), rest) => (last, rest),
+            (None, _) => return Err("Invalid range".to_owned()),
+        },
+        None => (first, rest),
+    };
+    Ok((Some((first.min(last), first.max(last))), rest))
+}
+
+/// Parses a line number, `.` or `$` followed by any number of `+N` and `-N`. A missing number
+/// after the sign stands for 1 and a missing line in front of it for `.`.
+fn parse_address(
+    text: &str,
+    cursor: usize,
+    line_count: usize,
+) -> Result<(Option<usize>, &str), String> {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (mut line, mut rest) = if digits > 0 {
+        let number = text[..digits]
+            .parse::<usize>()
+            .map_err(|_| "Invalid range".to_owned())?;
+        (Some(number.saturating_sub(1) as isize), &text[digits..])
+    } else if let Some(rest) = text.strip_prefix('.') {
+        (Some(cursor as isize), rest)
+    } else if let Some(rest) = text.strip_prefix('$') {
+        (Some(line_count as isize - 1), rest)
+    } else {
+        (None, text)
+    };
+
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        rest = &rest[1..];
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let offset = match digits {
+            0 => 1,
+            _ => rest[..digits]
+                .parse::<isize>()
+                .map_err(|_| "Invalid range".to_owned())?,
+        };
+        rest = &rest[digits..];
+        let base = line.unwrap_or(cursor as isize);
+        let moved = if sign == '+' {
+            base.checked_add(offset)
+        } else {
+            base.checked_sub(offset)
+        };
+        line = Some(moved.ok_or_else(|| "Invalid range".to_owned())?);
+    }
+
+    match line {
+        Some(line) if line < 0 => Err("Invalid range".to_owned()),
+        line => Ok((line.map(|line| line as usize), rest)),
+    }
+}
+
+/// Returns where the word in front of the cursor starts and what it could be completed to
+fn complete_command_line(before: &str) -> (usize, Vec<String>) {
+    let Some((command, _)) = before.trim_start().split_once(char::is_whitespace) else {
+        let start = before.chars().count() - before.trim_start().chars().count();
+        let name = before.trim_start();
+        let candidates = COMMANDS
+            .iter()
+            .filter(|command| command.starts_with(name))
+            .map(|command| command.to_string())
+            .collect();
+        return (start, candidates);
+    };
+
+    let word = before
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default();
+    let start = before.chars().count() - word.chars().count();
+    let arguments = before.trim_start()[command.len()..]
+        .split_whitespace()
+        .count();
+    let is_first_argument = arguments == 0 || (arguments == 1 && !word.is_empty());
+
+    let candidates = match command {
+        "set" | "se" | "setlocal" | "setl" | "setglobal" | "setg" => complete_option(word),
+        "colorscheme" | "colo" => complete_colorscheme(word),
+        "lsp" if is_first_argument => LSP_SUBCOMMANDS
+            .iter()
+            .filter(|subcommand| subcommand.starts_with(word))
+            .map(|subcommand| subcommand.to_string())
+            .collect(),
+        "pick" | "pic" if is_first_argument => picker::SOURCES
+            .iter()
+            .filter(|source| source.starts_with(word))
+            .map(|source| source.to_string())
+            .collect(),
+        command if FILE_COMMANDS.contains(&command) => completion::complete_path(word)
+            .into_iter()
+            .map(|item| item.word)
+            .filter(|path| path.starts_with(word))
+            .collect(),
+        command if DIRECTORY_COMMANDS.contains(&command) => completion::complete_path(word)
+            .into_iter()
+            .map(|item| item.word)
+            .filter(|path| path.starts_with(word) && path.ends_with('/'))
+            .collect(),
+        _ => Vec::new(),
+    };
+    (start, candidates)
+}
+
+fn complete_option(word: &str) -> Vec<String> {
+    // Values aren't completed
+    if word.contains(['=', ':', '!', '&', '?']) {
+        return Vec::new();
+    }
+    // `no` and `inv` in front of boolean options are kept
+    let is_option_prefix = |word: &str| {
+        options::all_options()
+            .iter()
+            .any(|info| info.name.starts_with(word))
+    };
+    let (prefix, name) = match ["no", "inv"]
+        .iter()
+        .find_map(|prefix| Some((*prefix, word.strip_prefix(prefix)?)))
+    {
+        Some(split) if !is_option_prefix(word) => split,
+        _ => ("", word),
+    };
+
+    let mut names = options::all_options()
+        .iter()
+        .filter(|info| prefix.is_empty() || info.get_type() == options::OptionType::Bool)
+        .map(|info| info.name)
+        .filter(|option| option.starts_with(name))
+        .map(|option| format!("{}{}", prefix, option))
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn complete_colorscheme(word: &str) -> Vec<String> {
+    let mut names = vec!["default".to_owned()];
+    if let Some(entries) =
+        config::config_dir().and_then(|dir| fs::read_dir(dir.join("colors")).ok())
+    {
+        names.extend(entries.flatten().filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "bvim" {
+                return None;
+            }
+            Some(path.file_stem()?.to_str()?.to_owned())
+        }));
+    }
+    names.retain(|name| name.starts_with(word));
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_commands_at_bars() {
+        assert_eq!(
+            split_commands(".!sed s/a/b/ | update"),
+            [".!sed s/a/b/", "update"]
+        );
+        assert_eq!(
+            split_commands(r".!tr a b \| sort|w"),
+            [".!tr a b | sort", "w"]
+        );
+        assert_eq!(split_commands(r"print a\b"), [r"print a\b"]);
+        assert!(split_commands(" | ").is_empty());
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("%!sort", 3, 10), Ok((Some((0, 9)), "!sort")));
+        assert_eq!(parse_range(".,.+5!fmt", 3, 10), Ok((Some((3, 8)), "!fmt")));
+        assert_eq!(parse_range("$,-!tac", 3, 10), Ok((Some((2, 9)), "!tac")));
+        assert_eq!(parse_range("5!cat", 3, 10), Ok((Some((4, 4)), "!cat")));
+        assert_eq!(parse_range("!ls", 3, 10), Ok((None, "!ls")));
+        assert!(parse_range("-5!cat", 3, 10).is_err());
+        assert!(parse_range("99999999999999999999999!cat", 3, 10).is_err());
+        assert!(parse_range(".+9223372036854775807!cat", 3, 10).is_err());
+        assert!(parse_range("$-9223372036854775807-9!cat", 3, 10).is_err());
+    }
+
+    #[test]
+    fn only_shell_commands_take_a_range() {
+        assert!(is_shell_command("!ls"));
+        assert!(is_shell_command("%!sort"));
+        assert!(is_shell_command(".,$-1!fmt"));
+        assert!(!is_shell_command("r !ls"));
+        assert!(!is_shell_command("-5"));
+        assert!(!is_shell_command("99999999999999999999999"));
+        assert!(!is_shell_command("q!"));
+    }
+}
@@ -0,0 +1,416 @@
+use std::path::{Path, PathBuf};
+
+use crate::{buffer::Buffer, fuzzy, highlight::Highlights, screen::Screen, util::Position};
+
+/// The most lines of documentation shown next to the popup menu
+const MAX_DOCUMENTATION_HEIGHT: usize = 15;
+const MAX_DOCUMENTATION_WIDTH: usize = 60;
+
+/// The kind of text a source completes, which decides where the completed text starts and which
+/// typed characters continue it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextKind {
+    Keyword,
+    Path,
+}
+
+impl TextKind {
+    pub fn contains(self, c: char) -> bool {
+        match self {
+            TextKind::Keyword => c.is_alphanumeric() || c == '_',
+            TextKind::Path => {
+                !c.is_whitespace()
+                    && !matches!(
+                        c,
+                        '"' | '\''
+                            | '`'
+                            | '('
+                            | ')'
+                            | '<'
+                            | '>'
+                            | '['
+                            | ']'
+                            | '{'
+                            | '}'
+                            | ','
+                            | ';'
+                            | '|'
+                    )
+            }
+        }
+    }
+
+    /// Returns the column where the text of this kind in front of the cursor starts
+    pub fn start(self, line: &str, cursor: usize) -> usize {
+        let before = line.chars().take(cursor).collect::<Vec<_>>();
+        before
+            .iter()
+            .rposition(|c| !self.contains(*c))
+            .map_or(0, |index| index + 1)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompletionItem {
+    /// The text that replaces the completed text
+    pub word: String,
+    /// The text shown in the menu
+    pub label: String,
+    /// What the item is, e.g. `Function`, shown next to the label
+    pub kind: Option<String>,
+    pub documentation: Option<String>,
+}
+
+impl CompletionItem {
+    pub fn new(word: String) -> Self {
+        Self {
+            label: word.clone(),
+            word,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct CompletionContext<'a> {
+    /// The buffers to complete from, the current one first
+    pub buffers: &'a [&'a Buffer],
+    pub cursor: Position<usize>,
+    /// The text between the start of the completion and the cursor
+    pub base: &'a str,
+}
+
+/// Something that can provide candidates for the completion popup
+pub trait CompletionSource {
+    fn name(&self) -> &'static str;
+
+    fn text_kind(&self) -> TextKind {
+        TextKind::Keyword
+    }
+
+    /// Returns the candidates for the text in front of the cursor. Sources that answer
+    /// asynchronously return `None` and hand their candidates to [`Completion::deliver`] later.
+    fn complete(&mut self, context: &CompletionContext) -> Option<Vec<CompletionItem>>;
+}
+
+/// Completes words that appear in the open buffers, the ones after the cursor first
+pub struct BufferWords;
+
+impl CompletionSource for BufferWords {
+    fn name(&self) -> &'static str {
+        "words"
+    }
+
+    fn complete(&mut self, context: &CompletionContext) -> Option<Vec<CompletionItem>> {
+        let mut words = Vec::new();
+        for (i, buffer) in context.buffers.iter().enumerate() {
+            let lines = &buffer.lines;
+            // Continue from the cursor to the end of the current buffer and wrap around
+            let first = if i == 0 { context.cursor.y } else { 0 };
+            for y in (first..lines.len()).chain(0..first) {
+                let mut column = 0;
+                for word in lines[y].split(|c: char| !TextKind::Keyword.contains(c)) {
+                    let start = column;
+                    column += word.chars().count() + 1;
+                    // The word being completed doesn't complete itself
+                    if i == 0
+                        && y == context.cursor.y
+                        && start + word.chars().count() == context.cursor.x
+                    {
+                        continue;
+                    }
+                    if word.chars().count() > 1 && !words.iter().any(|known| known == word) {
+                        words.push(word.to_owned());
+                    }
+                }
+            }
+        }
+        Some(words.into_iter().map(CompletionItem::new).collect())
+    }
+}
+
+/// Completes file and directory names relative to the working directory
+pub struct Paths;
+
+impl CompletionSource for Paths {
+    fn name(&self) -> &'static str {
+        "paths"
+    }
+
+    fn text_kind(&self) -> TextKind {
+        TextKind::Path
+    }
+
+    fn complete(&mut self, context: &CompletionContext) -> Option<Vec<CompletionItem>> {
+        Some(complete_path(context.base))
+    }
+}
+
+/// Returns the files and directories in the directory the given text points into, directories
+/// ending with a slash. The part after the last slash isn't used for filtering, it only decides
+/// whether hidden files are included. `~` stands for the home directory.
+pub fn complete_path(base: &str) -> Vec<CompletionItem> {
+    let (directory, name) = match base.rfind('/') {
+        Some(index) => base.split_at(index + 1),
+        None => ("", base),
+    };
+    let path = match directory.strip_prefix('~') {
+        Some(rest) => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(rest.trim_start_matches('/')))
+            .unwrap_or_else(|| PathBuf::from(directory)),
+        None if directory.is_empty() => PathBuf::from("."),
+        None => PathBuf::from(directory),
+    };
+
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return Vec::new();
+    };
+    let mut items = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_str()?.to_owned();
+            // Hidden files are only offered once a dot was typed
+            if file_name.starts_with('.') && !name.starts_with('.') {
+                return None;
+            }
+            let is_dir = Path::is_dir(&entry.path());
+            let suffix = if is_dir { "/" } else { "" };
+            Some(CompletionItem {
+                word: format!("{}{}{}", directory, file_name, suffix),
+                label: format!("{}{}", file_name, suffix),
+                kind: Some(if is_dir { "Directory" } else { "File" }.to_owned()),
+                documentation: None,
+            })
+        })
+        .collect::<Vec<_>>();
+    items.sort_by(|a, b| a.label.cmp(&b.label));
+    items
+}
+
+/// The state of the completion popup menu in insert mode
+#[derive(Debug, Clone)]
+pub struct Completion {
+    /// The name of the source the candidates come from
+    pub source: &'static str,
+    pub text_kind: TextKind,
+    /// Where the completed text starts
+    pub start: Position<usize>,
+    /// Whether the source still has to deliver its candidates
+    pub pending: bool,
+    items: Vec<CompletionItem>,
+    /// Indices of the items matching the typed text, best match first
+    matches: Vec<usize>,
+    /// Index into `matches`
+    selected: usize,
+}
+
+impl Completion {
+    /// Asks the source for candidates for the text in front of the cursor in the first of the
+    /// given buffers
+    pub fn start(
+        source: &mut dyn CompletionSource,
+        buffers: &[&Buffer],
+        cursor: Position<usize>,
+    ) -> Self {
+        let line = &buffers[0].lines[cursor.y];
+        let text_kind = source.text_kind();
+        let start = Position::new(text_kind.start(line, cursor.x), cursor.y);
+        let base = line
+            .chars()
+            .skip(start.x)
+            .take(cursor.x - start.x)
+            .collect::<String>();
+
+        let context = CompletionContext {
+            buffers,
+            cursor,
+            base: &base,
+        };
+        let items = source.complete(&context);
+        let mut completion = Self {
+            source: source.name(),
+            text_kind,
+            start,
+            pending: items.is_none(),
+            items: items.unwrap_or_default(),
+            matches: Vec::new(),
+            selected: 0,
+        };
+        completion.filter(&base);
+        completion
+    }
+
+    /// Returns the text typed since the completion started, or `None` if the cursor left it
+    pub fn base(&self, buffer: &Buffer, cursor: Position<usize>) -> Option<String> {
+        if cursor.y != self.start.y || cursor.x < self.start.x {
+            return None;
+        }
+        let base = buffer.lines[cursor.y]
+            .chars()
+            .skip(self.start.x)
+            .take(cursor.x - self.start.x)
+            .collect::<String>();
+        base.chars()
+            .all(|c| self.text_kind.contains(c))
+            .then_some(base)
+    }
+
+    /// Sets the candidates of a source that answered asynchronously
+    pub fn deliver(&mut self, items: Vec<CompletionItem>, base: &str) {
+        self.items = items;
+        self.pending = false;
+        self.filter(base);
+    }
+
+    /// Narrows the candidates down to the ones matching the typed text and selects the best one
+    pub fn filter(&mut self, base: &str) {
+        self.selected = 0;
+        // Without anything typed the source's order is kept
+        if base.is_empty() {
+            self.matches = (0..self.items.len()).collect();
+            return;
+        }
+        let mut matches = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| Some((fuzzy::score(base, &item.word)?, i)))
+            .collect::<Vec<_>>();
+        // The sort is stable, so equally good matches keep the source's order
+        matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        self.matches = matches.into_iter().map(|(_, i)| i).collect();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    /// Moves the selection by the given number of items, wrapping around at both ends
+    pub fn select(&mut self, offset: isize) {
+        if self.matches.is_empty() {
+            return;
+        }
+        let count = self.matches.len() as isize;
+        self.selected = (self.selected as isize + offset).rem_euclid(count) as usize;
+    }
+
+    pub fn select_last(&mut self) {
+        self.selected = self.matches.len().saturating_sub(1);
+    }
+
+    pub fn selected(&self) -> Option<&CompletionItem> {
+        self.matches.get(self.selected).map(|i| &self.items[*i])
+    }
+
+    /// Renders the menu below or above the text at `anchor` and the documentation of the
+    /// selected item next to it. `max_height` limits the number of items shown if not 0, and
+    /// `area` is the size of the space the popup may cover.
+    pub fn render(
+        &self,
+        screen: &mut Screen,
+        highlights: &Highlights,
+        anchor: (u16, u16),
+        area: (u16, u16),
+        max_height: usize,
+    ) {
+        let (anchor_x, anchor_y) = (anchor.0 as usize, anchor.1 as usize);
+        let (area_width, area_height) = (area.0 as usize, area.1 as usize);
+        if self.matches.is_empty() || area_width == 0 {
+            return;
+        }
+
+        // Prefer showing the menu below the cursor unless there's more space above it
+        let below = area_height.saturating_sub(anchor_y + 1);
+        let above = anchor_y;
+        let space = below.max(above);
+        let mut height = self.matches.len().min(space);
+        if max_height > 0 {
+            height = height.min(max_height);
+        }
+        if height == 0 {
+            return;
+        }
+        let top = if below >= height || below >= above {
+            anchor_y + 1
+        } else {
+            anchor_y - height
+        };
+
+        let items = self.matches.iter().map(|i| &self.items[*i]);
+        let label_width = items
+            .clone()
+            .map(|item| item.label.chars().count())
+            .max()
+            .unwrap_or(0);
+        let kind_width = items
+            .map(|item| item.kind.as_ref().map_or(0, |kind| kind.chars().count()))
+            .max()
+            .unwrap_or(0);
+        let width = (label_width + kind_width + if kind_width > 0 { 3 } else { 2 })
+            .min(area_width)
+            .min(area_width / 2 + 10);
+        let left = anchor_x.min(area_width - width);
+
+        // Scroll the menu so the selected item is visible
+        let first = (self.selected + 1).saturating_sub(height);
+        for row in 0..height {
+            let index = first + row;
+            let item = &self.items[self.matches[index]];
+            let selected = index == self.selected;
+            let kind = item.kind.as_deref().unwrap_or_default();
+            let label_space = width.saturating_sub(kind.chars().count() + 2);
+            let label = item.label.chars().take(label_space).collect::<String>();
+
+            highlights.apply(screen, if selected { "PmenuSel" } else { "Pmenu" });
+            let text = format!(" {:<width$}", label, width = label_space);
+            screen.print_at(left as u16, (top + row) as u16, &text);
+            highlights.apply(
+                screen,
+                if selected {
+                    "PmenuKindSel"
+                } else {
+                    "PmenuKind"
+                },
+            );
+            let text = format!("{:<width$}", kind, width = width - label_space - 1);
+            screen.print_at((left + 1 + label_space) as u16, (top + row) as u16, text);
+        }
+
+        if let Some(documentation) = self.selected().and_then(|item| item.documentation.as_ref()) {
+            // Place the documentation right of the menu, or left of it if there's more space there
+            let right = area_width.saturating_sub(left + width);
+            let (doc_left, doc_width) = if right >= left {
+                (left + width, right.min(MAX_DOCUMENTATION_WIDTH))
+            } else {
+                let doc_width = left.min(MAX_DOCUMENTATION_WIDTH);
+                (left - doc_width, doc_width)
+            };
+            if doc_width < 3 {
+                return;
+            }
+            let lines = wrap(documentation, doc_width - 2);
+            let doc_height = lines.len().min(MAX_DOCUMENTATION_HEIGHT).min(area_height);
+            let doc_top = top.min(area_height - doc_height);
+            highlights.apply(screen, "PmenuDoc");
+            for (row, line) in lines.iter().take(doc_height).enumerate() {
+                let text = format!(" {:<width$} ", line, width = doc_width - 2);
+                screen.print_at(doc_left as u16, (doc_top + row) as u16, text);
+            }
+        }
+        screen.clear_colors();
+    }
+}
+
+/// Breaks text into lines of at most `width` characters
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let chars = line.chars().collect::<Vec<_>>();
+        if chars.is_empty() {
+            lines.push(String::new());
+        }
+        for chunk in chars.chunks(width.max(1)) {
+            lines.push(chunk.iter().collect());
+        }
+    }
+    lines
+}
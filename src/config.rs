@@ -0,0 +1,26 @@
+use std::{env, path::PathBuf};
+
+/// Returns bvim's configuration directory, i.e. `$XDG_CONFIG_HOME/bvim` or `~/.config/bvim` if
+/// `$XDG_CONFIG_HOME` isn't set.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("bvim"))
+}
+
+/// Returns the path of the config file that's sourced at startup.
+pub fn config_file() -> Option<PathBuf> {
+    Some(config_dir()?.join("bvimrc"))
+}
+
+/// Returns the directory for state that persists between sessions like the command history,
+/// i.e. `$XDG_STATE_HOME/bvim` or `~/.local/state/bvim` if `$XDG_STATE_HOME` isn't set.
+pub fn state_dir() -> Option<PathBuf> {
+    let state_home = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_home.join("bvim"))
+}
@@ -0,0 +1,172 @@
+use crate::{buffer::Edit, util::Position};
+
+/// How severe a diagnostic is, the most severe one coming first
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    /// Converts the `DiagnosticSeverity` of the language server protocol
+    pub fn from_lsp(severity: i64) -> Self {
+        match severity {
+            2 => Severity::Warning,
+            3 => Severity::Info,
+            4 => Severity::Hint,
+            _ => Severity::Error,
+        }
+    }
+
+    pub fn to_lsp(self) -> i64 {
+        match self {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+            Severity::Info => 3,
+            Severity::Hint => 4,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+            Severity::Hint => "hint",
+        }
+    }
+
+    /// The text shown in the sign column
+    pub fn sign(self) -> &'static str {
+        match self {
+            Severity::Error => "E",
+            Severity::Warning => "W",
+            Severity::Info => "I",
+            Severity::Hint => "H",
+        }
+    }
+
+    /// Returns the name of the highlight group for the given kind of display, e.g.
+    /// `DiagnosticSignWarn` for `"Sign"`.
+    pub fn highlight_group(self, kind: &str) -> String {
+        let severity = match self {
+            Severity::Error => "Error",
+            Severity::Warning => "Warn",
+            Severity::Info => "Info",
+            Severity::Hint => "Hint",
+        };
+        format!("Diagnostic{}{}", kind, severity)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub start: Position<usize>,
+    pub end: Position<usize>,
+    pub severity: Severity,
+    pub message: String,
+    /// The tool that found the problem, e.g. `rustc` or `clippy`
+    pub source: Option<String>,
+    pub code: Option<String>,
+}
+
+impl Diagnostic {
+    /// Returns the range of characters the diagnostic covers in the given line, which may be
+    /// empty
+    pub fn columns(&self, line: usize, line_length: usize) -> Option<(usize, usize)> {
+        if line < self.start.y || line > self.end.y {
+            return None;
+        }
+        let start = if line == self.start.y {
+            self.start.x
+        } else {
+            0
+        };
+        let end = if line == self.end.y {
+            self.end.x
+        } else {
+            line_length
+        };
+        Some((start.min(line_length), end.min(line_length).max(start)))
+    }
+}
+
+/// The diagnostics of a buffer, grouped by the provider that reported them so e.g. a language
+/// server and a linter don't replace each other's diagnostics
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    providers: Vec<(String, Vec<Diagnostic>)>,
+}
+
+impl Diagnostics {
+    /// Replaces all diagnostics of the given provider
+    pub fn set(&mut self, provider: &str, diagnostics: Vec<Diagnostic>) {
+        self.providers.retain(|(name, _)| name != provider);
+        if !diagnostics.is_empty() {
+            self.providers.push((provider.to_owned(), diagnostics));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+
+    /// Returns all diagnostics ordered by their position
+    pub fn all(&self) -> Vec<&Diagnostic> {
+        let mut diagnostics = self
+            .providers
+            .iter()
+            .flat_map(|(_, diagnostics)| diagnostics)
+            .collect::<Vec<_>>();
+        diagnostics.sort_by_key(|diagnostic| {
+            (diagnostic.start.y, diagnostic.start.x, diagnostic.severity)
+        });
+        diagnostics
+    }
+
+    /// Returns the diagnostics touching the given line
+    pub fn on_line(&self, line: usize) -> impl Iterator<Item = &Diagnostic> {
+        self.providers
+            .iter()
+            .flat_map(|(_, diagnostics)| diagnostics)
+            .filter(move |diagnostic| diagnostic.start.y <= line && line <= diagnostic.end.y)
+    }
+
+    /// Returns the most severe diagnostic touching the given line
+    pub fn worst_on_line(&self, line: usize) -> Option<&Diagnostic> {
+        self.on_line(line)
+            .min_by_key(|diagnostic| diagnostic.severity)
+    }
+
+    /// Returns the first diagnostic after or before the given position, wrapping around at the
+    /// end of the buffer.
+    pub fn next(&self, position: Position<usize>, forward: bool) -> Option<&Diagnostic> {
+        let all = self.all();
+        let key = |diagnostic: &&Diagnostic| (diagnostic.start.y, diagnostic.start.x);
+        let position = (position.y, position.x);
+        if forward {
+            all.iter()
+                .find(|diagnostic| key(diagnostic) > position)
+                .or(all.first())
+                .copied()
+        } else {
+            all.iter()
+                .rev()
+                .find(|diagnostic| key(diagnostic) < position)
+                .or(all.last())
+                .copied()
+        }
+    }
+
+    /// Moves the diagnostics along with the text they are attached to
+    pub fn edited(&mut self, edit: &Edit) {
+        for (_, diagnostics) in &mut self.providers {
+            for diagnostic in diagnostics {
+                diagnostic.start = edit.transform(diagnostic.start);
+                diagnostic.end = edit.transform(diagnostic.end);
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// The orders entries can be listed in, as accepted by 'dirsort'. Directories always come first.
+pub const SORT_ORDERS: &[&str] = &["name", "time", "size"];
+
+/// The entries of a directory as listed in a buffer: one name per line, with a `/` after the
+/// names of directories. Editing the lines and writing the buffer creates, renames and deletes
+/// the files.
+#[derive(Debug)]
+pub struct Directory {
+    pub path: PathBuf,
+    /// The names listed when the directory was read
+    entries: Vec<String>,
+}
+
+/// A change to a directory, found by comparing its listing with the entries it was read with
+#[derive(Debug, Clone)]
+pub enum Operation {
+    Create(String),
+    Rename(String, String),
+    Delete(String),
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Create(name) => write!(f, "create {}", name),
+            Operation::Rename(from, to) => write!(f, "rename {} to {}", from, to),
+            Operation::Delete(name) if name.ends_with('/') => {
+                write!(f, "delete {} and everything in it", name)
+            }
+            Operation::Delete(name) => write!(f, "delete {}", name),
+        }
+    }
+}
+
+impl Directory {
+    /// Reads the entries of the directory in the given 'dirsort' order. Hidden entries, whose
+    /// names start with a dot, are left out unless `hidden` is set.
+    pub fn read(path: &Path, hidden: bool, sort: &str) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !hidden && name.starts_with('.') {
+                continue;
+            }
+            // Symlinks are listed like what they point to, broken ones like files
+            let metadata = match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata,
+                Err(_) => entry.metadata()?,
+            };
+            entries.push((name, metadata));
+        }
+
+        entries.sort_by(|(a_name, a), (b_name, b)| {
+            b.is_dir()
+                .cmp(&a.is_dir())
+                .then_with(|| match sort {
+                    // Newest and largest first
+                    "time" => b.modified().ok().cmp(&a.modified().ok()),
+                    "size" => b.len().cmp(&a.len()),
+                    _ => Ordering::Equal,
+                })
+                .then_with(|| a_name.cmp(b_name))
+        });
+        let entries = entries
+            .into_iter()
+            .map(|(name, metadata)| match metadata.is_dir() {
+                true => name + "/",
+                false => name,
+            })
+            .collect();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Returns the path of a name in the listing
+    pub fn entry_path(&self, name: &str) -> PathBuf {
+        self.path.join(name.trim_end_matches('/'))
+    }
+
+    /// Compares the lines of the listing with the entries it was read with. Lines that are still
+    /// listed keep their entry, even if they moved. The other lines take the place of the removed
+    /// entries of the same kind that came after the same kept entry, in order, which makes them
+    /// renames. The rest of the lines are created and the rest of the removed entries deleted.
+    ///
+    /// The operations are returned in the order they have to be applied in: deletions, renames
+    /// and creations.
+    pub fn changes(&self, lines: &[String]) -> Result<Vec<Operation>, String> {
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| (entry.trim_end_matches('/'), entry.as_str()))
+            .collect::<HashMap<_, _>>();
+        let mut names = HashSet::new();
+        let mut listed = Vec::new();
+        let mut kept = HashSet::new();
+        for line in lines.iter().filter(|line| !line.is_empty()) {
+            validate_name(line)?;
+            let name = line.trim_end_matches('/');
+            if !names.insert(name) {
+                return Err(format!("Listed twice: {}", line));
+            }
+            // Adding or removing the slash of a directory changes nothing
+            match entries.get(name) {
+                Some(entry) => {
+                    kept.insert(*entry);
+                    listed.push(*entry);
+                }
+                None => listed.push(line.as_str()),
+            }
+        }
+
+        let mut removed: HashMap<Option<&str>, VecDeque<&str>> = HashMap::new();
+        let mut anchor = None;
+        for entry in &self.entries {
+            if kept.contains(entry.as_str()) {
+                anchor = Some(entry.as_str());
+            } else {
+                removed.entry(anchor).or_default().push_back(entry);
+            }
+        }
+
+        let mut renames = Vec::new();
+        let mut creations = Vec::new();
+        let mut anchor = None;
+        for line in listed {
+            if kept.contains(line) {
+                anchor = Some(line);
+                continue;
+            }
+            // Files are only renamed to files and directories to directories
+            let entry = removed.get_mut(&anchor).and_then(|entries| {
+                let index = entries
+                    .iter()
+                    .position(|entry| entry.ends_with('/') == line.ends_with('/'))?;
+                entries.remove(index)
+            });
+            match entry {
+                Some(entry) => renames.push((entry, line)),
+                None => creations.push(line),
+            }
+        }
+        let renamed = renames
+            .iter()
+            .map(|(from, _)| *from)
+            .collect::<HashSet<_>>();
+        let deletions = self
+            .entries
+            .iter()
+            .map(String::as_str)
+            .filter(|entry| !kept.contains(entry) && !renamed.contains(entry))
+            .collect::<Vec<_>>();
+
+        // Nothing may be overwritten, except entries that are deleted first. Swapping names would
+        // need a temporary name and isn't supported.
+        let deleted = deletions
+            .iter()
+            .map(|name| name.trim_end_matches('/'))
+            .collect::<HashSet<_>>();
+        let sources = renames
+            .iter()
+            .map(|(from, _)| from.trim_end_matches('/'))
+            .collect::<HashSet<_>>();
+        for target in renames
+            .iter()
+            .map(|(_, to)| *to)
+            .chain(creations.iter().copied())
+        {
+            let name = target.trim_end_matches('/');
+            if sources.contains(name) {
+                return Err(format!(
+                    "Can't rename to {}, which is renamed itself",
+                    target
+                ));
+            }
+            if !deleted.contains(name) && fs::symlink_metadata(self.entry_path(name)).is_ok() {
+                return Err(format!("{} already exists", target));
+            }
+        }
+
+        let mut operations = deletions
+            .into_iter()
+            .map(|name| Operation::Delete(name.to_owned()))
+            .collect::<Vec<_>>();
+        operations.extend(
+            renames
+                .into_iter()
+                .map(|(from, to)| Operation::Rename(from.to_owned(), to.to_owned())),
+        );
+        operations.extend(
+            creations
+                .into_iter()
+                .map(|name| Operation::Create(name.to_owned())),
+        );
+        Ok(operations)
+    }
+
+    /// Applies the operations, stopping at the first one that fails. Missing parent directories
+    /// of new names are created.
+    pub fn apply(&self, operations: &[Operation]) -> Result<(), String> {
+        for operation in operations {
+            let result = match operation {
+                Operation::Create(name) => {
+                    let path = self.entry_path(name);
+                    if name.ends_with('/') {
+                        fs::create_dir_all(&path)
+                    } else {
+                        create_parent(&path).and_then(|_| File::create_new(&path).map(|_| ()))
+                    }
+                }
+                Operation::Rename(from, to) => {
+                    let target = self.entry_path(to);
+                    create_parent(&target).and_then(|_| fs::rename(self.entry_path(from), target))
+                }
+                Operation::Delete(name) => {
+                    let path = self.entry_path(name);
+                    // Symlinks to directories are removed, not what they point to
+                    match fs::symlink_metadata(&path) {
+                        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&path),
+                        _ => fs::remove_file(&path),
+                    }
+                }
+            };
+            result.map_err(|e| format!("Can't {}: {}", operation, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Makes sure a name written in a listing stays inside the listed directory
+fn validate_name(name: &str) -> Result<(), String> {
+    let valid = Path::new(name)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !valid {
+        return Err(format!("Invalid name: {}", name));
+    }
+    Ok(())
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
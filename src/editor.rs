@@ -1,19 +1,66 @@
-use std::{thread, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
-use blessings::{ClearType, CursorStyle, Screen, WindowBounds};
+use blessings::{ClearType, CursorStyle, WindowBounds};
 use crossterm::{
-    event::{self, Event, KeyEvent, KeyEventKind, MouseEventKind},
-    style::Color,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEventKind},
     terminal,
 };
+use serde_json::{json, Value};
 
 use crate::{
-    buffer::Buffer,
-    keymap::{Action, KeyMap},
+    buffer::{Buffer, Edit},
+    cmdline::{self, CommandLine, CommandLineKind, History},
+    completion::{BufferWords, Completion, CompletionSource, Paths},
+    config,
+    diagnostics::{Diagnostic, Severity},
+    directory::{self, Operation},
+    encoding,
+    fileio::{self, FileStamp},
+    finder::Files,
+    hex::{self, BinaryBuffer, HexView},
+    highlight::Highlights,
+    jumplist::{Jump, JumpList},
+    keymap::{self, Action, KeyMap, MapCommand, MapMode, Mapping, MappingLookup, SequenceLookup},
+    keys,
+    largefile::LargeFileView,
+    lsp::{self, Client, Event as LspEvent, LspCompletion, PositionEncoding, Request},
+    make::{self, Make},
+    motion::{self, Motion, Operator, Parsed, PendingOperator},
+    options::{self, OptionInfo, OptionScope, OptionType, OptionValue, Options, SetOperation},
+    picker::{self, Items, Picker, PickerItem, PickerSource, Target},
+    quickfix::{self, ErrorFormat, QuickfixList},
+    register::{self, Register, Registers},
+    screen::Screen,
+    swap::{self, SwapFile, SwapInfo},
+    syntax::SyntaxSet,
+    util::{self, Position},
     window::Window,
 };
 
+/// Result of an ex command, the error being the message shown to the user
+pub type CommandResult = std::result::Result<(), String>;
+
+const NOT_MODIFIABLE: &str = "Cannot make changes, 'modifiable' is off";
+
+/// Messages of panics on background threads, e.g. of a language server's reader, which the main
+/// loop shows as errors since the editor keeps running
+static THREAD_PANICS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Reports the panic of a background thread to the user, see [`THREAD_PANICS`]
+pub fn report_thread_panic(message: String) {
+    if let Ok(mut panics) = THREAD_PANICS.lock() {
+        panics.push(message);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum LogLevel {
     Info,
@@ -31,6 +78,74 @@ impl Notification {
     pub fn new(message: String, level: LogLevel) -> Self {
         Self { message, level }
     }
+
+    fn is_multiline(&self) -> bool {
+        self.message.contains('\n')
+    }
+}
+
+/// A question that has to be answered with one of the given keys before editing continues
+#[derive(Debug, Clone)]
+struct Prompt {
+    message: String,
+    /// The keys answering the prompt and how they are shown, e.g. `('r', "[R]ecover")`
+    choices: Vec<(char, &'static str)>,
+    kind: PromptKind,
+}
+
+#[derive(Debug, Clone)]
+enum PromptKind {
+    /// What to do about the swap file found when opening a file
+    SwapFile(SwapInfo),
+    /// Whether to reload the current file after another program changed it
+    FileChanged,
+    /// Whether to apply the changes made to the listing of a directory
+    DirectoryChanges(Vec<Operation>),
+}
+
+/// Shown instead of the window for files that aren't edited as text
+#[derive(Debug)]
+enum FileView {
+    /// A binary file edited byte by byte
+    Hex(HexView),
+    /// A read-only view of a file larger than 'largefile'
+    Large(LargeFileView),
+}
+
+/// A key waiting to be processed, either typed by the user or the result of a mapping
+#[derive(Debug, Copy, Clone)]
+struct TypeaheadKey {
+    event: KeyEvent,
+    /// Whether user mappings may be applied to this key
+    remap: bool,
+}
+
+/// Which working directory `:cd` and its variants change. As the process has a single working
+/// directory, it's always set to the one that applies to the current window.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DirectoryScope {
+    Global,
+    Tab,
+    Window,
+}
+
+/// Which values `:set` and its variants modify for local options
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SetScope {
+    All,
+    Local,
+    Global,
+}
+
+/// Normal mode commands that take the next key as their argument
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CharCommand {
+    /// `"`, which selects the register for the next yank, delete or put
+    SelectRegister,
+    /// `m`, which sets a mark
+    SetMark,
+    /// `` ` `` and `'`, which jump to a mark or to the start of its line
+    JumpToMark { linewise: bool },
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -49,11 +164,11 @@ impl Mode {
         }
     }
 
-    fn get_color(&self) -> Color {
+    fn highlight_group(&self) -> &'static str {
         match self {
-            Mode::Normal => Color::Blue,
-            Mode::Insert => Color::Magenta,
-            Mode::Command => Color::Green,
+            Mode::Normal => "ModeNormal",
+            Mode::Insert => "ModeInsert",
+            Mode::Command => "ModeCommand",
         }
     }
 }
@@ -64,39 +179,151 @@ pub(crate) struct Editor {
     width: u16,
     height: u16,
     keymap: KeyMap,
+    typeahead: VecDeque<TypeaheadKey>,
+    last_key: Instant,
+    mapping_depth: usize,
+    /// The count typed before a command in normal mode
+    count: Option<usize>,
+    /// The operator waiting for its motion
+    operator: Option<PendingOperator>,
+    /// The command waiting for the next key, like `m` for the name of the mark
+    pending_char: Option<CharCommand>,
+    registers: Registers,
+    /// The register selected with `"` for the next yank, delete or put
+    register: Option<char>,
+    jumps: JumpList,
+    options: Options,
+    highlights: Highlights,
+    colorscheme: Option<String>,
+    syntaxes: SyntaxSet,
+    /// Commands of the language servers to start for each file type
+    language_servers: HashMap<String, String>,
+    /// Running language servers by file type
+    lsp_clients: HashMap<String, Client>,
+    /// File type and URI of the current buffer if it's synced with a language server
+    lsp_document: Option<(String, String)>,
+    /// The code actions offered by the last `:lsp codeaction`
+    code_actions: Vec<Value>,
+    /// The popup menu of insert mode completion if it's open
+    completion: Option<Completion>,
+    /// The picker if it's open, e.g. the fuzzy file finder
+    picker: Option<Picker>,
+    quickfix: QuickfixList,
+    /// The running `:make` and whether to jump to the first error when it's done
+    make: Option<(Make, bool)>,
     window: Window,
     terminate: bool,
-    command: String,
+    command_line: CommandLine,
+    history: History,
+    /// The pattern of the last search, used by `n` and `N`
+    last_search: Option<String>,
     notification: Option<Notification>,
+    prompt: Option<Prompt>,
+    /// The swap file of the current buffer, if it has one
+    swap_file: Option<SwapFile>,
+    /// Shown instead of the window while a binary or large file is open. The window keeps an
+    /// empty buffer with the file's path and options then.
+    file_view: Option<FileView>,
+    /// The working directory set with `:tcd`, which takes precedence over the global one
+    tab_directory: Option<PathBuf>,
+    /// The working directory before the last change, for `:cd -`
+    previous_directory: Option<PathBuf>,
 }
 
 impl Editor {
-    pub fn new(path: Option<String>) -> Result<Self> {
+    /// Creates the editor and opens the file at `path`, which is 'readonly' if `readonly` is set
+    pub fn new(path: Option<String>, readonly: bool) -> Result<Self> {
         let (width, height) = terminal::size()?;
 
         let screen = Screen::new()?;
 
         let keymap = KeyMap::default();
 
-        let window_bounds = WindowBounds::new(0, 0, width, height - 2);
-        let buffer = if let Some(path) = &path {
-            Buffer::new_from_file(path)?
-        } else {
-            Buffer::new()
-        };
-        let window = Window::new(buffer, window_bounds);
+        // The file is only read once the config has set 'fileencodings'
+        let window = Window::new(Buffer::new(), WindowBounds::new(0, 0, width, height - 2));
+
+        let mut options = Options::new_global();
+        // Assume support for 24-bit colors if the terminal advertises it
+        let truecolor = matches!(
+            std::env::var("COLORTERM").as_deref(),
+            Ok("truecolor") | Ok("24bit")
+        );
+        options.set(
+            options::find_option("termguicolors").unwrap(),
+            OptionValue::Bool(truecolor),
+        );
+
+        let (syntaxes, syntax_errors) = SyntaxSet::load();
 
-        Ok(Self {
+        let mut editor = Self {
             screen,
             mode: Mode::Normal,
             width,
             height,
             keymap,
+            typeahead: VecDeque::new(),
+            last_key: Instant::now(),
+            mapping_depth: 0,
+            count: None,
+            operator: None,
+            pending_char: None,
+            registers: Registers::default(),
+            register: None,
+            jumps: JumpList::default(),
+            options,
+            highlights: Highlights::new(truecolor),
+            colorscheme: None,
+            syntaxes,
+            language_servers: HashMap::new(),
+            lsp_clients: HashMap::new(),
+            lsp_document: None,
+            code_actions: Vec::new(),
+            completion: None,
+            picker: None,
+            quickfix: QuickfixList::default(),
+            make: None,
             window,
             terminate: false,
-            command: String::new(),
+            command_line: CommandLine::new(CommandLineKind::Command),
+            history: History::load(),
+            last_search: None,
             notification: None,
-        })
+            prompt: None,
+            swap_file: None,
+            file_view: None,
+            tab_directory: None,
+            previous_directory: None,
+        };
+
+        if let Err(message) = join_errors(syntax_errors) {
+            editor.notify(message, LogLevel::Error);
+        }
+
+        if let Some(path) = config::config_file() {
+            if path.is_file() {
+                if let Err(message) = editor.source(&path) {
+                    editor.notify(message, LogLevel::Error);
+                }
+            }
+        }
+
+        if let Some(path) = &path {
+            let options = EditOptions {
+                readonly,
+                ..EditOptions::default()
+            };
+            let (buffer, file_view) = editor.read_file(Path::new(path), options)?;
+            editor.window.set_buffer(buffer);
+            editor.file_view = file_view;
+        }
+
+        // Detecting the file type has to wait for the config, which may disable modelines
+        if let Err(message) = editor.detect_filetype() {
+            editor.notify(message, LogLevel::Error);
+        }
+        editor.open_swap_file();
+
+        Ok(editor)
     }
 
     pub fn run(&mut self) -> Result<()> {
@@ -105,6 +332,13 @@ impl Editor {
             &mut std::io::stdout(),
             crossterm::event::EnableMouseCapture,
         )?;
+        crossterm::QueueableCommand::queue(
+            &mut std::io::stdout(),
+            crossterm::event::EnableFocusChange,
+        )?;
+
+        // Process keys fed in before the editor started running, e.g. by a script
+        self.process_typeahead(false)?;
 
         while !self.terminate {
             // handle all input events
@@ -118,44 +352,97 @@ impl Editor {
                             self.window.mouse_down(button, event.row, event.column);
                         }
                     }
+                    // Another program may have changed the file while the user was away
+                    Event::FocusGained => self.check_time(),
                     Event::Resize {
                         0: width,
                         1: height,
-                    } => {
-                        self.width = width;
-                        self.height = height;
-
-                        self.screen.resize(width, height);
-                        self.window.set_bounds(WindowBounds::new(
-                            0,
-                            0,
-                            self.width,
-                            self.height - 2,
-                        ));
-                    }
+                    } => self.resize(width, height),
                     e => {
                         self.notify(format!("unhandled event: {:?}", e), LogLevel::Debug);
                     }
                 }
             }
 
+            // resolve ambiguous mappings if the user stopped typing
+            if !self.typeahead.is_empty()
+                && self.options.get_bool("timeout")
+                && self.last_key.elapsed()
+                    >= Duration::from_millis(self.options.get_int("timeoutlen") as u64)
+            {
+                self.process_typeahead(true)?;
+            }
+
+            // handle messages of language servers
+            self.handle_lsp_events();
+            // Their edits, like formatting the buffer, are steps of their own
+            self.finish_undo_step();
+
+            let panics = THREAD_PANICS
+                .lock()
+                .map(|mut panics| std::mem::take(&mut *panics))
+                .unwrap_or_default();
+            for message in panics {
+                self.notify(message, LogLevel::Error);
+            }
+
+            self.poll_make();
+
+            // show the items the picker's source found in the meantime
+            if let Some(picker) = &mut self.picker {
+                picker.poll();
+            }
+
+            self.update_swap_file();
+
             // render tui
             self.render();
             // show rendered screen
             self.screen.show()?;
 
-            thread::sleep(Duration::from_millis(16));
+            thread::sleep(Duration::from_millis(
+                self.options.get_int("frametime") as u64
+            ));
         }
 
+        for client in self.lsp_clients.values_mut() {
+            client.shutdown();
+        }
+        // Losing the history isn't worth keeping the user from quitting
+        let _ = self.history.save();
+        self.close_swap_file();
+
         crossterm::QueueableCommand::queue(
             &mut std::io::stdout(),
             crossterm::event::DisableMouseCapture,
         )?;
+        crossterm::QueueableCommand::queue(
+            &mut std::io::stdout(),
+            crossterm::event::DisableFocusChange,
+        )?;
         self.screen.end()?;
 
         Ok(())
     }
 
+    fn resize(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+
+        self.screen.resize(width, height);
+        self.window.set_bounds(self.window_bounds());
+        let bounds = self.window_bounds();
+        match &mut self.file_view {
+            Some(FileView::Hex(hex_view)) => hex_view.set_bounds(bounds),
+            Some(FileView::Large(view)) => {
+                if let Err(e) = view.set_bounds(bounds) {
+                    self.notify(format!("Can't read the file: {}", e), LogLevel::Error);
+                }
+            }
+            None => {}
+        }
+    }
+
     fn render(&mut self) {
         //let start = std::time::Instant::now();
 
@@ -165,30 +452,104 @@ impl Editor {
         // contents if we're just going to overwrite them anyways
         self.screen.clear(ClearType::All);
 
-        self.window.render(&mut self.screen);
+        let virtual_text = self.options.get_bool("virtualtext");
+        match &self.file_view {
+            Some(FileView::Hex(hex_view)) => hex_view.render(&mut self.screen, &self.highlights),
+            Some(FileView::Large(view)) => view.render(&mut self.screen, &self.highlights),
+            None => self
+                .window
+                .render(&mut self.screen, &self.highlights, virtual_text),
+        }
         if self.mode == Mode::Normal || self.mode == Mode::Insert {
             cursor = self.screen.get_cursor();
         }
+        if let (Some(completion), Mode::Insert) = (&self.completion, self.mode) {
+            // The menu is aligned with the start of the completed text
+            let offset = self.window.get_cursor().x - completion.start.x;
+            let anchor = (cursor.0.saturating_sub(offset as u16), cursor.1);
+            completion.render(
+                &mut self.screen,
+                &self.highlights,
+                anchor,
+                (self.width, self.height - 2),
+                self.options.get_int("pumheight") as usize,
+            );
+        }
+        if let Some(picker) = &self.picker {
+            cursor = picker.render(
+                &mut self.screen,
+                &self.highlights,
+                (self.width, self.height - 1),
+            );
+        }
 
         self.render_mode(self.mode);
 
+        self.fill_row(self.height - 1, "Normal");
         if self.mode == Mode::Command {
-            self.screen.move_to(0, self.height - 1);
-            self.screen.print_char(':');
-            self.screen.print(&self.command);
-
-            cursor = self.screen.get_cursor();
+            cursor = self.render_command_line();
         }
+        self.screen.clear_colors();
 
-        if let Some(notification) = &self.notification {
-            let (fg, bg) = match notification.level {
-                LogLevel::Info => (Color::Reset, Color::Reset),
-                LogLevel::Error => (Color::Red, Color::Reset),
-                LogLevel::Debug => (Color::Magenta, Color::Reset),
+        if let Some(notification) = self.notification.clone() {
+            let group = match notification.level {
+                LogLevel::Info => "Normal",
+                LogLevel::Error => "ErrorMsg",
+                LogLevel::Debug => "DebugMsg",
             };
-            self.screen.set_colors(fg, bg);
+            self.highlights.apply(&mut self.screen, group);
+            if notification.is_multiline() {
+                // Messages spanning multiple lines are shown above the last line and stay until
+                // the next key press
+                let lines = notification.message.lines().collect::<Vec<_>>();
+                let visible = lines.len().min(self.height as usize - 1);
+                let first_row = self.height as usize - 1 - visible;
+                for (i, line) in lines[lines.len() - visible..].iter().enumerate() {
+                    self.fill_row((first_row + i) as u16, group);
+                    self.screen.move_to(0, (first_row + i) as u16);
+                    self.screen.print(line);
+                }
+
+                self.fill_row(self.height - 1, "MoreMsg");
+                self.screen.move_to(0, self.height - 1);
+                self.screen.print("Press any key to continue");
+                cursor = self.screen.get_cursor();
+            } else {
+                self.screen
+                    .print_at(0, self.height - 1, &notification.message);
+            }
+            self.screen.clear_colors();
+        } else if self.mode == Mode::Normal {
+            // Show the diagnostic of the cursor line where messages would go
+            let buffer = self.window.get_buffer();
+            if let Some(diagnostic) = buffer.diagnostics.worst_on_line(self.window.get_cursor().y) {
+                let group = diagnostic.severity.highlight_group("");
+                let message = format_diagnostic(diagnostic);
+                let message = message.lines().next().unwrap_or_default().to_owned();
+                self.highlights.apply(&mut self.screen, &group);
+                self.screen.print_at(0, self.height - 1, message);
+                self.screen.clear_colors();
+            }
+        }
+
+        if let Some(prompt) = self.prompt.clone() {
+            cursor = self.render_prompt(&prompt);
+        }
+
+        // Show the keys of a mapping that is being typed
+        if self.options.get_bool("showcmd") && self.mode != Mode::Command {
+            let pending = self
+                .typeahead
+                .iter()
+                .map(|key| key.event)
+                .collect::<Vec<_>>();
+            let pending = keys::format_keys(&pending);
+            // Like in vim, only the last 10 characters are shown
+            let skipped = pending.chars().count().saturating_sub(10);
+            let pending = pending.chars().skip(skipped).collect::<String>();
+            self.highlights.apply(&mut self.screen, "Normal");
             self.screen
-                .print_at(0, self.height - 1, &notification.message);
+                .print_at(self.width.saturating_sub(11), self.height - 1, pending);
             self.screen.clear_colors();
         }
 
@@ -202,19 +563,339 @@ impl Editor {
         self.screen.move_to(cursor.0, cursor.1);
     }
 
+    /// Renders the prompt's message above the last line and its choices in the last line, and
+    /// returns the cursor position
+    fn render_prompt(&mut self, prompt: &Prompt) -> (u16, u16) {
+        let lines = prompt.message.lines().collect::<Vec<_>>();
+        let visible = lines.len().min(self.height as usize - 1);
+        let first_row = self.height as usize - 1 - visible;
+        for (i, line) in lines[lines.len() - visible..].iter().enumerate() {
+            self.fill_row((first_row + i) as u16, "Normal");
+            self.screen.print_at(0, (first_row + i) as u16, line);
+        }
+
+        let choices = prompt
+            .choices
+            .iter()
+            .map(|(_, label)| *label)
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.fill_row(self.height - 1, "MoreMsg");
+        self.screen.move_to(0, self.height - 1);
+        self.screen.print(format!("{}: ", choices));
+        let cursor = self.screen.get_cursor();
+        self.screen.clear_colors();
+        cursor
+    }
+
+    /// Renders the command line and the wildmenu above it and returns the cursor position
+    fn render_command_line(&mut self) -> (u16, u16) {
+        let line = &self.command_line;
+        let width = self.width as usize;
+        // Scroll the text horizontally so the cursor stays visible
+        let skip = (line.cursor() + 2).saturating_sub(width);
+        let text = format!("{}{}", line.kind.prompt(), line.text());
+        let text = text.chars().skip(skip).take(width).collect::<String>();
+        self.highlights.apply(&mut self.screen, "Normal");
+        self.screen.print_at(0, self.height - 1, text);
+        let cursor = ((line.cursor() + 1 - skip) as u16, self.height - 1);
+        if line.pending_register {
+            self.screen.print_at(cursor.0, cursor.1, "\"");
+        }
+
+        if let Some(wildmenu) = self.command_line.wildmenu.clone() {
+            // Start with the first candidate that still lets the selected one fit
+            let mut first = wildmenu.selected;
+            let mut used = wildmenu.candidates[first].chars().count();
+            while first > 0 && used + wildmenu.candidates[first - 1].chars().count() + 2 <= width {
+                first -= 1;
+                used += wildmenu.candidates[first].chars().count() + 2;
+            }
+
+            self.fill_row(self.height - 2, "StatusLine");
+            let mut x = 0;
+            for (i, candidate) in wildmenu.candidates.iter().enumerate().skip(first) {
+                if x + candidate.chars().count() > width {
+                    break;
+                }
+                let group = if i == wildmenu.selected {
+                    "WildMenu"
+                } else {
+                    "StatusLine"
+                };
+                self.highlights.apply(&mut self.screen, group);
+                self.screen.print_at(x as u16, self.height - 2, candidate);
+                x += candidate.chars().count() + 2;
+            }
+        }
+        self.screen.clear_colors();
+        cursor
+    }
+
     fn render_mode(&mut self, mode: Mode) {
-        self.screen.move_to(0, self.height - 2);
-        self.screen.clear(ClearType::CurrentLine);
+        self.fill_row(self.height - 2, "StatusLine");
 
-        // TODO: make text bold
-        self.screen.set_colors(Color::Black, mode.get_color());
+        self.highlights
+            .apply(&mut self.screen, mode.highlight_group());
+        self.screen.move_to(0, self.height - 2);
         self.screen.print(format!(" {} ", mode.to_str()));
+
+        // Show how the file is written at the right end
+        let options = &self.window.get_buffer().options;
+        let mut info = match &self.file_view {
+            Some(FileView::Hex(hex_view)) => format!("binary 0x{:x}", hex_view.cursor()),
+            Some(FileView::Large(view)) => format!(
+                "large file {}/{}",
+                view.cursor_line() + 1,
+                view.line_count()
+                    .map_or("?".to_owned(), |count| count.to_string())
+            ),
+            None if self.window.get_buffer().directory.is_some() => {
+                format!("sorted by {}", self.options.get_string("dirsort"))
+            }
+            None => format!(
+                "{}{} {}",
+                options.get_string("fileencoding"),
+                if options.get_bool("bomb") {
+                    "[BOM]"
+                } else {
+                    ""
+                },
+                options.get_string("fileformat")
+            ),
+        };
+        if self.file_view.is_none()
+            && !options.get_bool("endofline")
+            && !options.get_bool("fixendofline")
+        {
+            info.insert_str(0, "[noeol] ");
+        }
+        if !options.get_bool("modifiable") {
+            info.insert_str(0, "[-] ");
+        } else if options.get_bool("readonly") {
+            info.insert_str(0, "[RO] ");
+        }
+        let x = (self.width as usize).saturating_sub(info.chars().count() + 1);
+        self.highlights.apply(&mut self.screen, "StatusLine");
+        self.screen.print_at(x as u16, self.height - 2, info);
         self.screen.clear_colors();
     }
 
+    /// The area of the screen the window or hex view is shown in, which leaves space for the
+    /// status line and the command line
+    fn window_bounds(&self) -> WindowBounds {
+        WindowBounds::new(0, 0, self.width, self.height - 2)
+    }
+
+    /// Fills the given row with spaces in the colors of the given highlight group and leaves the
+    /// group's colors set
+    fn fill_row(&mut self, row: u16, group: &str) {
+        self.highlights.apply(&mut self.screen, group);
+        self.screen
+            .print_at(0, row, " ".repeat(self.width as usize));
+    }
+
+    /// Writes the contents of modified buffers to the `recovery` directory of the state
+    /// directory and returns the paths of the written files. Used when the editor crashed.
+    pub fn emergency_save(&self) -> std::io::Result<Vec<PathBuf>> {
+        let buffer = self.window.get_buffer();
+        if self.is_saved() {
+            return Ok(Vec::new());
+        }
+
+        let dir = config::state_dir()
+            .ok_or_else(|| std::io::Error::other("no state directory"))?
+            .join("recovery");
+        std::fs::create_dir_all(&dir)?;
+        let name = buffer
+            .path
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map_or("unnamed".into(), |name| name.to_string_lossy());
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let path = dir.join(format!("{}.{}.{}", name, timestamp, std::process::id()));
+        match &self.file_view {
+            Some(FileView::Hex(hex_view)) => std::fs::write(&path, &hex_view.buffer.bytes)?,
+            _ => std::fs::write(&path, buffer.file_content())?,
+        }
+        Ok(vec![path])
+    }
+
+    /// Whether the current buffer, or the file in the hex view, has no unsaved changes
+    fn is_saved(&self) -> bool {
+        match &self.file_view {
+            Some(FileView::Hex(hex_view)) => hex_view.buffer.is_saved(),
+            Some(FileView::Large(_)) => true,
+            None => self.window.get_buffer().is_saved(),
+        }
+    }
+
+    /// Queues keys given in key notation as if they were typed by the user.
+    pub fn feed_keys(&mut self, notation: &str) {
+        let leader = self.options.get_string("mapleader").to_owned();
+        self.typeahead.extend(
+            keys::parse_keys(notation, &leader)
+                .into_iter()
+                .map(|event| TypeaheadKey { event, remap: true }),
+        );
+    }
+
     fn handle_key(&mut self, event: KeyEvent) -> Result<()> {
         if event.kind == KeyEventKind::Press {
-            if let Some(actions) = self.keymap.handle(self.mode, event) {
+            if let Some(prompt) = &self.prompt {
+                if let KeyCode::Char(c) = event.code {
+                    let c = c.to_ascii_lowercase();
+                    if prompt.choices.iter().any(|(choice, _)| *choice == c) {
+                        let prompt = self.prompt.take().unwrap();
+                        self.answer_prompt(prompt.kind, c);
+                    }
+                }
+                return Ok(());
+            }
+
+            // The key press dismissing a message spanning multiple lines isn't processed any further
+            if let Some(notification) = &self.notification {
+                if notification.is_multiline() {
+                    self.notification = None;
+                    return Ok(());
+                }
+            }
+
+            self.typeahead.push_back(TypeaheadKey {
+                event: keys::normalize_key(event),
+                remap: true,
+            });
+            self.last_key = Instant::now();
+            self.process_typeahead(false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies user mappings to the keys in the typeahead and executes the resulting keys. Keys
+    /// that could still be the start of a mapping are kept until more keys arrive or `timed_out`
+    /// is set.
+    fn process_typeahead(&mut self, timed_out: bool) -> Result<()> {
+        while let Some(front) = self.typeahead.front() {
+            // The picker takes all keys while it's open
+            if self.picker.is_some() {
+                let key = self.typeahead.pop_front().unwrap();
+                if self.typeahead.is_empty() {
+                    self.mapping_depth = 0;
+                }
+                self.picker_key(key.event);
+                continue;
+            }
+            // The key after `"`, `m` or `'` is taken as it is, without applying mappings
+            if let Some(command) = self.pending_char.take() {
+                let key = self.typeahead.pop_front().unwrap();
+                if self.typeahead.is_empty() {
+                    self.mapping_depth = 0;
+                }
+                if let Err(message) = self.char_command(command, key.event) {
+                    self.notify(message, LogLevel::Error);
+                }
+                continue;
+            }
+            // Operators read their motion without applying mappings, as there are no mappings
+            // for operator-pending mode
+            if let Some(operator) = &mut self.operator {
+                let key = self.typeahead.pop_front().unwrap();
+                if self.typeahead.is_empty() {
+                    self.mapping_depth = 0;
+                }
+                match operator.key(key.event) {
+                    Parsed::Pending => {}
+                    Parsed::Cancel => self.operator = None,
+                    Parsed::Motion(motion, count) => {
+                        let operator = self.operator.take().unwrap();
+                        if let Err(message) = self.apply_operator(operator, motion, count) {
+                            self.notify(message, LogLevel::Error);
+                        }
+                        self.finish_undo_step();
+                    }
+                }
+                continue;
+            }
+            if front.remap {
+                let keys = self
+                    .typeahead
+                    .iter()
+                    .take_while(|key| key.remap)
+                    .map(|key| key.event)
+                    .collect::<Vec<_>>();
+                match self
+                    .keymap
+                    .find_mapping(MapMode::from_mode(self.mode), &keys, timed_out)
+                {
+                    MappingLookup::Pending => return Ok(()),
+                    MappingLookup::Found(mapping) => {
+                        self.mapping_depth += 1;
+                        if self.mapping_depth > self.options.get_int("maxmapdepth") as usize {
+                            self.typeahead.clear();
+                            self.mapping_depth = 0;
+                            self.notify("Recursive mapping", LogLevel::Error);
+                            return Ok(());
+                        }
+
+                        self.typeahead.drain(..mapping.lhs.len());
+                        // Like in vim, keys at the start of the rhs that equal the lhs aren't
+                        // mapped again, so that e.g. `:nmap x x<Esc>` doesn't recurse
+                        let unmapped = if mapping.rhs.starts_with(&mapping.lhs) {
+                            mapping.lhs.len()
+                        } else {
+                            0
+                        };
+                        for (i, event) in mapping.rhs.iter().enumerate().rev() {
+                            self.typeahead.push_front(TypeaheadKey {
+                                event: *event,
+                                remap: !mapping.noremap && i >= unmapped,
+                            });
+                        }
+                        continue;
+                    }
+                    MappingLookup::NotFound => {}
+                }
+            }
+
+            let keys = self
+                .typeahead
+                .iter()
+                .map(|key| key.event)
+                .collect::<Vec<_>>();
+            match self.keymap.find_sequence(self.mode, &keys, timed_out) {
+                SequenceLookup::Pending => return Ok(()),
+                SequenceLookup::Found(length, actions) => {
+                    self.typeahead.drain(..length);
+                    if self.typeahead.is_empty() {
+                        self.mapping_depth = 0;
+                    }
+                    for action in actions {
+                        self.execute_action(action)?;
+                    }
+                    continue;
+                }
+                SequenceLookup::NotFound => {}
+            }
+
+            let key = self.typeahead.pop_front().unwrap();
+            if self.typeahead.is_empty() {
+                self.mapping_depth = 0;
+            }
+            // The completion menu takes the keys for navigating it while it's open
+            if self.completion.is_some() && self.completion_key(key.event) {
+                continue;
+            }
+            if self.mode == Mode::Command && self.command_line.pending_register {
+                self.insert_register(key.event);
+                continue;
+            }
+            if self.mode == Mode::Normal && self.file_view.is_none() && self.count_key(key.event) {
+                continue;
+            }
+            if let Some(actions) = self.keymap.handle(self.mode, key.event) {
                 for action in actions {
                     self.execute_action(action)?;
                 }
@@ -225,98 +906,3011 @@ impl Editor {
     }
 
     fn execute_action(&mut self, action: Action) -> Result<()> {
+        // The count and the register selected with `"` only apply to the action right after them
+        let count = self.count.take();
+        let register = self.register.take();
+        // In the hex view, only typed characters change the file
+        let edits = match self.file_view {
+            Some(_) => matches!(action, Action::InsertChar(_)),
+            None => matches!(
+                action,
+                Action::InsertChar(_) | Action::InsertTab | Action::RemoveChar | Action::DeleteChar
+            ),
+        };
+        let result = match action {
+            Action::ChangeMode(Mode::Insert)
+                if !self.window.get_buffer().options.get_bool("modifiable") =>
+            {
+                Err(NOT_MODIFIABLE.to_owned())
+            }
+            _ if edits => self.check_modifiable(),
+            _ => Ok(()),
+        };
+        if let Err(message) = result {
+            self.notify(message, LogLevel::Error);
+            return Ok(());
+        }
+
+        if self.file_view.is_some() && self.execute_file_view_action(action) {
+            return Ok(());
+        }
+
         match action {
             Action::ChangeMode(mode) => self.change_mode(mode),
             Action::MoveUp => self.window.move_up(),
             Action::MoveDown => self.window.move_down(),
             Action::MoveLeft => self.window.move_left(),
             Action::MoveRight => self.window.move_right(),
-            Action::InsertChar(c) => self.window.insert_char(c),
+            Action::InsertChar(c) => {
+                self.window.insert_char(c);
+                self.lsp_trigger_signature_help(c);
+                // Continue path completion in the directory that was just completed
+                if c == '/'
+                    && self
+                        .completion
+                        .as_ref()
+                        .is_some_and(|c| c.source == "paths")
+                {
+                    self.start_completion(&mut Paths);
+                }
+            }
+            Action::InsertTab => self.window.insert_tab(),
             Action::RemoveChar => self.window.remove_char(),
             Action::DeleteChar => self.window.delete_char(),
             Action::ExecuteCommand => {
                 self.execute_command()?;
                 self.change_mode(Mode::Normal);
             }
-            Action::InsertCharCommand(c) => self.command.push(c),
-            Action::RemoveCharCommand => {
-                self.command.pop();
+            Action::InsertCharCommand(c) => self.command_line.insert(&c.to_string()),
+            Action::RemoveCharCommand => self.command_line.remove_char(),
+            Action::DeleteCharCommand => self.command_line.delete_char(),
+            Action::RemoveWordCommand => self.command_line.remove_word(),
+            Action::RemoveLineCommand => self.command_line.remove_to_start(),
+            Action::MoveLeftCommand => self.command_line.move_left(),
+            Action::MoveRightCommand => self.command_line.move_right(),
+            Action::MoveToStartCommand => self.command_line.move_to_start(),
+            Action::MoveToEndCommand => self.command_line.move_to_end(),
+            Action::HistoryPreviousCommand => self.command_line.browse_history(&self.history, true),
+            Action::HistoryNextCommand => self.command_line.browse_history(&self.history, false),
+            Action::CompleteCommand => {
+                self.command_line.complete(true);
+            }
+            Action::CompletePreviousCommand => {
+                self.command_line.complete(false);
+            }
+            Action::InsertRegisterCommand => self.command_line.pending_register = true,
+            Action::Search => {
+                self.change_mode(Mode::Command);
+                self.command_line = CommandLine::new(CommandLineKind::Search);
+            }
+            Action::SearchNext | Action::SearchPrevious => {
+                let forward = action == Action::SearchNext;
+                let result = match self.last_search.clone() {
+                    Some(pattern) => self.search(&pattern, forward),
+                    None => Err("No previous regular expression".to_owned()),
+                };
+                if let Err(message) = result {
+                    self.notify(message, LogLevel::Error);
+                }
             }
             Action::MoveToStartOfLine => self.window.move_to_start_of_line(),
             Action::MoveToEndOfLine => self.window.move_to_end_of_line(),
             Action::MoveToFirstCharacterInLine => self.window.move_to_first_char_in_line(),
+            Action::LspHover => {
+                if let Err(message) = self.lsp_command("hover") {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            Action::LspComplete => {
+                if let Err(message) = self.lsp_complete() {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            Action::NextDiagnostic => {
+                if let Err(message) = self.jump_to_diagnostic(true) {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            Action::PreviousDiagnostic => {
+                if let Err(message) = self.jump_to_diagnostic(false) {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            Action::CompleteNext => self.start_completion(&mut BufferWords),
+            Action::CompletePrevious => {
+                self.start_completion(&mut BufferWords);
+                if let Some(completion) = &mut self.completion {
+                    completion.select_last();
+                }
+            }
+            Action::CompletePaths => self.start_completion(&mut Paths),
+            Action::SelectRegister => {
+                // A count can be typed before the register, like `3"ayy`
+                self.count = count;
+                self.pending_char = Some(CharCommand::SelectRegister);
+            }
+            Action::YankLines => {
+                let y = self.window.get_cursor().y;
+                let line_count = self.window.get_buffer().lines.len();
+                let last = motion::target_line(Motion::Lines, count, y, line_count).unwrap_or(y);
+                if let Err(message) = self.yank_lines(register, y, last) {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            Action::PutAfter | Action::PutBefore => {
+                if let Err(message) = self.put(register, action == Action::PutBefore) {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            Action::SetMark => self.pending_char = Some(CharCommand::SetMark),
+            Action::JumpToMark | Action::JumpToMarkLine => {
+                let linewise = action == Action::JumpToMarkLine;
+                self.pending_char = Some(CharCommand::JumpToMark { linewise });
+            }
+            Action::JumpOlder | Action::JumpNewer => {
+                if let Err(message) = self.jump_in_list(action == Action::JumpOlder) {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            Action::OpenEntry
+            | Action::ParentDirectory
+            | Action::ToggleHiddenEntries
+            | Action::CycleSortOrder
+            | Action::ChangeToListedDirectory => {
+                if let Err(message) = self.directory_action(action) {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            Action::Operator(operator) => {
+                self.operator = Some(PendingOperator::new(operator, count, register));
+            }
+            Action::Undo | Action::Redo => {
+                let steps = count.unwrap_or(1);
+                if let Err(message) = self.undo(action == Action::Redo, steps) {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            Action::FindFile => {
+                if let Err(message) = self.pick_command("files") {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
         }
+        self.finish_undo_step();
+        self.update_completion();
         Ok(())
     }
 
-    fn change_mode(&mut self, mode: Mode) {
-        if self.mode == Mode::Command && mode != Mode::Command {
-            self.command.clear();
+    /// Adds a digit typed in normal mode to the count of the next command. A `0` only counts
+    /// after other digits, as it moves to the start of the line otherwise.
+    fn count_key(&mut self, event: KeyEvent) -> bool {
+        let KeyCode::Char(c) = event.code else {
+            return false;
+        };
+        let Some(digit) = c.to_digit(10) else {
+            return false;
+        };
+        if !event.modifiers.is_empty() || (digit == 0 && self.count.is_none()) {
+            return false;
         }
-        if mode == Mode::Command {
-            self.notification = None;
+        let count = self.count.unwrap_or(0);
+        self.count = Some(count.saturating_mul(10).saturating_add(digit as usize));
+        true
+    }
+
+    /// Ends the current step of edits for undo, unless insert mode keeps adding to it
+    fn finish_undo_step(&mut self) {
+        if self.mode != Mode::Insert {
+            self.window.get_buffer_mut().finish_undo_step();
         }
+    }
 
-        self.mode = mode;
+    /// Undoes the last `steps` steps of edits, or redoes them if `redo` is set
+    fn undo(&mut self, redo: bool, steps: usize) -> CommandResult {
+        self.check_modifiable()?;
+        let buffer = self.window.get_buffer_mut();
+        let mut start = None;
+        for _ in 0..steps {
+            let position = if redo { buffer.redo() } else { buffer.undo() };
+            match position {
+                Some(position) => start = Some(position),
+                None => break,
+            }
+        }
+        let start = start.ok_or_else(|| {
+            let end = if redo { "newest" } else { "oldest" };
+            format!("Already at {} change", end)
+        })?;
+        self.window.set_cursor(start);
+        Ok(())
+    }
 
-        match mode {
-            Mode::Normal | Mode::Command => {
-                self.screen.set_cursor_style(CursorStyle::SteadyBlock);
+    /// Applies the operator to the lines from the cursor line to the line the motion moves to.
+    /// Like in vim, the cursor goes to the first of the lines.
+    fn apply_operator(
+        &mut self,
+        pending: PendingOperator,
+        motion: Motion,
+        count: Option<usize>,
+    ) -> CommandResult {
+        let cursor = self.window.get_cursor();
+        let line_count = self.window.get_buffer().lines.len();
+        let target = match motion {
+            Motion::MarkLine(name) => Some(self.mark(name)?.y),
+            _ => motion::target_line(motion, count, cursor.y, line_count),
+        };
+        // A motion that can't move, like `j` on the last line, cancels the operator
+        let Some(target) = target else {
+            return Ok(());
+        };
+        let (first, last) = (cursor.y.min(target), cursor.y.max(target));
+
+        match pending.operator {
+            Operator::Filter => {
+                self.window.set_cursor(Position::new(cursor.x, first));
+                self.change_mode(Mode::Command);
+                let range = motion::format_range(first, last, line_count);
+                self.command_line.insert(&format!("{}!", range));
+                Ok(())
             }
-            Mode::Insert => {
-                self.screen.set_cursor_style(CursorStyle::SteadyBar);
+            Operator::Delete => self.delete_lines(pending.register, first, last),
+            Operator::Yank => {
+                self.yank_lines(pending.register, first, last)?;
+                self.window.set_cursor(Position::new(cursor.x, first));
+                Ok(())
             }
         }
     }
 
-    // FIXME: move this somewhere else
-    // The current problem is that something like
-    // > self.window.get_buffer_mut().save(&mut editor)
-    // isn't possible due to the burrow checker but this function should print information about
-    // the saved file or error messages if there's a problem
-    pub fn notify<S: std::fmt::Display>(&mut self, message: S, level: LogLevel) {
-        self.notification = Some(Notification::new(message.to_string(), level));
+    /// Checks whether the current buffer may be changed. Buffers whose 'modifiable' option is off
+    /// reject all changes, while a 'readonly' buffer only gets a warning before its first change.
+    fn check_modifiable(&mut self) -> CommandResult {
+        let options = &self.window.get_buffer().options;
+        if !options.get_bool("modifiable") {
+            return Err(NOT_MODIFIABLE.to_owned());
+        }
+        if options.get_bool("readonly") && self.is_saved() {
+            self.notify("Warning: Changing a readonly file", LogLevel::Error);
+        }
+        Ok(())
     }
 
-    fn execute_command(&mut self) -> Result<()> {
-        if self.command.starts_with("print ") {
-            self.notify(self.command["print ".len()..].to_string(), LogLevel::Info);
-        } else if self.command == "q" {
-            if self.window.get_buffer().is_saved() {
-                self.terminate = true;
-            } else {
-                // TODO: Add the information which buffers haven't been saved once multiple buffers
-                // are implemented
-                self.notify("No write since last change", LogLevel::Error);
-            }
-        } else if self.command == "q!" {
-            self.terminate = true;
-        } else if self.command == "w" {
-            match self.window.get_buffer_mut().save() {
-                Ok(notification) => self.notify(notification.message, notification.level),
-                Err(e) => self.notify(
-                    format!("Error when trying to save to file: {}", e),
-                    LogLevel::Error,
-                ),
+    /// Executes actions that work differently in the hex view or the large file view. Returns
+    /// false for the ones that work the same way, like changing modes or using the command line.
+    fn execute_file_view_action(&mut self, action: Action) -> bool {
+        let is_large = matches!(self.file_view, Some(FileView::Large(_)));
+        match action {
+            Action::ChangeMode(Mode::Insert) if is_large => {
+                self.notify("Large files are read-only", LogLevel::Error);
+                return true;
             }
-        } else if self.command == "wq" {
-            match self.window.get_buffer_mut().save() {
-                Ok(notification) => {
-                    self.terminate = true;
-                    self.notify(notification.message, notification.level);
+            Action::ChangeMode(_)
+            | Action::Search
+            | Action::SearchNext
+            | Action::SearchPrevious => return false,
+            _ if self.mode == Mode::Command => return false,
+            _ => {}
+        }
+
+        match self.file_view.as_mut().unwrap() {
+            FileView::Hex(hex_view) => match action {
+                Action::MoveUp => hex_view.move_up(),
+                Action::MoveDown => hex_view.move_down(),
+                Action::MoveLeft | Action::RemoveChar => hex_view.move_left(),
+                Action::MoveRight => hex_view.move_right(),
+                Action::MoveToStartOfLine | Action::MoveToFirstCharacterInLine => {
+                    hex_view.move_to_start_of_row()
+                }
+                Action::MoveToEndOfLine => hex_view.move_to_end_of_row(),
+                Action::JumpNewer | Action::InsertTab => hex_view.switch_column(),
+                Action::InsertChar(c) => {
+                    if let Err(message) = hex_view.overwrite(c) {
+                        self.notify(message, LogLevel::Error);
+                    }
+                }
+                // Actions on text like completion or the language server have nothing to work on
+                _ => {}
+            },
+            FileView::Large(view) => {
+                let result = match action {
+                    Action::MoveUp => view.move_up(),
+                    Action::MoveDown => view.move_down(),
+                    Action::MoveLeft => view.move_left(),
+                    Action::MoveRight => view.move_right(),
+                    Action::MoveToStartOfLine | Action::MoveToFirstCharacterInLine => {
+                        view.move_to_start_of_line()
+                    }
+                    Action::MoveToEndOfLine => view.move_to_end_of_line(),
+                    _ => Ok(()),
+                };
+                if let Err(e) = result {
+                    self.notify(format!("Can't read the file: {}", e), LogLevel::Error);
                 }
-                Err(e) => self.notify(
-                    format!("Error when trying to save to file: {}", e),
-                    LogLevel::Error,
-                ),
             }
-        } else {
-            self.notify(
-                format!("Not an editor command: {}", self.command),
-                LogLevel::Error,
-            );
         }
+        true
+    }
 
-        Ok(())
+    /// Opens the completion menu with the candidates of the given source
+    fn start_completion(&mut self, source: &mut dyn CompletionSource) {
+        let buffer = self.window.get_buffer();
+        let completion = Completion::start(source, &[buffer], self.window.get_cursor());
+        if completion.is_empty() && !completion.pending {
+            self.completion = None;
+            self.notify("No matches", LogLevel::Error);
+        } else {
+            self.completion = Some(completion);
+        }
     }
+
+    /// Opens the completion menu with candidates of the current buffer's language server
+    fn lsp_complete(&mut self) -> CommandResult {
+        self.sync_language_server();
+        let Some((filetype, uri)) = &self.lsp_document else {
+            return Err("No language server for this buffer".to_owned());
+        };
+        let client = match self.lsp_clients.get_mut(filetype) {
+            Some(client) if client.is_initialized() => client,
+            Some(_) => return Err("Language server is still starting".to_owned()),
+            None => return Err("No language server for this buffer".to_owned()),
+        };
+        if !client.supports("completionProvider") {
+            return Err("Language server doesn't support textDocument/completion".to_owned());
+        }
+
+        let mut source = LspCompletion {
+            client,
+            uri: uri.clone(),
+        };
+        let buffer = self.window.get_buffer();
+        self.completion = Some(Completion::start(
+            &mut source,
+            &[buffer],
+            self.window.get_cursor(),
+        ));
+        Ok(())
+    }
+
+    /// Filters the completion menu by the text typed since it was opened and closes it if the
+    /// cursor left that text or insert mode was left
+    fn update_completion(&mut self) {
+        let Some(completion) = &mut self.completion else {
+            return;
+        };
+        let base = completion.base(self.window.get_buffer(), self.window.get_cursor());
+        match base {
+            Some(base) if self.mode == Mode::Insert => completion.filter(&base),
+            _ => self.completion = None,
+        }
+    }
+
+    /// Handles the keys for navigating the open completion menu and returns whether the key was
+    /// one of them
+    fn completion_key(&mut self, event: KeyEvent) -> bool {
+        let Some(completion) = &mut self.completion else {
+            return false;
+        };
+        let control = event.modifiers == KeyModifiers::CONTROL;
+        match event.code {
+            KeyCode::Char('n') if control => completion.select(1),
+            KeyCode::Down | KeyCode::Tab => completion.select(1),
+            KeyCode::Char('p') if control => completion.select(-1),
+            KeyCode::Up | KeyCode::BackTab => completion.select(-1),
+            KeyCode::PageDown => completion.select(10),
+            KeyCode::PageUp => completion.select(-10),
+            KeyCode::Char('e') if control => self.completion = None,
+            KeyCode::Char('y') if control => self.accept_completion(),
+            KeyCode::Enter if !completion.is_empty() => self.accept_completion(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Replaces the completed text with the selected item and closes the menu
+    fn accept_completion(&mut self) {
+        let Some(completion) = self.completion.take() else {
+            return;
+        };
+        let Some(item) = completion.selected() else {
+            return;
+        };
+        let cursor = self.window.get_cursor();
+        self.window
+            .get_buffer_mut()
+            .replace_text(completion.start, cursor, &item.word);
+        let end = Edit {
+            start: completion.start,
+            removed: String::new(),
+            inserted: item.word.clone(),
+        }
+        .new_end();
+        self.window.set_cursor(end);
+    }
+
+    fn change_mode(&mut self, mode: Mode) {
+        if self.mode == Mode::Command && mode != Mode::Command {
+            self.command_line = CommandLine::new(CommandLineKind::Command);
+        }
+        if mode == Mode::Command {
+            self.notification = None;
+        }
+
+        self.mode = mode;
+        if let Some(FileView::Hex(hex_view)) = &mut self.file_view {
+            hex_view.set_insert(mode == Mode::Insert);
+        }
+
+        match mode {
+            Mode::Normal | Mode::Command => {
+                self.screen.set_cursor_style(CursorStyle::SteadyBlock);
+            }
+            Mode::Insert => {
+                self.screen.set_cursor_style(CursorStyle::SteadyBar);
+            }
+        }
+    }
+
+    // FIXME: move this somewhere else
+    // The current problem is that something like
+    // > self.window.get_buffer_mut().save(&mut editor)
+    // isn't possible due to the burrow checker but this function should print information about
+    // the saved file or error messages if there's a problem
+    pub fn notify<S: std::fmt::Display>(&mut self, message: S, level: LogLevel) {
+        self.notification = Some(Notification::new(message.to_string(), level));
+    }
+
+    fn execute_command(&mut self) -> Result<()> {
+        let line = std::mem::replace(
+            &mut self.command_line,
+            CommandLine::new(CommandLineKind::Command),
+        );
+        let max = self.options.get_int("history") as usize;
+        self.history.add(line.kind, line.text(), max);
+
+        let result = match line.kind {
+            CommandLineKind::Command => self.run_command(line.text()),
+            CommandLineKind::Search => {
+                // An empty pattern repeats the last search
+                match (line.text(), self.last_search.clone()) {
+                    ("", Some(pattern)) => self.search(&pattern, true),
+                    ("", None) => Err("No previous regular expression".to_owned()),
+                    (pattern, _) => {
+                        self.last_search = Some(pattern.to_owned());
+                        self.search(pattern, true)
+                    }
+                }
+            }
+        };
+        if let Err(message) = result {
+            self.notify(message, LogLevel::Error);
+        }
+
+        Ok(())
+    }
+
+    /// Moves the cursor to the next match of the regular expression after or before it,
+    /// wrapping around at the end of the buffer.
+    fn search(&mut self, pattern: &str, forward: bool) -> CommandResult {
+        // Binary files are searched for bytes given in hex
+        if let Some(FileView::Hex(hex_view)) = &mut self.file_view {
+            return hex_view.search(&hex::parse_pattern(pattern)?, forward);
+        }
+
+        let regex = regex::Regex::new(pattern)
+            .map_err(|_| format!("Invalid regular expression: {}", pattern))?;
+        if let Some(FileView::Large(view)) = &mut self.file_view {
+            return view.search(&regex, forward);
+        }
+        let buffer = self.window.get_buffer();
+        let cursor = self.window.get_cursor();
+        let line_count = buffer.lines.len();
+        let cursor_index = buffer.byte_index(cursor);
+
+        // Visit the cursor line again at the end for matches on the other side of the cursor
+        let found = if forward {
+            (0..=line_count).find_map(|offset| {
+                let y = (cursor.y + offset) % line_count;
+                let line = &buffer.lines[y];
+                regex
+                    .find_iter(line)
+                    .find(|m| offset == line_count || y != cursor.y || m.start() > cursor_index)
+                    .map(|m| (y, line, m.start()))
+            })
+        } else {
+            (0..=line_count).find_map(|offset| {
+                let y = (cursor.y + line_count * 2 - offset) % line_count;
+                let line = &buffer.lines[y];
+                regex
+                    .find_iter(line)
+                    .filter(|m| offset == line_count || y != cursor.y || m.start() < cursor_index)
+                    .last()
+                    .map(|m| (y, line, m.start()))
+            })
+        };
+
+        let (y, line, index) = found.ok_or_else(|| format!("Pattern not found: {}", pattern))?;
+        let x = line[..index].chars().count();
+        self.record_jump();
+        self.window.set_cursor(Position::new(x, y));
+        Ok(())
+    }
+
+    /// Inserts the contents of a register into the command line after `Ctrl-R`. `Ctrl-W` inserts
+    /// the word under the cursor.
+    fn insert_register(&mut self, event: KeyEvent) {
+        self.command_line.pending_register = false;
+        let text = match event.code {
+            KeyCode::Char('w') if event.modifiers == KeyModifiers::CONTROL => {
+                let cursor = self.window.get_cursor();
+                let line = &self.window.get_buffer().lines[cursor.y];
+                let is_word = |c: char| c.is_alphanumeric() || c == '_';
+                let chars = line.chars().collect::<Vec<_>>();
+                let start = chars[..cursor.x.min(chars.len())]
+                    .iter()
+                    .rposition(|c| !is_word(*c))
+                    .map_or(0, |i| i + 1);
+                Some(chars[start..].iter().take_while(|c| is_word(**c)).collect())
+            }
+            KeyCode::Char(c) if !event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.register(c).map(|register| register.text)
+            }
+            _ => None,
+        };
+        if let Some(text) = text {
+            self.command_line.insert(&text);
+        }
+    }
+
+    /// Returns the register with the name. Besides the ones text is yanked and deleted into,
+    /// there are the read-only registers `%` (the file name), `:` (the last command line) and `/`
+    /// (the last search pattern).
+    fn register(&self, name: char) -> Option<Register> {
+        let text = match name {
+            '%' => self
+                .window
+                .get_buffer()
+                .path
+                .as_deref()
+                .map(util::display_path),
+            ':' => self
+                .history
+                .last(CommandLineKind::Command)
+                .map(str::to_owned),
+            '/' => self.last_search.clone(),
+            _ => return self.registers.get(name).cloned(),
+        };
+        text.map(|text| Register {
+            text,
+            linewise: false,
+        })
+    }
+
+    /// Executes a command with the key typed after it. Keys other than characters cancel it.
+    fn char_command(&mut self, command: CharCommand, event: KeyEvent) -> CommandResult {
+        let KeyCode::Char(c) = event.code else {
+            return Ok(());
+        };
+        if event.modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(());
+        }
+        match command {
+            CharCommand::SelectRegister => {
+                if register::is_writable(c) || matches!(c, '%' | ':' | '/') {
+                    self.register = Some(c);
+                }
+                Ok(())
+            }
+            CharCommand::SetMark => self.set_mark(c),
+            CharCommand::JumpToMark { linewise } => self.jump_to_mark(c, linewise),
+        }
+    }
+
+    /// Yanks the lines from `first` to `last` into the register
+    fn yank_lines(&mut self, name: Option<char>, first: usize, last: usize) -> CommandResult {
+        register::check_writable(name)?;
+        let text = self.window.get_buffer().lines[first..=last].join("\n");
+        self.registers.yank(
+            name,
+            Register {
+                text,
+                linewise: true,
+            },
+        );
+        Ok(())
+    }
+
+    /// Deletes the lines from `first` to `last` into the register
+    fn delete_lines(&mut self, name: Option<char>, first: usize, last: usize) -> CommandResult {
+        register::check_writable(name)?;
+        self.check_modifiable()?;
+        let buffer = self.window.get_buffer_mut();
+        let text = buffer.lines[first..=last].join("\n");
+        buffer.remove_lines(first, last);
+        self.registers.delete(
+            name,
+            Register {
+                text,
+                linewise: true,
+            },
+        );
+        let y = first.min(self.window.get_buffer().lines.len() - 1);
+        self.window.set_cursor(Position::new(0, y));
+        self.window.move_to_first_char_in_line();
+        Ok(())
+    }
+
+    /// Puts the text of the register after the cursor, or before it if `before` is set. Lines
+    /// are put below or above the cursor line.
+    fn put(&mut self, name: Option<char>, before: bool) -> CommandResult {
+        if self.file_view.is_some() {
+            return Err("Only text can be put".to_owned());
+        }
+        self.check_modifiable()?;
+        let name = name.unwrap_or('"');
+        let register = self
+            .register(name)
+            .ok_or_else(|| format!("Nothing in register {}", name))?;
+
+        let cursor = self.window.get_cursor();
+        let buffer = self.window.get_buffer_mut();
+        if register.linewise {
+            let y = if before { cursor.y } else { cursor.y + 1 };
+            if before {
+                buffer.insert_text(Position::new(0, y), &format!("{}\n", register.text));
+            } else {
+                let end = Position::new(buffer.line_length(cursor.y), cursor.y);
+                buffer.insert_text(end, &format!("\n{}", register.text));
+            }
+            self.window.set_cursor(Position::new(0, y));
+            self.window.move_to_first_char_in_line();
+            return Ok(());
+        }
+
+        // The cursor is on the character the text is put after
+        let length = buffer.line_length(cursor.y);
+        let x = if before || length == 0 {
+            cursor.x
+        } else {
+            (cursor.x + 1).min(length)
+        };
+        let start = Position::new(x, cursor.y);
+        buffer.insert_text(start, &register.text);
+        // Like in vim, the cursor ends up on the last character that was put
+        let last_line = register.text.rsplit('\n').next().unwrap_or_default();
+        let lines = register.text.matches('\n').count();
+        let end_x = if lines == 0 { x } else { 0 } + last_line.chars().count();
+        self.window
+            .set_cursor(Position::new(end_x.saturating_sub(1), cursor.y + lines));
+        Ok(())
+    }
+
+    /// Sets the mark with the name at the cursor. `'` and `` ` `` set the mark the cursor returns
+    /// to with `''`.
+    fn set_mark(&mut self, name: char) -> CommandResult {
+        let name = match name {
+            'a'..='z' | '\'' => name,
+            '`' => '\'',
+            _ => return Err(format!("Unknown mark: {}", name)),
+        };
+        let cursor = self.window.get_cursor();
+        self.window.get_buffer_mut().marks.insert(name, cursor);
+        Ok(())
+    }
+
+    /// Returns the position of the mark in the current buffer
+    fn mark(&self, name: char) -> std::result::Result<Position<usize>, String> {
+        let name = match name {
+            'a'..='z' | '\'' => name,
+            '`' => '\'',
+            _ => return Err(format!("Unknown mark: {}", name)),
+        };
+        self.window
+            .get_buffer()
+            .marks
+            .get(&name)
+            .copied()
+            .ok_or_else(|| "Mark not set".to_owned())
+    }
+
+    /// Moves the cursor to the mark, or to the first character of its line if `linewise` is set
+    fn jump_to_mark(&mut self, name: char, linewise: bool) -> CommandResult {
+        let position = self.mark(name)?;
+        self.record_jump();
+        if linewise {
+            self.window.set_cursor(Position::new(0, position.y));
+            self.window.move_to_first_char_in_line();
+        } else {
+            self.window.set_cursor(position);
+        }
+        Ok(())
+    }
+
+    /// Remembers the cursor position before a jump in the jump list and as the `'` mark
+    fn record_jump(&mut self) {
+        if self.file_view.is_some() {
+            return;
+        }
+        let position = self.window.get_cursor();
+        let buffer = self.window.get_buffer_mut();
+        buffer.marks.insert('\'', position);
+        let path = buffer.path.clone();
+        self.jumps.push(Jump { path, position });
+    }
+
+    /// Goes to the previous entry of the jump list for `Ctrl-O`, or the next one for `Ctrl-I`
+    fn jump_in_list(&mut self, back: bool) -> CommandResult {
+        let from = Jump {
+            path: self.window.get_buffer().path.clone(),
+            position: self.window.get_cursor(),
+        };
+        let jump = if back {
+            self.jumps.back(from)
+        } else {
+            self.jumps.forward()
+        };
+        let Some(jump) = jump.cloned() else {
+            return Ok(());
+        };
+        if jump.path != self.window.get_buffer().path {
+            let path = jump.path.ok_or_else(|| "No file name".to_owned())?;
+            self.edit_file(Some(&path), false, EditOptions::default())?;
+        }
+        self.window.set_cursor(jump.position);
+        Ok(())
+    }
+
+    fn run_command(&mut self, command: &str) -> CommandResult {
+        let command = command.trim();
+        // `:!cmd` runs a command, `:{range}!cmd` filters the lines through it
+        let cursor = self.window.get_cursor().y;
+        let line_count = self.window.get_buffer().lines.len();
+        let shell_command = if cmdline::is_shell_command(command) {
+            let (range, rest) = cmdline::parse_range(command, cursor, line_count)?;
+            rest.strip_prefix('!').map(|shell| (range, shell))
+        } else {
+            None
+        };
+        if let Some((range, shell)) = shell_command {
+            let shell = shell.trim();
+            if shell.is_empty() {
+                return Err("Argument required".to_owned());
+            }
+            return match range {
+                Some((_, last)) if last >= line_count => Err("Invalid range".to_owned()),
+                Some(range) => self.filter_lines(range, shell),
+                None => self.shell_command(shell),
+            };
+        }
+        let (name, arguments) = match command.split_once(char::is_whitespace) {
+            Some((name, arguments)) => (name, arguments.trim_start()),
+            None => (command, ""),
+        };
+
+        match name {
+            "print" => self.notify(arguments, LogLevel::Info),
+            "q" => {
+                if self.is_saved() {
+                    self.terminate = true;
+                } else {
+                    // TODO: Add the information which buffers haven't been saved once multiple
+                    // buffers are implemented
+                    return Err("No write since last change".to_owned());
+                }
+            }
+            "q!" => self.terminate = true,
+            "w" | "w!" => {
+                let notification = self.save_buffer(name.ends_with('!'))?;
+                self.notify(notification.message, notification.level);
+            }
+            "update" | "up" | "update!" | "up!" => {
+                if !self.is_saved() {
+                    let notification = self.save_buffer(name.ends_with('!'))?;
+                    self.notify(notification.message, notification.level);
+                }
+            }
+            "wq" | "wq!" => {
+                let notification = self.save_buffer(name.ends_with('!'))?;
+                // Changes to a directory have to be confirmed first
+                self.terminate = self.prompt.is_none();
+                self.notify(notification.message, notification.level);
+            }
+            "set" | "se" => self.set_options(arguments, SetScope::All)?,
+            "setlocal" | "setl" => self.set_options(arguments, SetScope::Local)?,
+            "setglobal" | "setg" => self.set_options(arguments, SetScope::Global)?,
+            "highlight" | "hi" => {
+                if let Some(listing) = self.highlights.command(arguments)? {
+                    self.notify(listing, LogLevel::Info);
+                }
+            }
+            "colorscheme" | "colo" => self.load_colorscheme(arguments)?,
+            "edit" | "e" | "edit!" | "e!" | "view" | "vie" | "view!" | "vie!" => {
+                let (mut options, arguments) = parse_edit_arguments(arguments)?;
+                options.readonly = name.starts_with('v');
+                let path = (!arguments.is_empty()).then(|| Path::new(arguments));
+                if path.is_some() {
+                    self.record_jump();
+                }
+                self.edit_file(path, name.ends_with('!'), options)?;
+            }
+            "lsp" => self.lsp_command(arguments)?,
+            "diagnostics" | "diag" => self.diagnostics_command(arguments)?,
+            "source" | "so" => {
+                if arguments.is_empty() {
+                    return Err("Argument required".to_owned());
+                }
+                self.source(Path::new(arguments))?;
+            }
+            "checktime" | "checkt" => self.check_time(),
+            "find" | "fin" => self.pick_command(&format!("files {}", arguments))?,
+            "pick" | "pic" => self.pick_command(arguments)?,
+            "read" | "r" => self.read_command(arguments)?,
+            "grep" | "gr" | "grep!" | "gr!" => {
+                self.grep_command(arguments)?;
+                // Like in vim, `!` keeps the cursor where it is
+                if !name.ends_with('!') {
+                    self.jump_to_quickfix(0)?;
+                }
+            }
+            "cfile" | "cf" | "cfile!" | "cf!" => {
+                self.cfile_command(arguments)?;
+                if !name.ends_with('!') {
+                    self.jump_to_quickfix(0)?;
+                }
+            }
+            "make" | "mak" | "make!" | "mak!" => {
+                if self.make.is_some() {
+                    return Err("make is already running".to_owned());
+                }
+                let command = quickfix::command_line(self.options.get_string("makeprg"), arguments);
+                let format = ErrorFormat::new(self.options.get_list("errorformat"))?;
+                let make = Make::start(&command, format)
+                    .map_err(|e| format!("Can't run {}: {}", command, e))?;
+                self.make = Some((make, !name.ends_with('!')));
+                self.notify(format!("make: {}", command), LogLevel::Info);
+            }
+            "copen" | "cope" | "cop" => self.open_quickfix()?,
+            "cnext" | "cn" => self.jump_to_quickfix(self.quickfix.offset(1)?)?,
+            "cprevious" | "cprev" | "cp" | "cNext" | "cN" => {
+                self.jump_to_quickfix(self.quickfix.offset(-1)?)?
+            }
+            "cfirst" | "cfir" => self.jump_to_quickfix(0)?,
+            "clast" | "cla" => {
+                let last = self.quickfix.entries().len().saturating_sub(1);
+                self.jump_to_quickfix(last)?
+            }
+            "cc" => {
+                let index = match arguments {
+                    "" => self.quickfix.current(),
+                    number => number
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid number: {}", number))?
+                        .saturating_sub(1),
+                };
+                self.jump_to_quickfix(index)?
+            }
+            "cdo" => {
+                if arguments.is_empty() {
+                    return Err("Argument required".to_owned());
+                }
+                // Commands are separated by `|`, so e.g. `:cdo cmd | update` writes each file
+                // before moving on to the next
+                let commands = cmdline::split_commands(arguments);
+                for index in 0..self.quickfix.entries().len() {
+                    self.jump_to_quickfix(index)?;
+                    for command in &commands {
+                        self.run_command(command)?;
+                    }
+                }
+            }
+            "cd" | "chdir" | "chd" => self.cd_command(arguments, DirectoryScope::Global)?,
+            "tcd" | "tchdir" | "tch" => self.cd_command(arguments, DirectoryScope::Tab)?,
+            "lcd" | "lchdir" | "lch" => self.cd_command(arguments, DirectoryScope::Window)?,
+            "pwd" | "pw" => {
+                let directory = std::env::current_dir()
+                    .map_err(|e| format!("Can't get the working directory: {}", e))?;
+                // Like `:verbose pwd` in vim, tell where a local directory comes from
+                let scope = if self.window.get_local_directory().is_some() {
+                    "[window] "
+                } else if self.tab_directory.is_some() {
+                    "[tabpage] "
+                } else {
+                    ""
+                };
+                self.notify(
+                    format!("{}{}", scope, directory.to_string_lossy()),
+                    LogLevel::Info,
+                );
+            }
+            "$" => self.jump_to_line(None)?,
+            _ if name.parse::<usize>().is_ok() => self.jump_to_line(name.parse().ok())?,
+            _ => match keymap::parse_map_command(name) {
+                Some((map_command, modes)) => self.map(map_command, modes, arguments)?,
+                None => return Err(format!("Not an editor command: {}", command)),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Executes `:cd`, `:tcd` and `:lcd`. Without an argument they go to the home directory and
+    /// `-` goes back to the previous working directory.
+    fn cd_command(&mut self, arguments: &str, scope: DirectoryScope) -> CommandResult {
+        let directory = match arguments {
+            "" => util::expand_home("~"),
+            "-" => self
+                .previous_directory
+                .clone()
+                .ok_or_else(|| "No previous directory".to_owned())?,
+            _ => util::expand_home(arguments),
+        };
+        self.change_directory(&directory, scope)
+    }
+
+    /// Changes the working directory of the given scope. Like in vim, changing the global one
+    /// also removes the local ones of the current window and tab page, and changing the one of
+    /// the tab page removes the window's.
+    fn change_directory(&mut self, directory: &Path, scope: DirectoryScope) -> CommandResult {
+        let previous = std::env::current_dir().ok();
+        std::env::set_current_dir(directory)
+            .map_err(|e| format!("Can't change directory to {}: {}", directory.display(), e))?;
+        // Relative paths would be resolved differently later
+        let directory = std::env::current_dir()
+            .map_err(|e| format!("Can't get the working directory: {}", e))?;
+
+        match scope {
+            DirectoryScope::Global => {
+                self.tab_directory = None;
+                self.window.set_local_directory(None);
+            }
+            DirectoryScope::Tab => {
+                self.tab_directory = Some(directory);
+                self.window.set_local_directory(None);
+            }
+            DirectoryScope::Window => self.window.set_local_directory(Some(directory)),
+        }
+        self.previous_directory = previous;
+        Ok(())
+    }
+
+    /// Moves the cursor to the start of a line, counted from 1, or to the last line for `None`
+    fn jump_to_line(&mut self, line: Option<usize>) -> CommandResult {
+        let line = line.map_or(usize::MAX, |line| line.saturating_sub(1));
+        match &mut self.file_view {
+            Some(FileView::Hex(_)) => return Err("The hex view has no lines".to_owned()),
+            Some(FileView::Large(view)) => view
+                .set_cursor(line, 0)
+                .map_err(|e| format!("Can't read the file: {}", e))?,
+            None => {
+                self.record_jump();
+                self.window.set_cursor(Position::new(0, line));
+            }
+        }
+        Ok(())
+    }
+
+    fn map(&mut self, command: MapCommand, modes: &[MapMode], arguments: &str) -> CommandResult {
+        let leader = self.options.get_string("mapleader").to_owned();
+        let (lhs, rhs) = match arguments.split_once(char::is_whitespace) {
+            Some((lhs, rhs)) => (lhs, rhs.trim_start()),
+            None => (arguments, ""),
+        };
+        let lhs = keys::parse_keys(lhs, &leader);
+
+        match command {
+            MapCommand::Unmap => {
+                if lhs.is_empty() {
+                    return Err("Argument required".to_owned());
+                }
+                let mut found = false;
+                for mode in modes {
+                    found |= self.keymap.unmap(*mode, &lhs);
+                }
+                if !found {
+                    return Err("No such mapping".to_owned());
+                }
+            }
+            // Without a rhs, the mappings starting with lhs are listed
+            MapCommand::Map { .. } if rhs.is_empty() => {
+                let mappings = self.keymap.list_mappings(modes, &lhs);
+                if mappings.is_empty() {
+                    self.notify("No mapping found", LogLevel::Info);
+                } else {
+                    self.notify(mappings.join("\n"), LogLevel::Info);
+                }
+            }
+            MapCommand::Map { noremap } => {
+                if lhs.is_empty() {
+                    return Err("Argument required".to_owned());
+                }
+                let rhs = keys::parse_keys(rhs, &leader);
+                for mode in modes {
+                    self.keymap.map(
+                        *mode,
+                        Mapping {
+                            lhs: lhs.clone(),
+                            rhs: rhs.clone(),
+                            noremap,
+                        },
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the current buffer to its file. `force` overrides 'readonly' and writes over changes
+    /// another program made to the file.
+    fn save_buffer(&mut self, force: bool) -> std::result::Result<Notification, String> {
+        if !force && self.window.get_buffer().options.get_bool("readonly") {
+            return Err("'readonly' option is set (add ! to override)".to_owned());
+        }
+        if !force && self.file_changed() {
+            return Err(
+                "The file has been changed since reading it (add ! to override)".to_owned(),
+            );
+        }
+        if self.window.get_buffer().directory.is_some() {
+            return self.write_directory(force);
+        }
+        let result = match &mut self.file_view {
+            Some(FileView::Hex(hex_view)) => hex_view.buffer.save(&self.options),
+            Some(FileView::Large(_)) => return Err("Large files are read-only".to_owned()),
+            None => self.window.get_buffer_mut().save(&self.options),
+        };
+        let notification =
+            result.map_err(|e| format!("Error when trying to save to file: {}", e))?;
+
+        // The swap file no longer has changes to recover
+        if let Some(swap) = &mut self.swap_file {
+            let _ = swap.write(self.window.get_buffer());
+        }
+
+        self.sync_language_server();
+        if let Some((filetype, uri)) = &self.lsp_document {
+            if let Some(client) = self.lsp_clients.get_mut(filetype) {
+                client.did_save(uri);
+            }
+        }
+
+        Ok(notification)
+    }
+
+    /// Executes every line of the given file as a command. Empty lines and lines starting with `"`
+    /// are ignored.
+    fn source(&mut self, path: &Path) -> CommandResult {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't open file {}: {}", path.display(), e))?;
+
+        let mut errors = Vec::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('"') {
+                continue;
+            }
+            let line = line.strip_prefix(':').unwrap_or(line);
+
+            if let Err(message) = self.run_command(line) {
+                errors.push(format!("{}:{}: {}", path.display(), index + 1, message));
+            }
+        }
+
+        join_errors(errors)
+    }
+
+    /// Sets the file type of the current buffer based on its name or first line and applies its
+    /// modeline.
+    fn detect_filetype(&mut self) -> CommandResult {
+        // There's no text to highlight or to sync with a language server
+        if self.file_view.is_some() {
+            return Ok(());
+        }
+        let buffer = self.window.get_buffer();
+        let filetype = match &buffer.directory {
+            Some(_) => Some("directory".to_owned()),
+            None => self
+                .syntaxes
+                .detect(buffer.path.as_deref(), &buffer.lines[0])
+                .map(|name| name.to_owned()),
+        };
+        if let Some(filetype) = filetype {
+            let info = options::find_option("filetype").unwrap();
+            self.window
+                .get_buffer_mut()
+                .options
+                .set(info, OptionValue::String(filetype));
+            self.option_changed(info);
+        }
+
+        self.apply_modeline()
+    }
+
+    /// Applies the first modeline found in the first or last 'modelines' lines of the current
+    /// buffer. Only local options can be set by modelines.
+    fn apply_modeline(&mut self) -> CommandResult {
+        let buffer = self.window.get_buffer();
+        // File names aren't trusted to set options
+        if !buffer.options.get_bool("modeline") || buffer.directory.is_some() {
+            return Ok(());
+        }
+
+        let count = self.options.get_int("modelines") as usize;
+        let lines = &buffer.lines;
+        let first = lines.iter().take(count);
+        let last = lines.iter().skip(count).rev().take(count);
+        let Some(arguments) = first
+            .chain(last)
+            .find_map(|line| options::parse_modeline(line))
+        else {
+            return Ok(());
+        };
+
+        for argument in options::split_set_arguments(&arguments) {
+            let (info, operation) = options::parse_set_argument(&argument)
+                .map_err(|message| format!("modeline: {}", message))?;
+            if info.scope == OptionScope::Global {
+                return Err(format!("modeline: Not allowed here: {}", info.name));
+            }
+            self.set_option(info, &operation, SetScope::Local)?;
+        }
+
+        Ok(())
+    }
+
+    fn load_colorscheme(&mut self, name: &str) -> CommandResult {
+        if name.is_empty() {
+            let name = self.colorscheme.as_deref().unwrap_or("default").to_owned();
+            self.notify(name, LogLevel::Info);
+            return Ok(());
+        }
+
+        if name == "default" {
+            self.highlights.reset();
+            self.colorscheme = None;
+            return Ok(());
+        }
+
+        let path = config::config_dir()
+            .map(|dir| dir.join("colors").join(format!("{}.bvim", name)))
+            .filter(|path| path.is_file())
+            .ok_or_else(|| format!("Cannot find color scheme '{}'", name))?;
+
+        // Color schemes only describe the groups they change
+        self.highlights.reset();
+        self.colorscheme = Some(name.to_owned());
+        self.source(&path)
+    }
+
+    fn set_options(&mut self, arguments: &str, scope: SetScope) -> CommandResult {
+        let arguments = options::split_set_arguments(arguments);
+
+        let mut shown = Vec::new();
+        if arguments.is_empty() {
+            // Like vim, show all options that differ from their default value
+            for info in options::all_options() {
+                let value = self.get_option(info, scope);
+                if *value != info.default_value() {
+                    shown.push(options::format_option(info, value));
+                }
+            }
+        }
+
+        for argument in &arguments {
+            let (info, operation) = options::parse_set_argument(argument)?;
+            let value = self.set_option(info, &operation, scope)?;
+            if operation == SetOperation::Show
+                || (operation == SetOperation::Enable && info.get_type() != OptionType::Bool)
+            {
+                shown.push(options::format_option(info, &value));
+            }
+        }
+
+        if !shown.is_empty() {
+            self.notify(shown.join("  "), LogLevel::Info);
+        }
+
+        Ok(())
+    }
+
+    fn get_option(&self, info: &OptionInfo, scope: SetScope) -> &OptionValue {
+        match (info.scope, scope) {
+            (OptionScope::Global, _) | (_, SetScope::Global) => self.options.get(info.name),
+            (OptionScope::Buffer, _) => self.window.get_buffer().options.get(info.name),
+            (OptionScope::Window, _) => self.window.get_options().get(info.name),
+        }
+    }
+
+    /// Applies `:set` to an option and updates what depends on it if its value in effect for the
+    /// current buffer and window changed. Queries never count as a change.
+    fn set_option(
+        &mut self,
+        info: &OptionInfo,
+        operation: &SetOperation,
+        scope: SetScope,
+    ) -> std::result::Result<OptionValue, String> {
+        let previous = self.get_option(info, SetScope::Local).clone();
+        let value = self.apply_option(info, operation, scope)?;
+        if *self.get_option(info, SetScope::Local) != previous {
+            self.option_changed(info);
+        }
+        Ok(value)
+    }
+
+    fn apply_option(
+        &mut self,
+        info: &OptionInfo,
+        operation: &SetOperation,
+        scope: SetScope,
+    ) -> std::result::Result<OptionValue, String> {
+        let value = match (info.scope, scope) {
+            (OptionScope::Global, _) | (_, SetScope::Global) => {
+                return self.options.apply(info, operation);
+            }
+            (OptionScope::Buffer, _) => {
+                self.window.get_buffer_mut().apply_option(info, operation)?
+            }
+            (OptionScope::Window, _) => self.window.get_options_mut().apply(info, operation)?,
+        };
+        // `:set` changes both the local value and the global one used for new buffers and windows.
+        // Like in vim, new buffers don't inherit the file type though.
+        if scope == SetScope::All && info.name != "filetype" {
+            self.options.set(info, value.clone());
+        }
+
+        Ok(value)
+    }
+
+    /// Updates state that depends on the value of an option after it was set.
+    fn option_changed(&mut self, info: &OptionInfo) {
+        match info.name {
+            "termguicolors" => self
+                .highlights
+                .set_truecolor(self.options.get_bool("termguicolors")),
+            "swapfile" => {
+                if !self.window.get_buffer().options.get_bool("swapfile") {
+                    self.close_swap_file();
+                } else if self.swap_file.is_none() {
+                    self.open_swap_file();
+                }
+            }
+            "filetype" => {
+                let buffer = self.window.get_buffer_mut();
+                // Unknown file types simply aren't highlighted like in vim
+                let filetype = buffer.options.get_string("filetype").to_owned();
+                let syntax = self.syntaxes.find(&filetype);
+                buffer.set_syntax(syntax);
+
+                // Reopening the document would only make the server start over
+                if matches!(&self.lsp_document, Some((synced, _)) if *synced == filetype) {
+                    return;
+                }
+                self.detach_language_server();
+                if let Err(message) = self.attach_language_server() {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            // Unsaved changes of the listing would be lost
+            "dirhidden" | "dirsort" if self.is_saved() => {
+                if let Err(message) = self.refresh_directory() {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            // Options like 'number' change the width of the text area
+            _ if info.scope == OptionScope::Window => self.window.scroll_to_cursor(),
+            _ => {}
+        }
+    }
+
+    /// Executes `:lsp` and its subcommands.
+    fn lsp_command(&mut self, arguments: &str) -> CommandResult {
+        let (subcommand, arguments) = match arguments.split_once(char::is_whitespace) {
+            Some((subcommand, arguments)) => (subcommand, arguments.trim_start()),
+            None => (arguments, ""),
+        };
+
+        match subcommand {
+            "server" => {
+                let Some((filetype, command)) = arguments.split_once(char::is_whitespace) else {
+                    return Err("Usage: lsp server {filetype} {command}".to_owned());
+                };
+                self.language_servers
+                    .insert(filetype.to_owned(), command.trim().to_owned());
+                if self.window.get_buffer().options.get_string("filetype") == filetype {
+                    self.attach_language_server()?;
+                }
+            }
+            "info" => {
+                let mut lines = self
+                    .language_servers
+                    .iter()
+                    .map(|(filetype, command)| {
+                        let state = match self.lsp_clients.get(filetype) {
+                            Some(client) if client.is_initialized() => "running",
+                            Some(_) => "starting",
+                            None => "stopped",
+                        };
+                        format!("{:<12} {:<9} {}", filetype, state, command)
+                    })
+                    .collect::<Vec<_>>();
+                lines.sort();
+                if lines.is_empty() {
+                    self.notify("No language servers configured", LogLevel::Info);
+                } else {
+                    self.notify(lines.join("\n"), LogLevel::Info);
+                }
+            }
+            "stop" | "restart" => {
+                let filetype = self
+                    .window
+                    .get_buffer()
+                    .options
+                    .get_string("filetype")
+                    .to_owned();
+                self.detach_language_server();
+                let mut client = self
+                    .lsp_clients
+                    .remove(&filetype)
+                    .ok_or_else(|| "No language server running for this buffer".to_owned())?;
+                client.shutdown();
+                if subcommand == "restart" {
+                    self.attach_language_server()?;
+                }
+            }
+            "hover" => self.lsp_position_request(
+                Request::Hover,
+                "textDocument/hover",
+                "hoverProvider",
+                json!({}),
+            )?,
+            "definition" => self.lsp_position_request(
+                Request::Definition,
+                "textDocument/definition",
+                "definitionProvider",
+                json!({}),
+            )?,
+            "references" => self.lsp_position_request(
+                Request::References,
+                "textDocument/references",
+                "referencesProvider",
+                json!({ "context": { "includeDeclaration": true } }),
+            )?,
+            "rename" => {
+                if arguments.is_empty() {
+                    return Err("Argument required".to_owned());
+                }
+                self.lsp_position_request(
+                    Request::Rename,
+                    "textDocument/rename",
+                    "renameProvider",
+                    json!({ "newName": arguments }),
+                )?
+            }
+            "signature" => self.lsp_position_request(
+                Request::SignatureHelp { manual: true },
+                "textDocument/signatureHelp",
+                "signatureHelpProvider",
+                json!({}),
+            )?,
+            "format" => {
+                let tabstop = self.window.get_buffer().options.get_int("tabstop");
+                let expandtab = self.window.get_buffer().options.get_bool("expandtab");
+                self.lsp_request(
+                    Request::Formatting,
+                    "textDocument/formatting",
+                    "documentFormattingProvider",
+                    json!({ "options": { "tabSize": tabstop, "insertSpaces": expandtab } }),
+                )?
+            }
+            "codeaction" if arguments.is_empty() => {
+                let cursor = self.window.get_cursor();
+                let line_length = self.window.get_buffer().line_length(cursor.y);
+                let encoding = self.lsp_encoding()?;
+                let buffer = self.window.get_buffer();
+                let lines = &buffer.lines;
+                let start = encoding.encode_position(lines, Position::new(0, cursor.y));
+                let end = encoding.encode_position(lines, Position::new(line_length, cursor.y));
+                // Servers offer quick fixes for the diagnostics they are told about
+                let diagnostics = buffer
+                    .diagnostics
+                    .on_line(cursor.y)
+                    .map(|diagnostic| {
+                        json!({
+                            "range": {
+                                "start": encoding.encode_position(lines, diagnostic.start),
+                                "end": encoding.encode_position(lines, diagnostic.end),
+                            },
+                            "severity": diagnostic.severity.to_lsp(),
+                            "message": diagnostic.message,
+                            "source": diagnostic.source,
+                            "code": diagnostic.code,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                self.lsp_request(
+                    Request::CodeAction,
+                    "textDocument/codeAction",
+                    "codeActionProvider",
+                    json!({
+                        "range": { "start": start, "end": end },
+                        "context": { "diagnostics": diagnostics },
+                    }),
+                )?
+            }
+            "codeaction" => {
+                let index = arguments
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| number.checked_sub(1))
+                    .filter(|index| *index < self.code_actions.len())
+                    .ok_or_else(|| format!("Invalid code action: {}", arguments))?;
+                let action = self.code_actions[index].clone();
+                self.apply_code_action(&action)?;
+            }
+            "" => return Err("Argument required".to_owned()),
+            _ => return Err(format!("Unknown lsp command: {}", subcommand)),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the language server the current buffer is synced with and the buffer's URI
+    fn lsp_client(&mut self) -> std::result::Result<(String, &mut Client), String> {
+        let Some((filetype, uri)) = &self.lsp_document else {
+            return Err("No language server for this buffer".to_owned());
+        };
+        match self.lsp_clients.get_mut(filetype) {
+            Some(client) if client.is_initialized() => Ok((uri.clone(), client)),
+            Some(_) => Err("Language server is still starting".to_owned()),
+            None => Err("No language server for this buffer".to_owned()),
+        }
+    }
+
+    /// Returns how the language server of the current buffer counts columns
+    fn lsp_encoding(&mut self) -> std::result::Result<PositionEncoding, String> {
+        Ok(self.lsp_client()?.1.encoding)
+    }
+
+    /// Sends a request about the current buffer to its language server. `params` are extended by
+    /// the buffer's text document identifier.
+    fn lsp_request(
+        &mut self,
+        request: Request,
+        method: &str,
+        capability: &str,
+        mut params: Value,
+    ) -> CommandResult {
+        self.sync_language_server();
+        let (uri, client) = self.lsp_client()?;
+        if !client.supports(capability) {
+            return Err(format!("Language server doesn't support {}", method));
+        }
+        params["textDocument"] = json!({ "uri": uri });
+        client.request(request, method, params);
+        Ok(())
+    }
+
+    /// Like [`Editor::lsp_request`], additionally adding the cursor position to the parameters
+    fn lsp_position_request(
+        &mut self,
+        request: Request,
+        method: &str,
+        capability: &str,
+        mut params: Value,
+    ) -> CommandResult {
+        let cursor = self.window.get_cursor();
+        let encoding = self.lsp_encoding()?;
+        params["position"] = encoding.encode_position(&self.window.get_buffer().lines, cursor);
+        self.lsp_request(request, method, capability, params)
+    }
+
+    /// Starts the language server for the current buffer's file type if one is configured and
+    /// opens the buffer on it.
+    fn attach_language_server(&mut self) -> CommandResult {
+        let buffer = self.window.get_buffer();
+        let filetype = buffer.options.get_string("filetype").to_owned();
+        // Only files can be synced as servers identify documents by their path
+        let Some(path) = &buffer.path else {
+            return Ok(());
+        };
+        let uri = lsp::path_to_uri(path);
+        let Some(command) = self.language_servers.get(&filetype) else {
+            return Ok(());
+        };
+
+        if !self.lsp_clients.contains_key(&filetype) {
+            let root = std::env::current_dir().map_err(|e| e.to_string())?;
+            let client = Client::start(command, &root)
+                .map_err(|e| format!("Failed to start language server '{}': {}", command, e))?;
+            self.lsp_clients.insert(filetype.clone(), client);
+        }
+
+        let client = self.lsp_clients.get_mut(&filetype).unwrap();
+        let buffer = self.window.get_buffer_mut();
+        buffer.record_changes(true);
+        client.did_open(&uri, &filetype, buffer);
+        self.lsp_document = Some((filetype, uri));
+
+        Ok(())
+    }
+
+    /// Closes the current buffer on its language server, e.g. before another file is opened.
+    fn detach_language_server(&mut self) {
+        self.sync_language_server();
+        let Some((filetype, uri)) = self.lsp_document.take() else {
+            return;
+        };
+        if let Some(client) = self.lsp_clients.get_mut(&filetype) {
+            client.did_close(&uri);
+        }
+        let buffer = self.window.get_buffer_mut();
+        buffer.record_changes(false);
+        buffer.diagnostics.set("lsp", Vec::new());
+    }
+
+    /// Sends the changes made to the current buffer to its language server. Requests have to be
+    /// sent after this so they refer to the buffer's current state.
+    fn sync_language_server(&mut self) {
+        let Some((filetype, uri)) = &self.lsp_document else {
+            return;
+        };
+        let buffer = self.window.get_buffer_mut();
+        let changes = buffer.take_changes();
+        if changes.is_empty() {
+            return;
+        }
+        if let Some(client) = self.lsp_clients.get_mut(filetype) {
+            client.did_change(uri, buffer, &changes);
+        }
+    }
+
+    fn handle_lsp_events(&mut self) {
+        self.sync_language_server();
+
+        let mut events = Vec::new();
+        for (filetype, client) in &mut self.lsp_clients {
+            events.extend(
+                client
+                    .poll()
+                    .into_iter()
+                    .map(|event| (filetype.clone(), event)),
+            );
+        }
+
+        for (filetype, event) in events {
+            match event {
+                LspEvent::Response { request, result } => {
+                    let result = result
+                        .map_err(|message| format!("Language server error: {}", message))
+                        .and_then(|result| self.handle_lsp_response(request, result));
+                    if let Err(message) = result {
+                        self.notify(message, LogLevel::Error);
+                    }
+                }
+                LspEvent::ApplyEdit { id, edit } => {
+                    let result = self.apply_workspace_edit(&filetype, &edit);
+                    let response = match &result {
+                        Ok(_) => json!({ "applied": true }),
+                        Err(message) => json!({ "applied": false, "failureReason": message }),
+                    };
+                    if let Err(message) = result {
+                        self.notify(message, LogLevel::Error);
+                    }
+                    if let Some(client) = self.lsp_clients.get_mut(&filetype) {
+                        client.respond(id, Ok(response));
+                    }
+                }
+                LspEvent::Message { message, level } => self.notify(message, level),
+                LspEvent::Exited(reason) => {
+                    if let Some(client) = self.lsp_clients.remove(&filetype) {
+                        self.notify(
+                            format!("Language server '{}' exited: {}", client.name, reason),
+                            LogLevel::Error,
+                        );
+                    }
+                    if matches!(&self.lsp_document, Some((document, _)) if *document == filetype) {
+                        self.lsp_document = None;
+                        let buffer = self.window.get_buffer_mut();
+                        buffer.record_changes(false);
+                        buffer.diagnostics.set("lsp", Vec::new());
+                    }
+                }
+                LspEvent::Diagnostics {
+                    uri,
+                    version,
+                    diagnostics,
+                } => self.set_lsp_diagnostics(&filetype, &uri, version, &diagnostics),
+            }
+        }
+    }
+
+    /// Replaces the language server's diagnostics of the current buffer, ignoring diagnostics for
+    /// other documents or older versions of this one.
+    fn set_lsp_diagnostics(
+        &mut self,
+        filetype: &str,
+        uri: &str,
+        version: Option<i64>,
+        diagnostics: &[Value],
+    ) {
+        if !matches!(&self.lsp_document, Some((document, current)) if document == filetype && current == uri)
+        {
+            return;
+        }
+        let Ok(encoding) = self.lsp_encoding() else {
+            return;
+        };
+        let buffer = self.window.get_buffer_mut();
+        if version.is_some_and(|version| version != buffer.version as i64) {
+            return;
+        }
+
+        let diagnostics = diagnostics
+            .iter()
+            .map(|diagnostic| Diagnostic {
+                start: encoding.decode_position(&buffer.lines, &diagnostic["range"]["start"]),
+                end: encoding.decode_position(&buffer.lines, &diagnostic["range"]["end"]),
+                severity: Severity::from_lsp(diagnostic["severity"].as_i64().unwrap_or(1)),
+                message: diagnostic["message"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_owned(),
+                source: diagnostic["source"].as_str().map(str::to_owned),
+                code: match &diagnostic["code"] {
+                    Value::String(code) => Some(code.clone()),
+                    Value::Number(code) => Some(code.to_string()),
+                    _ => None,
+                },
+            })
+            .collect();
+        buffer.diagnostics.set("lsp", diagnostics);
+    }
+
+    /// Moves the cursor to the next or previous diagnostic of the current buffer
+    fn jump_to_diagnostic(&mut self, forward: bool) -> CommandResult {
+        let buffer = self.window.get_buffer();
+        let diagnostic = buffer
+            .diagnostics
+            .next(self.window.get_cursor(), forward)
+            .ok_or_else(|| "No diagnostics".to_owned())?;
+        let (start, message) = (diagnostic.start, format_diagnostic(diagnostic));
+        self.record_jump();
+        self.window.set_cursor(start);
+        self.notify(message, LogLevel::Info);
+        Ok(())
+    }
+
+    /// Lists the diagnostics of the current buffer, or jumps to the one with the given number
+    fn diagnostics_command(&mut self, arguments: &str) -> CommandResult {
+        let buffer = self.window.get_buffer();
+        let diagnostics = buffer.diagnostics.all();
+        if diagnostics.is_empty() {
+            return Err("No diagnostics".to_owned());
+        }
+
+        if arguments.is_empty() {
+            let listing = diagnostics
+                .iter()
+                .enumerate()
+                .map(|(i, diagnostic)| {
+                    format!(
+                        "{:>3}: {}:{} {}",
+                        i + 1,
+                        diagnostic.start.y + 1,
+                        diagnostic.start.x + 1,
+                        format_diagnostic(diagnostic)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            self.notify(listing, LogLevel::Info);
+            return Ok(());
+        }
+
+        let diagnostic = arguments
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| diagnostics.get(number.checked_sub(1)?))
+            .ok_or_else(|| format!("Invalid diagnostic number: {}", arguments))?;
+        let (start, message) = (diagnostic.start, format_diagnostic(diagnostic));
+        self.record_jump();
+        self.window.set_cursor(start);
+        self.notify(message, LogLevel::Info);
+        Ok(())
+    }
+
+    fn handle_lsp_response(&mut self, request: Request, result: Value) -> CommandResult {
+        match request {
+            Request::Hover => {
+                let text = lsp::markup_to_text(&result["contents"]);
+                if text.trim().is_empty() {
+                    return Err("No information available".to_owned());
+                }
+                self.notify(text.trim(), LogLevel::Info);
+            }
+            Request::Definition => {
+                let locations = lsp::locations(&result);
+                let Some((uri, range)) = locations.first() else {
+                    return Err("No definition found".to_owned());
+                };
+                self.jump_to_location(uri, &range["start"])?;
+                if locations.len() > 1 {
+                    self.notify(
+                        format!("definition 1 of {}", locations.len()),
+                        LogLevel::Info,
+                    );
+                }
+            }
+            Request::References => {
+                let locations = lsp::locations(&result);
+                if locations.is_empty() {
+                    return Err("No references found".to_owned());
+                }
+                let lines = locations
+                    .iter()
+                    .map(|(uri, range)| self.format_location(uri, &range["start"]))
+                    .collect::<Vec<_>>();
+                self.notify(lines.join("\n"), LogLevel::Info);
+            }
+            Request::Rename => {
+                let (filetype, _) = self.lsp_document.clone().unwrap_or_default();
+                let summary = self.apply_workspace_edit(&filetype, &result)?;
+                self.notify(summary, LogLevel::Info);
+            }
+            Request::Completion { position } => {
+                // The completion is outdated if the menu was closed or reopened in the meantime
+                let Some(completion) = &mut self.completion else {
+                    return Ok(());
+                };
+                if completion.source != "lsp"
+                    || !completion.pending
+                    || completion.start.y != position.y
+                {
+                    return Ok(());
+                }
+                let cursor = self.window.get_cursor();
+                let Some(base) = completion.base(self.window.get_buffer(), cursor) else {
+                    return Ok(());
+                };
+                completion.deliver(lsp::completion_items(&result), &base);
+                if completion.is_empty() {
+                    self.completion = None;
+                    return Err("No completions found".to_owned());
+                }
+            }
+            Request::SignatureHelp { manual } => {
+                let signatures = result["signatures"].as_array().cloned().unwrap_or_default();
+                let active = result["activeSignature"].as_u64().unwrap_or(0) as usize;
+                match signatures.get(active).or(signatures.first()) {
+                    Some(signature) => {
+                        let label = signature["label"].as_str().unwrap_or_default();
+                        self.notify(label, LogLevel::Info);
+                    }
+                    None if manual => return Err("No signature help available".to_owned()),
+                    None => {}
+                }
+            }
+            Request::DocumentSymbol { query } => {
+                let encoding = self.lsp_encoding()?;
+                let buffer = self.window.get_buffer();
+                let items = lsp::document_symbols(&result)
+                    .into_iter()
+                    .map(|(name, kind, position)| {
+                        let position = encoding.decode_position(&buffer.lines, &position);
+                        PickerItem {
+                            kind,
+                            preview: buffer.path.clone().map(|path| (path, position.y)),
+                            ..PickerItem::new(name, Target::Position(position))
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                if items.is_empty() {
+                    return Err("No symbols found".to_owned());
+                }
+                self.picker = Some(Picker::new(Box::new(Items::new("symbols", items)), &query));
+            }
+            Request::Formatting => {
+                let edits = result.as_array().cloned().unwrap_or_default();
+                let encoding = self.lsp_encoding()?;
+                self.check_modifiable()?;
+                encoding.apply_text_edits(self.window.get_buffer_mut(), &edits);
+                // The cursor may now be past the end of its line
+                self.window.set_cursor(self.window.get_cursor());
+            }
+            Request::CodeAction => {
+                self.code_actions = result.as_array().cloned().unwrap_or_default();
+                if self.code_actions.is_empty() {
+                    return Err("No code actions available".to_owned());
+                }
+                let mut lines = self
+                    .code_actions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, action)| {
+                        format!(
+                            "{:>3}: {}",
+                            i + 1,
+                            action["title"].as_str().unwrap_or_default()
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                lines.push("Apply one with :lsp codeaction {number}".to_owned());
+                self.notify(lines.join("\n"), LogLevel::Info);
+            }
+            Request::Initialize | Request::ExecuteCommand => {}
+        }
+
+        Ok(())
+    }
+
+    /// Requests signature help if the character typed in insert mode is one of the server's
+    /// trigger characters
+    fn lsp_trigger_signature_help(&mut self, c: char) {
+        let Ok((_, client)) = self.lsp_client() else {
+            return;
+        };
+        let triggers = &client.capabilities()["signatureHelpProvider"]["triggerCharacters"];
+        let triggered = triggers
+            .as_array()
+            .is_some_and(|triggers| triggers.iter().any(|t| t.as_str() == Some(&c.to_string())));
+        if triggered {
+            // Not every server answers with something useful, so errors aren't shown
+            let _ = self.lsp_position_request(
+                Request::SignatureHelp { manual: false },
+                "textDocument/signatureHelp",
+                "signatureHelpProvider",
+                json!({}),
+            );
+        }
+    }
+
+    fn apply_code_action(&mut self, action: &Value) -> CommandResult {
+        let (filetype, _) = self.lsp_document.clone().unwrap_or_default();
+        if action["edit"].is_object() {
+            self.apply_workspace_edit(&filetype, &action["edit"])?;
+        }
+
+        // Actions are either a Command themselves or may contain one
+        let command = match &action["command"] {
+            Value::String(_) => action,
+            command => command,
+        };
+        if command["command"].is_string() {
+            self.sync_language_server();
+            let (_, client) = self.lsp_client()?;
+            client.request(
+                Request::ExecuteCommand,
+                "workspace/executeCommand",
+                json!({ "command": command["command"], "arguments": command["arguments"] }),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Applies a workspace edit of the language server for the given file type. Files other than
+    /// the current buffer are changed on disk. Returns a summary of the changes.
+    fn apply_workspace_edit(
+        &mut self,
+        filetype: &str,
+        edit: &Value,
+    ) -> std::result::Result<String, String> {
+        let mut files: Vec<(String, Vec<Value>)> = Vec::new();
+        if let Some(changes) = edit["changes"].as_object() {
+            for (uri, edits) in changes {
+                files.push((uri.clone(), edits.as_array().cloned().unwrap_or_default()));
+            }
+        }
+        if let Some(document_changes) = edit["documentChanges"].as_array() {
+            for change in document_changes {
+                let Some(uri) = change["textDocument"]["uri"].as_str() else {
+                    return Err("Creating, renaming and deleting files isn't supported".to_owned());
+                };
+                let edits = change["edits"].as_array().cloned().unwrap_or_default();
+                files.push((uri.to_owned(), edits));
+            }
+        }
+
+        let encoding = self
+            .lsp_clients
+            .get(filetype)
+            .ok_or_else(|| "No language server for this buffer".to_owned())?
+            .encoding;
+        let current = self.lsp_document.as_ref().map(|(_, uri)| uri.clone());
+        // Check before other files are changed on disk
+        if files.iter().any(|(uri, _)| Some(uri) == current.as_ref()) {
+            self.check_modifiable()?;
+        }
+        let mut count = 0;
+        for (uri, edits) in &files {
+            count += edits.len();
+            if Some(uri) == current.as_ref() {
+                encoding.apply_text_edits(self.window.get_buffer_mut(), edits);
+                continue;
+            }
+
+            let path = lsp::uri_to_path(uri).ok_or_else(|| format!("Invalid URI: {}", uri))?;
+            let mut buffer = Buffer::new_from_file(&path, self.options.get_list("fileencodings"))
+                .map_err(|e| format!("Can't open file {}: {}", path.display(), e))?;
+            encoding.apply_text_edits(&mut buffer, edits);
+            buffer
+                .save(&self.options)
+                .map_err(|e| format!("Error when trying to save to file: {}", e))?;
+        }
+        self.window.set_cursor(self.window.get_cursor());
+
+        Ok(format!("{} changes in {} files", count, files.len()))
+    }
+
+    fn jump_to_location(&mut self, uri: &str, position: &Value) -> CommandResult {
+        self.record_jump();
+        let current = self.lsp_document.as_ref().map(|(_, uri)| uri.as_str());
+        if current != Some(uri) {
+            let path = lsp::uri_to_path(uri).ok_or_else(|| format!("Invalid URI: {}", uri))?;
+            // The server of the current buffer decides how columns are counted
+            let encoding = self.lsp_encoding()?;
+            self.edit_file(Some(&path), false, EditOptions::default())?;
+            let position = encoding.decode_position(&self.window.get_buffer().lines, position);
+            self.window.set_cursor(position);
+            return Ok(());
+        }
+
+        let encoding = self.lsp_encoding()?;
+        let position = encoding.decode_position(&self.window.get_buffer().lines, position);
+        self.window.set_cursor(position);
+        Ok(())
+    }
+
+    /// Formats a location as `path:line:column: text of the line`
+    fn format_location(&self, uri: &str, position: &Value) -> String {
+        let path = lsp::uri_to_path(uri).unwrap_or_else(|| uri.into());
+        let line = position["line"].as_u64().unwrap_or(0) as usize;
+        let character = position["character"].as_u64().unwrap_or(0) as usize;
+
+        let current = self.lsp_document.as_ref().map(|(_, uri)| uri.as_str());
+        let text = if current == Some(uri) {
+            self.window.get_buffer().lines.get(line).cloned()
+        } else {
+            std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| contents.lines().nth(line).map(|line| line.to_owned()))
+        }
+        .unwrap_or_default();
+
+        format!(
+            "{}:{}:{}: {}",
+            util::display_path(&path),
+            line + 1,
+            character + 1,
+            text.trim()
+        )
+    }
+
+    /// Replaces the current buffer by the given file, or reloads the current file if no path is
+    /// given. Unless `force` is set, unsaved changes prevent this.
+    fn edit_file(
+        &mut self,
+        path: Option<&Path>,
+        force: bool,
+        options: EditOptions,
+    ) -> CommandResult {
+        if !force && !self.is_saved() {
+            return Err("No write since last change (add ! to override)".to_owned());
+        }
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => self
+                .window
+                .get_buffer()
+                .path
+                .clone()
+                .ok_or_else(|| "No file name".to_owned())?,
+        };
+
+        let (buffer, file_view) = self
+            .read_file(&path, options)
+            .map_err(|e| format!("Can't open file {}: {}", util::display_path(&path), e))?;
+        self.detach_language_server();
+        self.close_swap_file();
+        self.window.set_buffer(buffer);
+        self.file_view = file_view;
+        let result = self.detect_filetype();
+        self.open_swap_file();
+        result
+    }
+
+    /// Runs a command of `:!` with the terminal handed over to it, so it can be interactive, and
+    /// waits for Enter afterwards so its output can be read
+    fn shell_command(&mut self, command: &str) -> CommandResult {
+        self.suspend_terminal()
+            .map_err(|e| format!("Can't suspend the terminal: {}", e))?;
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .status();
+        if let Ok(status) = &status {
+            let mut stdout = std::io::stdout();
+            if !status.success() {
+                let _ = write!(stdout, "\r\nshell returned {}", exit_code(status));
+            }
+            let _ = write!(stdout, "\r\n\r\nPress ENTER to continue");
+            let _ = stdout.flush();
+            let _ = std::io::stdin().read_line(&mut String::new());
+        }
+        self.resume_terminal()
+            .map_err(|e| format!("Can't resume the terminal: {}", e))?;
+
+        let status = status.map_err(|e| format!("Can't run {}: {}", command, e))?;
+        if !status.success() {
+            return Err(format!("shell returned {}", exit_code(&status)));
+        }
+        Ok(())
+    }
+
+    /// Gives the terminal back to the shell for running a command
+    fn suspend_terminal(&mut self) -> Result<()> {
+        crossterm::QueueableCommand::queue(
+            &mut std::io::stdout(),
+            crossterm::event::DisableMouseCapture,
+        )?;
+        crossterm::QueueableCommand::queue(
+            &mut std::io::stdout(),
+            crossterm::event::DisableFocusChange,
+        )?;
+        self.screen.end()?;
+        Ok(())
+    }
+
+    /// Takes the terminal back after [`Editor::suspend_terminal`]. The command may have changed
+    /// its size, and everything is drawn again.
+    fn resume_terminal(&mut self) -> Result<()> {
+        self.screen.begin()?;
+        crossterm::QueueableCommand::queue(
+            &mut std::io::stdout(),
+            crossterm::event::EnableMouseCapture,
+        )?;
+        crossterm::QueueableCommand::queue(
+            &mut std::io::stdout(),
+            crossterm::event::EnableFocusChange,
+        )?;
+        let (width, height) = terminal::size()?;
+        self.resize(width, height);
+        Ok(())
+    }
+
+    /// Replaces the lines from `first` to `last` by the output of the command they're piped
+    /// into. The lines are replaced all at once, so it's a single change.
+    fn filter_lines(&mut self, (first, last): cmdline::LineRange, command: &str) -> CommandResult {
+        if self.file_view.is_some() {
+            return Err("Only text can be filtered".to_owned());
+        }
+        self.check_modifiable()?;
+        let buffer = self.window.get_buffer();
+        let input = buffer.lines[first..=last].join("\n") + "\n";
+        let output = run_shell(command, Some(input))?;
+        let output = output.strip_suffix('\n').unwrap_or(&output);
+
+        let end = Position::new(buffer.line_length(last), last);
+        let buffer = self.window.get_buffer_mut();
+        if !output.is_empty() {
+            buffer.replace_text(Position::new(0, first), end, output);
+        } else {
+            // Without output the lines are deleted, including the line break after them
+            buffer.remove_lines(first, last);
+        }
+        self.window.set_cursor(Position::new(
+            0,
+            first.min(self.window.get_buffer().lines.len() - 1),
+        ));
+        self.notify(
+            format!("{} lines filtered", last - first + 1),
+            LogLevel::Info,
+        );
+        Ok(())
+    }
+
+    /// Executes `:r file` and `:r !cmd`, which insert the file or the output of the command
+    /// below the cursor line. Without an argument the current file is inserted.
+    fn read_command(&mut self, arguments: &str) -> CommandResult {
+        if self.file_view.is_some() {
+            return Err("Only text can be inserted".to_owned());
+        }
+        self.check_modifiable()?;
+        let text = match arguments.strip_prefix('!') {
+            Some(command) if command.trim().is_empty() => {
+                return Err("Argument required".to_owned())
+            }
+            Some(command) => run_shell(command.trim(), None)?,
+            None => {
+                let path = match arguments {
+                    "" => self
+                        .window
+                        .get_buffer()
+                        .path
+                        .clone()
+                        .ok_or_else(|| "No file name".to_owned())?,
+                    path => util::expand_home(path),
+                };
+                let bytes = std::fs::read(&path)
+                    .map_err(|e| format!("Can't open file {}: {}", util::display_path(&path), e))?;
+                String::from_utf8_lossy(&bytes).replace("\r\n", "\n")
+            }
+        };
+        let text = text.strip_suffix('\n').unwrap_or(&text);
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let y = self.window.get_cursor().y;
+        let end = Position::new(self.window.get_buffer().line_length(y), y);
+        self.window
+            .get_buffer_mut()
+            .insert_text(end, &format!("\n{}", text));
+        self.window.set_cursor(Position::new(0, y + 1));
+        Ok(())
+    }
+
+    /// Executes `:pick {source} [query]`, which opens the picker on the source with the query
+    /// typed
+    fn pick_command(&mut self, arguments: &str) -> CommandResult {
+        let (name, query) = match arguments.split_once(char::is_whitespace) {
+            Some((name, query)) => (name, query.trim()),
+            None => (arguments, ""),
+        };
+        let source: Box<dyn PickerSource> = match name {
+            "" => return Err("Argument required".to_owned()),
+            "files" => {
+                let root = std::env::current_dir()
+                    .map_err(|e| format!("Can't get the working directory: {}", e))?;
+                Box::new(Files::new(root))
+            }
+            "buffers" => Box::new(picker::buffers(&[self.window.get_buffer()])),
+            "commands" => Box::new(picker::commands()),
+            "searches" => Box::new(picker::searches(&self.history)),
+            "registers" => {
+                let mut registers = self
+                    .registers
+                    .list()
+                    .into_iter()
+                    .map(|(name, register)| (name, register.clone()))
+                    .collect::<Vec<_>>();
+                for name in [':', '%', '/'] {
+                    if let Some(register) = self.register(name) {
+                        registers.push((name, register));
+                    }
+                }
+                Box::new(picker::registers(&registers))
+            }
+            "marks" => Box::new(picker::marks(self.window.get_buffer())),
+            "jumps" => Box::new(picker::jumps(&self.jumps, self.window.get_buffer())),
+            // The picker opens once the language server answered
+            "symbols" => {
+                return self.lsp_request(
+                    Request::DocumentSymbol {
+                        query: query.to_owned(),
+                    },
+                    "textDocument/documentSymbol",
+                    "documentSymbolProvider",
+                    json!({}),
+                )
+            }
+            _ => return Err(format!("Unknown picker: {}", name)),
+        };
+        self.picker = Some(Picker::new(source, query));
+        Ok(())
+    }
+
+    /// Handles a key typed while the picker is open. Enter picks the selected item.
+    fn picker_key(&mut self, event: KeyEvent) {
+        let Some(picker) = &mut self.picker else {
+            return;
+        };
+        let control = event.modifiers == KeyModifiers::CONTROL;
+        match event.code {
+            KeyCode::Esc => self.picker = None,
+            KeyCode::Char('c') if control => self.picker = None,
+            KeyCode::Enter => {
+                let Some(item) = picker.selected() else {
+                    return;
+                };
+                let target = item.target.clone();
+                self.picker = None;
+                if let Err(message) = self.pick(target) {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            KeyCode::Char('n') if control => picker.select(1),
+            KeyCode::Down | KeyCode::Tab => picker.select(1),
+            KeyCode::Char('p') if control => picker.select(-1),
+            KeyCode::Up | KeyCode::BackTab => picker.select(-1),
+            KeyCode::PageDown => picker.select(10),
+            KeyCode::PageUp => picker.select(-10),
+            KeyCode::Char('u') if control => picker.set_query(String::new()),
+            KeyCode::Backspace => {
+                let mut query = picker.query().to_owned();
+                query.pop();
+                picker.set_query(query);
+            }
+            KeyCode::Char(c) if !control => {
+                let query = format!("{}{}", picker.query(), c);
+                picker.set_query(query);
+            }
+            _ => {}
+        }
+    }
+
+    /// Executes `:grep pattern [paths]`, which fills the quickfix list with the lines matching
+    /// the pattern. 'grepprg' decides whether the built-in search or an external program is
+    /// used.
+    fn grep_command(&mut self, arguments: &str) -> CommandResult {
+        if arguments.is_empty() {
+            return Err("Argument required".to_owned());
+        }
+        let program = self.options.get_string("grepprg");
+        let entries = if program == "internal" {
+            let (pattern, mut paths) = quickfix::split_grep_arguments(arguments)?;
+            let regex = regex::Regex::new(&pattern)
+                .map_err(|_| format!("Invalid regular expression: {}", pattern))?;
+            if paths.is_empty() {
+                paths.push(PathBuf::from("."));
+            }
+            quickfix::grep(&regex, &paths)
+        } else {
+            let command = quickfix::command_line(program, arguments);
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .output()
+                .map_err(|e| format!("Can't run {}: {}", command, e))?;
+            // grep exits with 1 if nothing matched and with more if something went wrong
+            if output.status.code().is_none_or(|code| code > 1) {
+                let error = String::from_utf8_lossy(&output.stderr);
+                return Err(format!(
+                    "{} failed: {}",
+                    command,
+                    error.lines().next().unwrap_or_default()
+                ));
+            }
+            let format = ErrorFormat::new(self.options.get_list("grepformat"))?;
+            format.parse(&String::from_utf8_lossy(&output.stdout))
+        };
+        if entries.is_empty() {
+            return Err(format!("No match: {}", arguments));
+        }
+        self.quickfix = QuickfixList::new(entries);
+        Ok(())
+    }
+
+    /// Executes `:cfile file`, which fills the quickfix list with the messages in the file,
+    /// e.g. the saved output of a compiler, as described by 'errorformat'
+    fn cfile_command(&mut self, arguments: &str) -> CommandResult {
+        if arguments.is_empty() {
+            return Err("Argument required".to_owned());
+        }
+        let path = util::expand_home(arguments);
+        let output = std::fs::read(&path)
+            .map_err(|e| format!("Can't read {}: {}", util::display_path(&path), e))?;
+        let format = ErrorFormat::new(self.options.get_list("errorformat"))?;
+        let entries = format.parse(&String::from_utf8_lossy(&output));
+        self.quickfix = QuickfixList::new(entries);
+        Ok(())
+    }
+
+    /// Shows the output of a running `:make` and fills the quickfix list with the messages once
+    /// it's done, jumping to the first error unless `:make!` was used
+    fn poll_make(&mut self) {
+        let Some((make, _)) = &mut self.make else {
+            return;
+        };
+        let output = make.poll();
+        let done = make.is_done();
+        // Progress like cargo's `Compiling ...` is shown while it runs
+        if let Some(line) = output.iter().rev().find(|line| !line.trim().is_empty()) {
+            self.notify(format!("make: {}", line.trim()), LogLevel::Info);
+        }
+        if !done {
+            return;
+        }
+        let (mut make, jump) = self.make.take().unwrap();
+        let (status, entries) = match make.finish() {
+            Ok(result) => result,
+            Err(e) => {
+                self.notify(format!("make: {}", e), LogLevel::Error);
+                return;
+            }
+        };
+
+        let count = |kind| {
+            entries
+                .iter()
+                .filter(|entry| entry.kind == Some(kind))
+                .count()
+        };
+        let (errors, warnings) = (count('E'), count('W'));
+        let first_error = make::first_error(&entries);
+        self.quickfix = QuickfixList::new(entries);
+        let mut summary = format!("make: {} errors, {} warnings", errors, warnings);
+        if let Some(code) = status.code().filter(|code| *code != 0) {
+            summary.push_str(&format!(" (exit code {})", code));
+        }
+        let level = match status.success() {
+            true => LogLevel::Info,
+            false => LogLevel::Error,
+        };
+        self.notify(summary, level);
+        if let (Some(index), true) = (first_error, jump) {
+            if let Err(message) = self.jump_to_quickfix(index) {
+                self.notify(message, LogLevel::Error);
+            }
+        }
+    }
+
+    /// Opens the file of the quickfix entry with the given index and moves the cursor to it
+    fn jump_to_quickfix(&mut self, index: usize) -> CommandResult {
+        let entry = self.quickfix.select(index)?.clone();
+        self.record_jump();
+        if self.window.get_buffer().path.as_ref() != Some(&entry.path) {
+            self.edit_file(Some(&entry.path), false, EditOptions::default())?;
+        }
+        self.window.set_cursor(entry.position);
+        let count = self.quickfix.entries().len();
+        self.notify(
+            format!("({} of {}): {}", index + 1, count, entry.text),
+            LogLevel::Info,
+        );
+        Ok(())
+    }
+
+    /// Shows the quickfix list in the picker, with the current entry selected. There are no
+    /// windows to show it in yet.
+    fn open_quickfix(&mut self) -> CommandResult {
+        let items = self
+            .quickfix
+            .entries()
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| PickerItem {
+                preview: Some((entry.path.clone(), entry.position.y)),
+                ..PickerItem::new(entry.to_string(), Target::Quickfix(index))
+            })
+            .collect::<Vec<_>>();
+        if items.is_empty() {
+            return Err("No Errors".to_owned());
+        }
+        let mut picker = Picker::new(Box::new(Items::new("quickfix", items)), "");
+        picker.select(self.quickfix.current() as isize);
+        self.picker = Some(picker);
+        Ok(())
+    }
+
+    /// Does what picking an item of the picker does
+    fn pick(&mut self, target: Target) -> CommandResult {
+        match target {
+            // The current file isn't read again
+            Target::File(path) if self.window.get_buffer().path.as_ref() == Some(&path) => Ok(()),
+            Target::File(path) => {
+                self.record_jump();
+                self.edit_file(Some(&path), false, EditOptions::default())
+            }
+            Target::Position(position) => {
+                self.record_jump();
+                self.window.set_cursor(position);
+                Ok(())
+            }
+            Target::Location(path, position) => {
+                self.record_jump();
+                if self.window.get_buffer().path.as_ref() != Some(&path) {
+                    self.edit_file(Some(&path), false, EditOptions::default())?;
+                }
+                self.window.set_cursor(position);
+                Ok(())
+            }
+            Target::CommandLine(text) => {
+                self.change_mode(Mode::Command);
+                self.command_line.insert(&text);
+                Ok(())
+            }
+            Target::Search(pattern) => {
+                self.last_search = Some(pattern.clone());
+                self.search(&pattern, true)
+            }
+            Target::Quickfix(index) => self.jump_to_quickfix(index),
+            Target::Put(name) => self.put(Some(name), false),
+        }
+    }
+
+    /// Executes the actions for directory listings. Outside of listings, Enter moves to the next
+    /// line like in vim and `-` lists the directory of the current file.
+    fn directory_action(&mut self, action: Action) -> CommandResult {
+        let buffer = self.window.get_buffer();
+        let directory = buffer.directory.as_ref();
+        match action {
+            Action::OpenEntry => {
+                let Some(directory) = directory else {
+                    self.window.move_down();
+                    self.window.move_to_first_char_in_line();
+                    return Ok(());
+                };
+                let name = &buffer.lines[self.window.get_cursor().y];
+                if name.is_empty() {
+                    return Ok(());
+                }
+                let path = directory.entry_path(name);
+                self.edit_file(Some(&path), false, EditOptions::default())
+            }
+            Action::ParentDirectory => {
+                // Select the entry of the directory or file that was shown
+                let current = match (directory, &buffer.path) {
+                    (Some(directory), _) => directory.path.clone(),
+                    (None, Some(path)) => std::fs::canonicalize(path)
+                        .or_else(|_| std::path::absolute(path))
+                        .map_err(|e| format!("Can't resolve {}: {}", path.display(), e))?,
+                    (None, None) => {
+                        return self.edit_file(Some(Path::new(".")), false, EditOptions::default())
+                    }
+                };
+                let Some(parent) = current.parent() else {
+                    return Ok(());
+                };
+                self.edit_file(Some(parent), false, EditOptions::default())?;
+
+                let name = current
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let line = self
+                    .window
+                    .get_buffer()
+                    .lines
+                    .iter()
+                    .position(|line| line.trim_end_matches('/') == name);
+                if let Some(line) = line {
+                    self.window.set_cursor(Position::new(0, line));
+                }
+                Ok(())
+            }
+            _ if directory.is_none() => Err("Not a directory listing".to_owned()),
+            Action::ToggleHiddenEntries | Action::CycleSortOrder => {
+                if !self.is_saved() {
+                    return Err("No write since last change".to_owned());
+                }
+                let (name, value) = match action {
+                    Action::ToggleHiddenEntries => (
+                        "dirhidden",
+                        OptionValue::Bool(!self.options.get_bool("dirhidden")),
+                    ),
+                    _ => {
+                        let current = self.options.get_string("dirsort");
+                        let index = directory::SORT_ORDERS
+                            .iter()
+                            .position(|order| *order == current)
+                            .map_or(0, |index| (index + 1) % directory::SORT_ORDERS.len());
+                        (
+                            "dirsort",
+                            OptionValue::String(directory::SORT_ORDERS[index].to_owned()),
+                        )
+                    }
+                };
+                let info = options::find_option(name).unwrap();
+                self.options.set(info, value);
+                self.option_changed(info);
+                Ok(())
+            }
+            Action::ChangeToListedDirectory => {
+                let path = directory.unwrap().path.clone();
+                self.change_directory(&path, DirectoryScope::Global)?;
+                self.notify(path.to_string_lossy(), LogLevel::Info);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the listed directory again, keeping the cursor on the same line
+    fn refresh_directory(&mut self) -> CommandResult {
+        let Some(directory) = &self.window.get_buffer().directory else {
+            return Ok(());
+        };
+        let path = directory.path.clone();
+        let buffer = Buffer::new_directory(
+            &path,
+            self.options.get_bool("dirhidden"),
+            self.options.get_string("dirsort"),
+        )
+        .map_err(|e| format!("Can't read directory {}: {}", path.display(), e))?;
+
+        let cursor = self.window.get_cursor();
+        self.window.set_buffer(buffer);
+        self.window.set_cursor(cursor);
+        self.detect_filetype()
+    }
+
+    /// Writes the listing of a directory by applying the changes made to it, after asking unless
+    /// `force` is set
+    fn write_directory(&mut self, force: bool) -> std::result::Result<Notification, String> {
+        let buffer = self.window.get_buffer();
+        let directory = buffer.directory.as_ref().unwrap();
+        let operations = directory.changes(&buffer.lines)?;
+        if operations.is_empty() {
+            // Lines may have been moved around
+            self.refresh_directory()?;
+            return Ok(Notification::new(
+                "No changes to the directory".to_owned(),
+                LogLevel::Info,
+            ));
+        }
+        if force {
+            return self.apply_directory_changes(&operations);
+        }
+
+        // Keep the list on the screen
+        let shown = (self.height as usize).saturating_sub(4).max(1);
+        let mut message = format!(
+            "Apply these changes to {}?",
+            util::display_path(&directory.path)
+        );
+        for operation in operations.iter().take(shown) {
+            message.push_str(&format!("\n  {}", operation));
+        }
+        if operations.len() > shown {
+            message.push_str(&format!("\n  and {} more", operations.len() - shown));
+        }
+        let count = operations.len();
+        self.prompt = Some(Prompt {
+            message,
+            choices: vec![('y', "[Y]es"), ('n', "[N]o")],
+            kind: PromptKind::DirectoryChanges(operations),
+        });
+        Ok(Notification::new(
+            format!("{} changes to apply", count),
+            LogLevel::Info,
+        ))
+    }
+
+    fn apply_directory_changes(
+        &mut self,
+        operations: &[Operation],
+    ) -> std::result::Result<Notification, String> {
+        let directory = self.window.get_buffer().directory.as_ref().unwrap();
+        let result = directory.apply(operations);
+        // Show what was done even if not everything worked
+        self.refresh_directory()?;
+        result?;
+        Ok(Notification::new(
+            format!("{} changes applied", operations.len()),
+            LogLevel::Info,
+        ))
+    }
+
+    /// Returns the path of the file in the window or hex view and its stamp from when it was last
+    /// read or written. Large files are never written, so they aren't checked.
+    fn file_stamp(&self) -> Option<(&Path, Option<FileStamp>)> {
+        match &self.file_view {
+            Some(FileView::Hex(hex_view)) => Some((&hex_view.buffer.path, hex_view.buffer.stamp)),
+            Some(FileView::Large(_)) => None,
+            // Directories change whenever a file in them is written
+            None if self.window.get_buffer().directory.is_some() => None,
+            None => {
+                let buffer = self.window.get_buffer();
+                buffer.path.as_deref().map(|path| (path, buffer.stamp))
+            }
+        }
+    }
+
+    fn set_file_stamp(&mut self, stamp: Option<FileStamp>) {
+        match &mut self.file_view {
+            Some(FileView::Hex(hex_view)) => hex_view.buffer.stamp = stamp,
+            Some(FileView::Large(_)) => {}
+            None => self.window.get_buffer_mut().stamp = stamp,
+        }
+    }
+
+    /// Whether another program changed, replaced, created or deleted the current file since it
+    /// was last read or written
+    fn file_changed(&self) -> bool {
+        self.file_stamp()
+            .is_some_and(|(path, stamp)| FileStamp::read(path) != stamp)
+    }
+
+    /// Checks whether another program changed the current file. An unmodified buffer is reloaded
+    /// right away if 'autoread' is set, otherwise the user is asked whether to reload it.
+    fn check_time(&mut self) {
+        if self.prompt.is_some() || !self.file_changed() {
+            return;
+        }
+        let Some((path, _)) = self.file_stamp() else {
+            return;
+        };
+        let path = path.to_path_buf();
+
+        if !path.exists() {
+            // Don't complain again, writing the buffer creates the file anew
+            self.set_file_stamp(None);
+            self.notify(
+                format!(
+                    "The file \"{}\" no longer exists",
+                    util::display_path(&path)
+                ),
+                LogLevel::Error,
+            );
+            return;
+        }
+        if self.options.get_bool("autoread") && self.is_saved() {
+            match self.reload_file() {
+                Ok(()) => self.notify(
+                    format!("\"{}\" reloaded", util::display_path(&path)),
+                    LogLevel::Info,
+                ),
+                Err(message) => self.notify(message, LogLevel::Error),
+            }
+            return;
+        }
+
+        let mut message = format!(
+            "The file \"{}\" has been changed by another program.",
+            util::display_path(&path)
+        );
+        if !self.is_saved() {
+            message.push_str("\nReloading it discards the changes in the buffer.");
+        }
+        self.prompt = Some(Prompt {
+            message,
+            choices: vec![('r', "[R]eload"), ('k', "[K]eep the buffer")],
+            kind: PromptKind::FileChanged,
+        });
+    }
+
+    /// Reads the current file again, keeping the cursor where it was if possible
+    fn reload_file(&mut self) -> CommandResult {
+        let options = EditOptions {
+            binary: matches!(self.file_view, Some(FileView::Hex(_))),
+            readonly: self.window.get_buffer().options.get_bool("readonly"),
+            ..EditOptions::default()
+        };
+        let cursor = self.window.get_cursor();
+        let hex_cursor = match &self.file_view {
+            Some(FileView::Hex(hex_view)) => hex_view.cursor(),
+            _ => 0,
+        };
+
+        self.edit_file(None, true, options)?;
+        match &mut self.file_view {
+            Some(FileView::Hex(hex_view)) => hex_view.set_cursor(hex_cursor),
+            _ => self.window.set_cursor(cursor),
+        }
+        Ok(())
+    }
+
+    /// Reads a file like [`Editor::load_file`] does. The buffer is 'readonly' if asked to or if
+    /// the file can't be written. Its path is made absolute so it still refers to the same file
+    /// after the working directory changed.
+    fn read_file(
+        &self,
+        path: &Path,
+        options: EditOptions,
+    ) -> std::io::Result<(Buffer, Option<FileView>)> {
+        let path = &std::path::absolute(path)?;
+        let (mut buffer, file_view) = self.load_file(path, options)?;
+        if options.readonly || (path.is_file() && !fileio::is_writable(path)) {
+            buffer.options.set(
+                options::find_option("readonly").unwrap(),
+                OptionValue::Bool(true),
+            );
+        }
+        Ok((buffer, file_view))
+    }
+
+    /// Reads a file into a buffer. Binary files, or any file if `++bin` was given, are read into a
+    /// hex view instead and files larger than 'largefile' are only opened for a large file view.
+    /// Both come with an empty buffer for the file's options.
+    fn load_file(
+        &self,
+        path: &Path,
+        options: EditOptions,
+    ) -> std::io::Result<(Buffer, Option<FileView>)> {
+        let encodings = match options.encoding {
+            Some(encoding) => vec![encoding.to_owned()],
+            None => self.options.get_list("fileencodings").to_vec(),
+        };
+        if path.is_dir() {
+            // The listing keeps working when the working directory changes
+            let path = std::fs::canonicalize(path)?;
+            let buffer = Buffer::new_directory(
+                &path,
+                self.options.get_bool("dirhidden"),
+                self.options.get_string("dirsort"),
+            )?;
+            return Ok((buffer, None));
+        }
+        if !path.is_file() {
+            return Ok((Buffer::new_from_file(path, &encodings)?, None));
+        }
+
+        let placeholder = Buffer::new_with_path(Some(path.to_path_buf()));
+        let limit = self.options.get_int("largefile").max(0) as u64 * 1024 * 1024;
+        if !options.binary && std::fs::metadata(path)?.len() > limit {
+            let view = LargeFileView::open(path, self.window_bounds())?;
+            return Ok((placeholder, Some(FileView::Large(view))));
+        }
+
+        let bytes = std::fs::read(path)?;
+        if options.binary || hex::is_binary(&bytes) {
+            let buffer = BinaryBuffer::new(path.to_path_buf(), bytes);
+            let hex_view = HexView::new(buffer, self.window_bounds());
+            return Ok((placeholder, Some(FileView::Hex(hex_view))));
+        }
+        Ok((Buffer::from_bytes(path, &bytes, &encodings)?, None))
+    }
+
+    /// Creates the swap file of the current buffer, or asks what to do if there already is one
+    /// that belongs to another instance or has changes to recover.
+    fn open_swap_file(&mut self) {
+        let buffer = self.window.get_buffer();
+        // Swap files only hold text
+        if !buffer.options.get_bool("swapfile")
+            || buffer.options.get_bool("readonly")
+            || self.file_view.is_some()
+        {
+            return;
+        }
+        let Some(path) = buffer.path.as_deref().and_then(swap::swap_path) else {
+            return;
+        };
+
+        // Swap files of crashed instances without unsaved changes have nothing to recover and
+        // are simply replaced, just like unreadable ones
+        if let Ok(info) = SwapInfo::read(&path) {
+            let running = info.is_owner_running();
+            if running || (info.modified && info.pid != std::process::id()) {
+                self.prompt_swap_file(info, running);
+                return;
+            }
+        }
+
+        self.create_swap_file(path);
+    }
+
+    fn prompt_swap_file(&mut self, info: SwapInfo, running: bool) {
+        let buffer = self.window.get_buffer();
+        let name = buffer
+            .path
+            .as_deref()
+            .map(util::display_path)
+            .unwrap_or_default();
+        let mut message = format!(
+            "Found a swap file for \"{}\": {}\n",
+            name,
+            info.path.display()
+        );
+        if running {
+            message.push_str(&format!(
+                "The file is being edited by another bvim (process {}).\n",
+                info.pid
+            ));
+        } else {
+            message.push_str(&format!(
+                "It was left by process {}, which isn't running anymore.\n",
+                info.pid
+            ));
+        }
+        if info.modified {
+            message.push_str("It contains changes that weren't saved.\n");
+        }
+        if info.lines != buffer.lines {
+            message.push_str("Its contents differ from the file.");
+        }
+
+        let mut choices = vec![('r', "[R]ecover"), ('o', "[O]pen read-only")];
+        // Deleting the swap file of a running instance would lose its protection
+        if !running {
+            choices.push(('d', "[D]elete it"));
+        }
+        choices.push(('q', "[Q]uit"));
+        self.prompt = Some(Prompt {
+            message: message.trim_end().to_owned(),
+            choices,
+            kind: PromptKind::SwapFile(info),
+        });
+    }
+
+    fn answer_prompt(&mut self, kind: PromptKind, answer: char) {
+        match (kind, answer) {
+            (PromptKind::SwapFile(info), 'r') => {
+                let buffer = self.window.get_buffer_mut();
+                let last = buffer.lines.len() - 1;
+                let end = Position::new(buffer.line_length(last), last);
+                buffer.replace_text(Position::new(0, 0), end, &info.lines.join("\n"));
+                self.window.set_cursor(self.window.get_cursor());
+                // The swap file is taken over
+                self.create_swap_file(info.path);
+                self.notify(
+                    "Recovered the swap file's contents. Check them and write them with :w, then \
+                     the swap file is no longer needed.",
+                    LogLevel::Info,
+                );
+            }
+            (PromptKind::SwapFile(_), 'o') => {
+                self.window.get_buffer_mut().options.set(
+                    options::find_option("readonly").unwrap(),
+                    OptionValue::Bool(true),
+                );
+            }
+            (PromptKind::SwapFile(info), 'd') => {
+                if let Err(e) = std::fs::remove_file(&info.path) {
+                    self.notify(
+                        format!("Unable to delete swap file: {}", e),
+                        LogLevel::Error,
+                    );
+                    return;
+                }
+                self.open_swap_file();
+            }
+            (PromptKind::SwapFile(_), _) => self.terminate = true,
+            (PromptKind::FileChanged, 'r') => {
+                if let Err(message) = self.reload_file() {
+                    self.notify(message, LogLevel::Error);
+                }
+            }
+            (PromptKind::DirectoryChanges(operations), 'y') => {
+                match self.apply_directory_changes(&operations) {
+                    Ok(notification) => self.notify(notification.message, notification.level),
+                    Err(message) => self.notify(message, LogLevel::Error),
+                }
+            }
+            (PromptKind::DirectoryChanges(_), _) => {}
+            // The buffer wins, so don't ask again until the file changes once more
+            (PromptKind::FileChanged, _) => {
+                let stamp = self
+                    .file_stamp()
+                    .and_then(|(path, _)| FileStamp::read(path));
+                self.set_file_stamp(stamp);
+            }
+        }
+    }
+
+    fn create_swap_file(&mut self, path: PathBuf) {
+        match SwapFile::create(path, self.window.get_buffer()) {
+            Ok(swap) => self.swap_file = Some(swap),
+            Err(e) => self.notify(
+                format!("Unable to create swap file: {}", e),
+                LogLevel::Error,
+            ),
+        }
+    }
+
+    /// Writes the current buffer to its swap file once the user paused typing after a change
+    fn update_swap_file(&mut self) {
+        let Some(swap) = &mut self.swap_file else {
+            return;
+        };
+        let buffer = self.window.get_buffer();
+        let idle = Duration::from_millis(self.options.get_int("updatetime") as u64);
+        if !swap.is_outdated(buffer) || self.last_key.elapsed() < idle {
+            return;
+        }
+        if let Err(e) = swap.write(buffer) {
+            // Don't retry on every frame
+            self.swap_file = None;
+            self.notify(format!("Unable to write swap file: {}", e), LogLevel::Error);
+        }
+    }
+
+    fn close_swap_file(&mut self) {
+        if let Some(swap) = self.swap_file.take() {
+            let _ = swap.remove();
+        }
+    }
+}
+
+/// How `:edit` reads a file, given as `++enc={encoding}` and `++bin` before the file name
+#[derive(Debug, Default, Clone, Copy)]
+struct EditOptions {
+    /// The encoding to use instead of trying 'fileencodings'
+    encoding: Option<&'static str>,
+    /// Whether to edit the file in the hex view even if it doesn't look binary
+    binary: bool,
+    /// Whether to open the file 'readonly', like `:view` does
+    readonly: bool,
+}
+
+/// Splits the `++` options off the arguments of `:edit`, returning them and the remaining file
+/// name
+fn parse_edit_arguments(arguments: &str) -> std::result::Result<(EditOptions, &str), String> {
+    let mut options = EditOptions::default();
+    let mut rest = arguments;
+    while let Some(argument) = rest.strip_prefix("++") {
+        let (argument, remaining) = argument
+            .split_once(char::is_whitespace)
+            .unwrap_or((argument, ""));
+        match argument.split_once('=') {
+            Some(("enc" | "encoding", name)) => {
+                let name = encoding::canonical_name(name)
+                    .ok_or_else(|| format!("Invalid encoding: {}", name))?;
+                options.encoding = Some(name);
+            }
+            None if argument == "bin" || argument == "binary" => options.binary = true,
+            _ => return Err(format!("Invalid argument: ++{}", argument)),
+        }
+        rest = remaining.trim_start();
+    }
+    Ok((options, rest))
+}
+
+/// Formats a diagnostic as e.g. `error: cannot find value `x` [rustc E0425]`
+fn format_diagnostic(diagnostic: &Diagnostic) -> String {
+    let mut message = format!("{}: {}", diagnostic.severity.name(), diagnostic.message);
+    let origin = [diagnostic.source.as_deref(), diagnostic.code.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if !origin.is_empty() {
+        message.push_str(&format!(" [{}]", origin.join(" ")));
+    }
+    message
+}
+
+/// Combines the errors of commands that keep going after a failure into one message.
+fn join_errors(mut errors: Vec<String>) -> CommandResult {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        n => Err(format!("{} (and {} more errors)", errors[0], n - 1)),
+    }
+}
+
+/// Runs a command with the shell, writing `input` to its stdin, and returns its output. Failures
+/// give the exit code and what the command wrote to stderr.
+fn run_shell(command: &str, input: Option<String>) -> std::result::Result<String, String> {
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Can't run {}: {}", command, e))?;
+    // Writing on another thread keeps a command that writes before reading all its input from
+    // blocking on a full pipe
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Can't run {}: {}", command, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut message = format!("shell returned {}", exit_code(&output.status));
+        if !stderr.trim().is_empty() {
+            message.push('\n');
+            message.push_str(stderr.trim_end());
+        }
+        return Err(message);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n"))
+}
+
+/// The exit code of a program, or the signal that killed it
+#[cfg(unix)]
+fn exit_code(status: &std::process::ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code.to_string(),
+        (None, Some(signal)) => format!("signal {}", signal),
+        (None, None) => "unknown".to_owned(),
+    }
+}
+
+#[cfg(not(unix))]
+fn exit_code(status: &std::process::ExitStatus) -> String {
+    status
+        .code()
+        .map_or_else(|| "unknown".to_owned(), |code| code.to_string())
 }
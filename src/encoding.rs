@@ -0,0 +1,161 @@
+/// The encodings files can be read and written in, as accepted by 'fileencoding'
+pub const ENCODINGS: &[&str] = &["utf-8", "utf-16le", "utf-16be", "latin1", "cp1252"];
+
+/// The characters of the bytes 0x80 to 0x9f in cp1252. Bytes that aren't assigned decode to the
+/// control characters with the same value like in latin1, so decoding never fails.
+const CP1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// Returns the name used for an encoding, accepting some common aliases
+pub fn canonical_name(name: &str) -> Option<&'static str> {
+    let name = name.to_ascii_lowercase().replace('_', "-");
+    let name = match name.as_str() {
+        "utf8" => "utf-8",
+        "utf16le" | "ucs-2le" => "utf-16le",
+        "utf16be" | "utf-16" | "utf16" | "ucs-2" => "utf-16be",
+        "iso-8859-1" | "iso8859-1" | "latin-1" => "latin1",
+        "windows-1252" => "cp1252",
+        name => name,
+    };
+    ENCODINGS.iter().copied().find(|encoding| *encoding == name)
+}
+
+/// Returns the encoding announced by a byte order mark at the start of the bytes and the
+/// length of the mark
+pub fn detect_bom(bytes: &[u8]) -> Option<(&'static str, usize)> {
+    if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+        Some(("utf-8", 3))
+    } else if bytes.starts_with(&[0xff, 0xfe]) {
+        Some(("utf-16le", 2))
+    } else if bytes.starts_with(&[0xfe, 0xff]) {
+        Some(("utf-16be", 2))
+    } else {
+        None
+    }
+}
+
+fn bom(encoding: &str) -> &'static [u8] {
+    match encoding {
+        "utf-8" => &[0xef, 0xbb, 0xbf],
+        "utf-16le" => &[0xff, 0xfe],
+        "utf-16be" => &[0xfe, 0xff],
+        _ => &[],
+    }
+}
+
+/// A file's contents converted to UTF-8
+#[derive(Debug)]
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static str,
+    /// Whether the file started with a byte order mark
+    pub bom: bool,
+}
+
+/// Decodes a file's contents with the first of the given encodings that fits, like
+/// 'fileencodings'. "ucs-bom" stands for the encoding of a byte order mark if there is one.
+/// Unknown encodings are skipped.
+pub fn decode_file<S: AsRef<str>>(bytes: &[u8], encodings: &[S]) -> Option<Decoded> {
+    for name in encodings {
+        let name = name.as_ref();
+        if name == "ucs-bom" {
+            if let Some((encoding, length)) = detect_bom(bytes) {
+                if let Some(text) = decode(&bytes[length..], encoding) {
+                    return Some(Decoded {
+                        text,
+                        encoding,
+                        bom: true,
+                    });
+                }
+            }
+            continue;
+        }
+
+        let Some(encoding) = canonical_name(name) else {
+            continue;
+        };
+        // The mark is still recognized when the encoding is given explicitly
+        let (skipped, bom) = match detect_bom(bytes) {
+            Some((bom_encoding, length)) if bom_encoding == encoding => (length, true),
+            _ => (0, false),
+        };
+        if let Some(text) = decode(&bytes[skipped..], encoding) {
+            return Some(Decoded {
+                text,
+                encoding,
+                bom,
+            });
+        }
+    }
+    None
+}
+
+/// Decodes the bytes, or returns `None` if they aren't valid in the encoding
+pub fn decode(bytes: &[u8], encoding: &str) -> Option<String> {
+    match encoding {
+        "utf-8" => String::from_utf8(bytes.to_vec()).ok(),
+        "utf-16le" | "utf-16be" => {
+            if !bytes.len().is_multiple_of(2) {
+                return None;
+            }
+            let units = bytes.chunks_exact(2).map(|pair| {
+                let pair = [pair[0], pair[1]];
+                if encoding == "utf-16le" {
+                    u16::from_le_bytes(pair)
+                } else {
+                    u16::from_be_bytes(pair)
+                }
+            });
+            char::decode_utf16(units)
+                .collect::<Result<String, _>>()
+                .ok()
+        }
+        "latin1" => Some(bytes.iter().map(|&byte| byte as char).collect()),
+        "cp1252" => Some(
+            bytes
+                .iter()
+                .map(|&byte| match byte {
+                    0x80..=0x9f => CP1252[byte as usize - 0x80],
+                    _ => byte as char,
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Encodes the text for writing it to a file, starting with a byte order mark if `bom` is set.
+/// Fails with a message naming the first character the encoding can't represent.
+pub fn encode(text: &str, encoding: &str, bom: bool) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    if bom {
+        bytes.extend_from_slice(self::bom(encoding));
+    }
+
+    match encoding {
+        "utf-8" => bytes.extend_from_slice(text.as_bytes()),
+        "utf-16le" => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+        "utf-16be" => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+        "latin1" | "cp1252" => {
+            for c in text.chars() {
+                let byte = match (encoding, c) {
+                    (_, '\0'..='\u{7f}' | '\u{a0}'..='\u{ff}') => Some(c as u8),
+                    ("latin1", '\u{80}'..='\u{9f}') => Some(c as u8),
+                    ("cp1252", _) => CP1252
+                        .iter()
+                        .position(|&other| other == c)
+                        .map(|i| 0x80 + i as u8),
+                    _ => None,
+                };
+                match byte {
+                    Some(byte) => bytes.push(byte),
+                    None => return Err(format!("Can't convert '{}' to {}", c, encoding)),
+                }
+            }
+        }
+        _ => return Err(format!("Unknown encoding: {}", encoding)),
+    }
+    Ok(bytes)
+}
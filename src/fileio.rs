@@ -0,0 +1,319 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::options::Options;
+
+/// Writes `content` to the file at `path` according to the `backup`, `writebackup`,
+/// `backupcopy` and `backupext` options.
+///
+/// Unless `backupcopy` says otherwise, the content is written to a temporary file next to the
+/// target which then replaces it, so the original stays intact if anything goes wrong. Symlinks
+/// are followed so the link itself survives, and the original's permissions and, where
+/// possible, ownership are kept.
+pub fn write_file(path: &Path, content: &[u8], options: &Options) -> io::Result<()> {
+    let target = resolve_symlinks(path)?;
+    let metadata = fs::metadata(&target).ok();
+
+    // Keep a copy of the original until the new content is safely written
+    let backup = match &metadata {
+        Some(_) if options.get_bool("backup") || options.get_bool("writebackup") => {
+            let backup = with_suffix(&target, options.get_string("backupext"));
+            // Removing the backup afterwards would delete the file that was just written
+            if backup == target {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the backup file would overwrite the original",
+                ));
+            }
+            fs::copy(&target, &backup)?;
+            Some(backup)
+        }
+        _ => None,
+    };
+
+    let in_place = match (options.get_string("backupcopy"), &metadata) {
+        ("yes", _) => true,
+        ("no", _) | (_, None) => false,
+        // Replacing the file would separate it from its other hard links
+        (_, Some(metadata)) => link_count(metadata) > 1,
+    };
+    if in_place {
+        write_in_place(&target, content)?;
+    } else if let Err(e) = write_replacing(&target, content, metadata.as_ref()) {
+        match e.kind() {
+            // Without the permission to create files next to the target or to keep its owner,
+            // the only way left is to overwrite it
+            io::ErrorKind::PermissionDenied if options.get_string("backupcopy") == "auto" => {
+                write_in_place(&target, content)?
+            }
+            _ => return Err(e),
+        }
+    }
+
+    if let Some(backup) = backup {
+        if !options.get_bool("backup") {
+            fs::remove_file(backup)?;
+        }
+    }
+    Ok(())
+}
+
+/// What a file looked like on disk when it was read or written, to notice when another program
+/// changes it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    size: u64,
+    inode: u64,
+}
+
+impl FileStamp {
+    /// Returns the stamp of the file at `path`, or `None` if there is no such file
+    pub fn read(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            inode: inode(&metadata),
+        })
+    }
+}
+
+/// Whether the file at `path` can be written by this process. Opening it for appending leaves
+/// it untouched and also takes ACLs and read-only file systems into account.
+pub fn is_writable(path: &Path) -> bool {
+    OpenOptions::new().append(true).open(path).is_ok()
+}
+
+/// Returns the 'fileformat' whose line endings the text uses: "dos" if every line ends in CRLF,
+/// "mac" if lines only end in CR and "unix" otherwise. Stray CRs in unix files stay part of the
+/// lines so they are written back unchanged.
+pub fn detect_fileformat(text: &str) -> &'static str {
+    let lf = text.matches('\n').count();
+    let crlf = text.matches("\r\n").count();
+    if lf == 0 && text.contains('\r') {
+        "mac"
+    } else if lf > 0 && lf == crlf {
+        "dos"
+    } else {
+        "unix"
+    }
+}
+
+/// Returns the line ending of a 'fileformat'
+pub fn line_ending(fileformat: &str) -> &'static str {
+    match fileformat {
+        "dos" => "\r\n",
+        "mac" => "\r",
+        _ => "\n",
+    }
+}
+
+/// Returns the file a path refers to after following all symlinks. Unlike
+/// [`fs::canonicalize`], this also works for links pointing to files that don't exist yet.
+fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    // Give up on symlink loops like the OS does
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let link = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(parent) => parent.join(link),
+                    None => link,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "too many levels of symbolic links",
+    ))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn write_in_place(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+/// Writes the content to a temporary file in the same directory and renames it to `path`,
+/// which atomically replaces the old file.
+fn write_replacing(path: &Path, content: &[u8], metadata: Option<&fs::Metadata>) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = directory.join(format!(".{}.bvim-{}.tmp", name, std::process::id()));
+
+    let result = (|| {
+        let mut file = create_with_mode(&temp, metadata)?;
+        // A leftover temporary file keeps its permissions, so set them before writing
+        if let Some(metadata) = metadata {
+            file.set_permissions(metadata.permissions())?;
+            preserve_owner(&file, metadata)?;
+        }
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result;
+    }
+
+    // Make sure the rename itself survives a crash. Not every platform can sync directories.
+    if let Ok(directory) = File::open(directory) {
+        let _ = directory.sync_all();
+    }
+    Ok(())
+}
+
+/// Creates the file with the permissions of the original, so the content is never readable by
+/// more users than the original while it's written. New files get the usual defaults.
+#[cfg(unix)]
+fn create_with_mode(path: &Path, metadata: Option<&fs::Metadata>) -> io::Result<File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mode = metadata.map_or(0o666, |metadata| metadata.permissions().mode() & 0o777);
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_with_mode(path: &Path, _metadata: Option<&fs::Metadata>) -> io::Result<File> {
+    File::create(path)
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::nlink(metadata)
+}
+
+#[cfg(not(unix))]
+fn link_count(_metadata: &fs::Metadata) -> u64 {
+    1
+}
+
+/// Returns the file's inode, which changes when another program replaces the file instead of
+/// writing to it
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> u64 {
+    0
+}
+
+/// Gives the new file the owner and group of the original. Changing the group may be allowed
+/// when changing the owner isn't, which is fine as long as the owner already matches.
+#[cfg(unix)]
+fn preserve_owner(file: &File, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::fs::{fchown, MetadataExt};
+
+    let current = file.metadata()?;
+    if current.uid() == metadata.uid() && current.gid() == metadata.gid() {
+        return Ok(());
+    }
+    if fchown(file, Some(metadata.uid()), Some(metadata.gid())).is_ok() {
+        return Ok(());
+    }
+    let _ = fchown(file, None, Some(metadata.gid()));
+    if file.metadata()?.uid() != metadata.uid() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "unable to keep the file's owner",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn preserve_owner(_file: &File, _metadata: &fs::Metadata) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::{find_option, OptionValue, SetOperation};
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("bvim-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn backupext_cant_be_empty() {
+        let mut options = Options::new_global();
+        let info = find_option("backupext").unwrap();
+        assert!(options
+            .apply(info, &SetOperation::Assign(String::new()))
+            .is_err());
+        assert_eq!(options.get_string("backupext"), "~");
+    }
+
+    #[test]
+    fn refuses_a_backup_that_is_the_file_itself() {
+        let directory = temp_dir("backup");
+        let path = directory.join("file");
+        fs::write(&path, "old").unwrap();
+        let mut options = Options::new_global();
+        options.set(
+            find_option("backupext").unwrap(),
+            OptionValue::String(String::new()),
+        );
+
+        assert!(write_file(&path, b"new", &options).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_the_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = temp_dir("permissions");
+        let path = directory.join("secret");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let metadata = fs::metadata(&path).unwrap();
+        let temp = directory.join("temp");
+        let file = create_with_mode(&temp, Some(&metadata)).unwrap();
+        assert_eq!(file.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+
+        write_file(&path, b"new", &Options::new_global()).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!with_suffix(&path, "~").exists());
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread,
+};
+
+use regex::Regex;
+
+use crate::picker::{PickerItem, PickerSource, Target};
+
+/// How many paths the indexing thread collects before handing them over
+const BATCH_SIZE: usize = 500;
+
+/// The files below a directory for the picker. They're indexed on a background thread and show
+/// up as they're found.
+pub struct Files {
+    root: PathBuf,
+    /// Delivers the paths found by the indexing thread, `None` once it's done
+    receiver: Option<Receiver<Vec<String>>>,
+}
+
+impl Files {
+    /// Starts indexing the files below `root`
+    pub fn new(root: PathBuf) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let thread_root = root.clone();
+        thread::spawn(move || index(&thread_root, &sender));
+        Self {
+            root,
+            receiver: Some(receiver),
+        }
+    }
+}
+
+impl PickerSource for Files {
+    fn name(&self) -> &'static str {
+        "files"
+    }
+
+    fn poll(&mut self) -> Vec<PickerItem> {
+        let Some(receiver) = &self.receiver else {
+            return Vec::new();
+        };
+        let mut items = Vec::new();
+        loop {
+            match receiver.try_recv() {
+                Ok(paths) => items.extend(paths.into_iter().map(|name| {
+                    let path = self.root.join(&name);
+                    PickerItem {
+                        preview: Some((path.clone(), 0)),
+                        ..PickerItem::new(name, Target::File(path))
+                    }
+                })),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.receiver = None;
+                    break;
+                }
+            }
+        }
+        items
+    }
+
+    fn is_complete(&self) -> bool {
+        self.receiver.is_none()
+    }
+
+    fn has_preview(&self) -> bool {
+        true
+    }
+}
+
+/// Sends the paths of the files below `root` to `sender` in batches. Stops early if the finder
+/// was closed.
+fn index(root: &Path, sender: &Sender<Vec<String>>) {
+    let mut batch = Vec::new();
+    let finished = walk(root, |path| {
+        batch.push(path);
+        batch.len() < BATCH_SIZE || sender.send(std::mem::take(&mut batch)).is_ok()
+    });
+    if finished && !batch.is_empty() {
+        let _ = sender.send(batch);
+    }
+}
+
+/// Calls `visit` with the path relative to `root` of each file below it, in order, until it
+/// returns `false`. Hidden files and whatever the `.gitignore` files and `.git/info/exclude`
+/// ignore are left out. Returns whether all files were visited.
+pub fn walk(root: &Path, mut visit: impl FnMut(String) -> bool) -> bool {
+    let mut ignores = Vec::new();
+    if let Some(ignore) = Ignore::read(&root.join(".git/info/exclude"), "") {
+        ignores.push(ignore);
+    }
+    let mut pending = vec![(String::new(), ignores)];
+    while let Some((directory, ignores)) = pending.pop() {
+        let Ok(entries) = fs::read_dir(root.join(&directory)) else {
+            continue;
+        };
+        let mut ignores = ignores;
+        if let Some(ignore) = Ignore::read(&root.join(&directory).join(".gitignore"), &directory) {
+            ignores.push(ignore);
+        }
+        let mut entries = entries
+            .flatten()
+            .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry)))
+            .collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        // Visit subdirectories in order, which means pushing them in reverse
+        let mut subdirectories = Vec::new();
+        for (name, entry) in entries {
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = format!("{}{}", directory, name);
+            // Symlinks to directories aren't followed, which could loop
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let is_dir = file_type.is_dir();
+            if is_ignored(&ignores, &path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                subdirectories.push((path + "/", ignores.clone()));
+            } else if (file_type.is_file() || entry.path().is_file()) && !visit(path) {
+                return false;
+            }
+        }
+        pending.extend(subdirectories.into_iter().rev());
+    }
+    true
+}
+
+/// Whether a path relative to the root is ignored. The last matching pattern decides, and the
+/// patterns of deeper `.gitignore` files come later.
+fn is_ignored(ignores: &[Ignore], path: &str, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for ignore in ignores {
+        let Some(relative) = path.strip_prefix(&ignore.base) else {
+            continue;
+        };
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        for pattern in &ignore.patterns {
+            if pattern.directory && !is_dir {
+                continue;
+            }
+            let text = if pattern.anchored { relative } else { name };
+            if pattern.regex.is_match(text) {
+                ignored = !pattern.negated;
+            }
+        }
+    }
+    ignored
+}
+
+/// The patterns of a `.gitignore` file
+#[derive(Clone)]
+struct Ignore {
+    /// The directory of the file relative to the root, with a trailing slash unless it's the root
+    base: String,
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Clone)]
+struct Pattern {
+    regex: Regex,
+    /// Patterns starting with `!` include what earlier patterns ignored
+    negated: bool,
+    /// Patterns ending with a slash only match directories
+    directory: bool,
+    /// Patterns with a slash before their end match the path relative to the `.gitignore` file,
+    /// the others match names at any depth
+    anchored: bool,
+}
+
+impl Ignore {
+    fn read(path: &Path, base: &str) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        let patterns = contents.lines().filter_map(Pattern::parse).collect();
+        Some(Self {
+            base: base.to_owned(),
+            patterns,
+        })
+    }
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (directory, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        let regex = Regex::new(&format!("^{}$", glob_to_regex(line))).ok()?;
+        Some(Self {
+            regex,
+            negated,
+            directory,
+            anchored,
+        })
+    }
+}
+
+/// Translates a glob of a `.gitignore` file to a regex: `*` and `?` don't match slashes, `**`
+/// matches any number of directories and `[...]` is a character class
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    regex.push_str(&regex::escape(&c.to_string()));
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex
+}
@@ -0,0 +1,122 @@
+/// Bonus for a character matching right after the previous one
+const CONSECUTIVE_BONUS: i64 = 5;
+/// Bonus for matching the first character of the text
+const START_BONUS: i64 = 10;
+/// Bonus for matching the first character of a word, e.g. after `_` or `/` or in camel case
+const WORD_START_BONUS: i64 = 8;
+/// The penalty for skipping characters between two matches grows by one per character up to this
+const MAX_GAP_PENALTY: usize = 5;
+
+/// Matches the characters of `pattern` in order against `text`, with gaps allowed. Case is
+/// ignored unless the pattern contains an uppercase character. Returns `None` if the text doesn't
+/// match and otherwise a score that is higher the better the text matches, preferring matches at
+/// the start, at word boundaries and of consecutive characters. Of all the ways the pattern can
+/// be matched, the one with the best score counts, like in fzf.
+pub fn score(pattern: &str, text: &str) -> Option<i64> {
+    let ignore_case = !pattern.chars().any(char::is_uppercase);
+    let normalize = |c: char| {
+        if ignore_case {
+            c.to_lowercase().next().unwrap_or(c)
+        } else {
+            c
+        }
+    };
+
+    let pattern = pattern.chars().map(normalize).collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    // Shorter texts win if everything else is equal
+    let length_penalty = text.len() as i64;
+    let Some((&first, rest)) = pattern.split_first() else {
+        return Some(-length_penalty);
+    };
+
+    // What matching each character of the text is worth on its own
+    let bonuses = text
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| {
+            let word_start = match i.checked_sub(1).map(|previous| text[previous]) {
+                None => true,
+                Some(previous) => {
+                    !previous.is_alphanumeric() || (previous.is_lowercase() && c.is_uppercase())
+                }
+            };
+            1 + if i == 0 { START_BONUS } else { 0 } + if word_start { WORD_START_BONUS } else { 0 }
+        })
+        .collect::<Vec<_>>();
+
+    // The best score of matching the pattern up to the current character with that character at
+    // each position of the text
+    let mut best = text
+        .iter()
+        .zip(&bonuses)
+        .map(|(&c, bonus)| (normalize(c) == first).then_some(*bonus))
+        .collect::<Vec<_>>();
+    for &wanted in rest {
+        let mut next = vec![None; text.len()];
+        // The best score of the previous character matching so far back that the gap penalty
+        // reached its maximum
+        let mut distant = None;
+        for i in 0..text.len() {
+            if i > MAX_GAP_PENALTY {
+                distant = distant.max(best[i - MAX_GAP_PENALTY - 1]);
+            }
+            if normalize(text[i]) != wanted {
+                continue;
+            }
+            let mut previous = distant.map(|score| score - MAX_GAP_PENALTY as i64);
+            for gap in 0..MAX_GAP_PENALTY.min(i) {
+                let Some(score) = best[i - 1 - gap] else {
+                    continue;
+                };
+                let score = match gap {
+                    0 => score + CONSECUTIVE_BONUS,
+                    gap => score - gap as i64,
+                };
+                previous = previous.max(Some(score));
+            }
+            next[i] = previous.map(|score| score + bonuses[i]);
+        }
+        best = next;
+    }
+
+    let score = best.into_iter().flatten().max()?;
+    Some(score * 100 - length_penalty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_in_order_with_gaps() {
+        assert!(score("abc", "a_b_c").is_some());
+        assert!(score("abc", "acb").is_none());
+        assert!(score("abcd", "abc").is_none());
+        assert_eq!(score("", "abc"), Some(-3));
+    }
+
+    #[test]
+    fn is_smart_case() {
+        assert!(score("main", "Main.rs").is_some());
+        assert!(score("Main", "main.rs").is_none());
+        assert!(score("Main", "src/Main.rs").is_some());
+    }
+
+    #[test]
+    fn finds_the_best_alignment() {
+        // Matching the first `m` would leave the rest scattered, while the `m` after the slash
+        // starts a word and is followed by the rest of the pattern
+        assert_eq!(score("main", "xmx/main"), score("main", "xxx/main"));
+        assert!(score("main", "xmx/main") > score("main", "xmx/mxaxixn"));
+    }
+
+    #[test]
+    fn prefers_starts_boundaries_and_runs() {
+        assert!(score("fo", "foo") > score("fo", "xfoo"));
+        assert!(score("fb", "foo_bar") > score("fb", "foobar"));
+        assert!(score("fb", "fooBar") > score("fb", "foobar"));
+        assert!(score("foo", "xfoo") > score("foo", "xfxoxo"));
+        assert!(score("foo", "foo") > score("foo", "foox"));
+    }
+}
@@ -0,0 +1,330 @@
+use std::{io, path::PathBuf};
+
+use blessings::WindowBounds;
+
+use crate::{
+    editor::{LogLevel, Notification},
+    encoding,
+    fileio::{self, FileStamp},
+    highlight::Highlights,
+    options::Options,
+    screen::Screen,
+    util,
+};
+
+/// How many bytes each row of the hex view shows
+const BYTES_PER_ROW: usize = 16;
+
+/// How many bytes at the start of a file are checked when detecting binary files
+const DETECTION_LIMIT: usize = 8000;
+
+/// Whether a file's contents should be edited as bytes: they contain a NUL byte near the start
+/// and don't start with a byte order mark, since UTF-16 text is full of NUL bytes.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    encoding::detect_bom(bytes).is_none() && bytes[..bytes.len().min(DETECTION_LIMIT)].contains(&0)
+}
+
+/// Parses a search pattern of hex digits like `de ad be ef`. Whitespace is ignored.
+pub fn parse_pattern(pattern: &str) -> Result<Vec<u8>, String> {
+    let digits = pattern
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect::<Option<Vec<_>>>();
+    match digits {
+        Some(digits) if !digits.is_empty() && digits.len() % 2 == 0 => Ok(digits
+            .chunks_exact(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect()),
+        _ => Err(format!("Invalid hex pattern: {}", pattern)),
+    }
+}
+
+/// A file edited as raw bytes, which are written back exactly as they are
+#[derive(Debug)]
+pub struct BinaryBuffer {
+    pub bytes: Vec<u8>,
+    pub path: PathBuf,
+    pub changed: bool,
+    /// The file as it was when it was read or written
+    pub stamp: Option<FileStamp>,
+}
+
+impl BinaryBuffer {
+    pub fn new(path: PathBuf, bytes: Vec<u8>) -> Self {
+        Self {
+            stamp: FileStamp::read(&path),
+            bytes,
+            path,
+            changed: false,
+        }
+    }
+
+    pub fn is_saved(&self) -> bool {
+        !self.changed
+    }
+
+    pub fn save(&mut self, global_options: &Options) -> io::Result<Notification> {
+        fileio::write_file(&self.path, &self.bytes, global_options)?;
+        self.changed = false;
+        self.stamp = FileStamp::read(&self.path);
+        Ok(Notification::new(
+            format!(
+                "\"{}\" {}B written",
+                util::display_path(&self.path),
+                self.bytes.len()
+            ),
+            LogLevel::Info,
+        ))
+    }
+}
+
+/// The column of the hex view the cursor is in, which decides how typed characters are
+/// interpreted
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Column {
+    Hex,
+    Ascii,
+}
+
+/// Shows a [`BinaryBuffer`] like `xxd` does, with the offset, the bytes in hex and their ASCII
+/// characters in each row, and edits it by overwriting bytes.
+#[derive(Debug)]
+pub struct HexView {
+    pub buffer: BinaryBuffer,
+    /// The offset of the byte under the cursor. In insert mode, it may be one past the last byte
+    /// to append to the file.
+    cursor: usize,
+    /// Whether the cursor is on the second digit of the byte in the hex column
+    low_nibble: bool,
+    column: Column,
+    /// Whether typed characters overwrite bytes, which lets the cursor move past the last byte
+    insert: bool,
+    /// The first row that is shown
+    scroll: usize,
+    bounds: WindowBounds,
+}
+
+impl HexView {
+    pub fn new(buffer: BinaryBuffer, bounds: WindowBounds) -> Self {
+        Self {
+            buffer,
+            cursor: 0,
+            low_nibble: false,
+            column: Column::Hex,
+            insert: false,
+            scroll: 0,
+            bounds,
+        }
+    }
+
+    pub fn set_bounds(&mut self, bounds: WindowBounds) {
+        self.bounds = bounds;
+        self.scroll_to_cursor();
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn set_insert(&mut self, insert: bool) {
+        self.insert = insert;
+        self.low_nibble = false;
+        self.set_cursor(self.cursor);
+    }
+
+    /// Moves the cursor to the given offset, clamping it to the file, and scrolls to it
+    pub fn set_cursor(&mut self, offset: usize) {
+        let last = if self.insert {
+            self.buffer.bytes.len()
+        } else {
+            self.buffer.bytes.len().saturating_sub(1)
+        };
+        if offset != self.cursor {
+            self.low_nibble = false;
+        }
+        self.cursor = offset.min(last);
+        self.scroll_to_cursor();
+    }
+
+    fn scroll_to_cursor(&mut self) {
+        let row = self.cursor / BYTES_PER_ROW;
+        let height = (self.bounds.height as usize).max(1);
+        if row < self.scroll {
+            self.scroll = row;
+        } else if row >= self.scroll + height {
+            self.scroll = row + 1 - height;
+        }
+    }
+
+    pub fn move_up(&mut self) {
+        if self.cursor >= BYTES_PER_ROW {
+            self.set_cursor(self.cursor - BYTES_PER_ROW);
+        }
+    }
+
+    pub fn move_down(&mut self) {
+        // Stay in the last row instead of jumping to the last byte of a shorter one
+        let last_row = self.buffer.bytes.len().saturating_sub(1) / BYTES_PER_ROW;
+        if self.cursor / BYTES_PER_ROW < last_row {
+            self.set_cursor(self.cursor + BYTES_PER_ROW);
+        }
+    }
+
+    pub fn move_left(&mut self) {
+        if self.low_nibble {
+            self.low_nibble = false;
+        } else if self.cursor > 0 {
+            self.set_cursor(self.cursor - 1);
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        self.set_cursor(self.cursor + 1);
+    }
+
+    pub fn move_to_start_of_row(&mut self) {
+        self.set_cursor(self.cursor - self.cursor % BYTES_PER_ROW);
+    }
+
+    pub fn move_to_end_of_row(&mut self) {
+        self.set_cursor(self.cursor - self.cursor % BYTES_PER_ROW + BYTES_PER_ROW - 1);
+    }
+
+    pub fn switch_column(&mut self) {
+        self.column = match self.column {
+            Column::Hex => Column::Ascii,
+            Column::Ascii => Column::Hex,
+        };
+        self.low_nibble = false;
+    }
+
+    /// Overwrites the byte under the cursor with a typed character: a hex digit sets one nibble
+    /// in the hex column and an ASCII character the whole byte in the ASCII column. Typing at the
+    /// end of the file appends a byte.
+    pub fn overwrite(&mut self, c: char) -> Result<(), String> {
+        let value = match self.column {
+            Column::Hex => c
+                .to_digit(16)
+                .ok_or_else(|| format!("Not a hex digit: {}", c))? as u8,
+            Column::Ascii if c.is_ascii() => c as u8,
+            Column::Ascii => return Err(format!("Not an ASCII character: {}", c)),
+        };
+
+        if self.cursor == self.buffer.bytes.len() {
+            self.buffer.bytes.push(0);
+        }
+        let byte = &mut self.buffer.bytes[self.cursor];
+        match (self.column, self.low_nibble) {
+            (Column::Hex, false) => *byte = value << 4 | *byte & 0x0f,
+            (Column::Hex, true) => *byte = *byte & 0xf0 | value,
+            (Column::Ascii, _) => *byte = value,
+        }
+        self.buffer.changed = true;
+
+        if self.column == Column::Hex && !self.low_nibble {
+            self.low_nibble = true;
+        } else {
+            self.set_cursor(self.cursor + 1);
+        }
+        Ok(())
+    }
+
+    /// Moves the cursor to the next occurrence of the bytes after or before it, wrapping around
+    /// at the end of the file
+    pub fn search(&mut self, needle: &[u8], forward: bool) -> Result<(), String> {
+        let length = self.buffer.bytes.len();
+        let found = (1..=length)
+            .map(|distance| {
+                if forward {
+                    (self.cursor + distance) % length
+                } else {
+                    (self.cursor + length * 2 - distance) % length
+                }
+            })
+            .find(|&offset| self.buffer.bytes[offset..].starts_with(needle))
+            .ok_or_else(|| "Pattern not found".to_owned())?;
+        self.set_cursor(found);
+        Ok(())
+    }
+
+    pub fn render(&self, screen: &mut Screen, highlights: &Highlights) {
+        screen.begin_window(0, 0, self.bounds.width, self.bounds.height);
+
+        highlights.apply(screen, "Normal");
+        let blank = " ".repeat(self.bounds.width as usize);
+        for row in 0..self.bounds.height {
+            screen.print_at(0, row, &blank);
+        }
+
+        let bytes = &self.buffer.bytes;
+        // The cursor may be in a row of its own when appending
+        let rows = bytes
+            .len()
+            .div_ceil(BYTES_PER_ROW)
+            .max(self.cursor / BYTES_PER_ROW + 1);
+        let mut cursor = (0, 0);
+        for (i, row) in (self.scroll..rows)
+            .take(self.bounds.height as usize)
+            .enumerate()
+        {
+            let y = i as u16;
+            let start = row * BYTES_PER_ROW;
+            highlights.apply(screen, "LineNr");
+            screen.print_at(0, y, format!("{:08x}:", start));
+
+            for column in 0..BYTES_PER_ROW {
+                let offset = start + column;
+                let hex_x = hex_column_x(column);
+                let ascii_x = ascii_column_x(column);
+                if offset == self.cursor {
+                    cursor = match self.column {
+                        Column::Hex => (hex_x + self.low_nibble as u16, y),
+                        Column::Ascii => (ascii_x, y),
+                    };
+                }
+                let Some(&byte) = bytes.get(offset) else {
+                    continue;
+                };
+
+                // Mark the cursor's byte in the column the cursor isn't in
+                let highlighted = offset == self.cursor;
+                highlights.apply(
+                    screen,
+                    if highlighted && self.column == Column::Ascii {
+                        "Visual"
+                    } else {
+                        "Normal"
+                    },
+                );
+                screen.print_at(hex_x, y, format!("{:02x}", byte));
+
+                let (group, c) = match byte {
+                    0x20..=0x7e => ("Normal", byte as char),
+                    _ => ("NonText", '.'),
+                };
+                let group = if highlighted && self.column == Column::Hex {
+                    "Visual"
+                } else {
+                    group
+                };
+                highlights.apply(screen, group);
+                screen.print_at(ascii_x, y, c.to_string());
+            }
+        }
+
+        screen.clear_colors();
+        screen.move_to(cursor.0, cursor.1);
+        screen.end_window();
+    }
+}
+
+/// Returns where the hex digits of the byte in the given column of a row start. The bytes are
+/// grouped in halves like in `hexdump -C`.
+fn hex_column_x(column: usize) -> u16 {
+    (10 + column * 3 + column / 8) as u16
+}
+
+fn ascii_column_x(column: usize) -> u16 {
+    hex_column_x(BYTES_PER_ROW) + column as u16
+}
@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+
+use crossterm::style::Color;
+
+use crate::screen::{Attributes, Screen};
+
+const COLOR_NAMES: &[(&str, Color)] = &[
+    ("black", Color::Black),
+    ("darkgrey", Color::DarkGrey),
+    ("darkgray", Color::DarkGrey),
+    ("red", Color::Red),
+    ("darkred", Color::DarkRed),
+    ("green", Color::Green),
+    ("darkgreen", Color::DarkGreen),
+    ("yellow", Color::Yellow),
+    ("darkyellow", Color::DarkYellow),
+    ("blue", Color::Blue),
+    ("darkblue", Color::DarkBlue),
+    ("magenta", Color::Magenta),
+    ("darkmagenta", Color::DarkMagenta),
+    ("cyan", Color::Cyan),
+    ("darkcyan", Color::DarkCyan),
+    ("white", Color::White),
+    ("grey", Color::Grey),
+    ("gray", Color::Grey),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub attributes: Attributes,
+}
+
+impl Style {
+    const fn new(fg: Option<Color>, bg: Option<Color>) -> Self {
+        Self {
+            fg,
+            bg,
+            attributes: Attributes::NONE,
+        }
+    }
+
+    const fn bold(mut self) -> Self {
+        self.attributes.bold = true;
+        self
+    }
+
+    const fn italic(mut self) -> Self {
+        self.attributes.italic = true;
+        self
+    }
+
+    const fn underline(mut self) -> Self {
+        self.attributes.underline = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Group {
+    Style(Style),
+    Link(String),
+}
+
+const DEFAULT_GROUPS: &[(&str, Style)] = &[
+    ("Normal", Style::new(None, None)),
+    ("StatusLine", Style::new(None, None)),
+    (
+        "ModeNormal",
+        Style::new(Some(Color::Black), Some(Color::Blue)).bold(),
+    ),
+    (
+        "ModeInsert",
+        Style::new(Some(Color::Black), Some(Color::Magenta)).bold(),
+    ),
+    (
+        "ModeCommand",
+        Style::new(Some(Color::Black), Some(Color::Green)).bold(),
+    ),
+    ("LineNr", Style::new(Some(Color::DarkYellow), None)),
+    (
+        "CursorLineNr",
+        Style::new(Some(Color::DarkYellow), None).bold(),
+    ),
+    ("ErrorMsg", Style::new(Some(Color::Red), None)),
+    ("MoreMsg", Style::new(Some(Color::Green), None)),
+    ("DebugMsg", Style::new(Some(Color::Magenta), None)),
+    (
+        "Search",
+        Style::new(Some(Color::Black), Some(Color::Yellow)),
+    ),
+    ("Visual", Style::new(None, Some(Color::DarkGrey))),
+    ("NonText", Style::new(Some(Color::Blue), None)),
+    (
+        "WildMenu",
+        Style::new(Some(Color::Black), Some(Color::Yellow)),
+    ),
+    // Groups used by syntax highlighting
+    ("Comment", Style::new(Some(Color::DarkCyan), None)),
+    ("Constant", Style::new(Some(Color::Magenta), None)),
+    ("Identifier", Style::new(Some(Color::Cyan), None)),
+    ("Statement", Style::new(Some(Color::Yellow), None)),
+    ("PreProc", Style::new(Some(Color::Blue), None)),
+    ("Type", Style::new(Some(Color::Green), None)),
+    ("Special", Style::new(Some(Color::Red), None)),
+    ("Title", Style::new(Some(Color::Magenta), None).bold()),
+    (
+        "Underlined",
+        Style::new(Some(Color::Blue), None).underline(),
+    ),
+    ("Bold", Style::new(None, None).bold()),
+    ("Italic", Style::new(None, None).italic()),
+    // Completion menu
+    (
+        "Pmenu",
+        Style::new(Some(Color::White), Some(Color::DarkGrey)),
+    ),
+    (
+        "PmenuSel",
+        Style::new(Some(Color::Black), Some(Color::Grey)),
+    ),
+    (
+        "PmenuKind",
+        Style::new(Some(Color::Cyan), Some(Color::DarkGrey)),
+    ),
+    (
+        "PmenuKindSel",
+        Style::new(Some(Color::DarkBlue), Some(Color::Grey)),
+    ),
+    // Diagnostics
+    ("DiagnosticError", Style::new(Some(Color::Red), None)),
+    ("DiagnosticWarn", Style::new(Some(Color::Yellow), None)),
+    ("DiagnosticInfo", Style::new(Some(Color::Blue), None)),
+    ("DiagnosticHint", Style::new(Some(Color::Cyan), None)),
+    (
+        "DiagnosticUnderlineError",
+        Style::new(Some(Color::Red), None).underline(),
+    ),
+    (
+        "DiagnosticUnderlineWarn",
+        Style::new(Some(Color::Yellow), None).underline(),
+    ),
+    (
+        "DiagnosticUnderlineInfo",
+        Style::new(Some(Color::Blue), None).underline(),
+    ),
+    (
+        "DiagnosticUnderlineHint",
+        Style::new(Some(Color::Cyan), None).underline(),
+    ),
+];
+
+/// Groups that link to a more general group by default, so color schemes only have to style the
+/// general ones
+const DEFAULT_LINKS: &[(&str, &str)] = &[
+    ("String", "Constant"),
+    ("Character", "Constant"),
+    ("Number", "Constant"),
+    ("Boolean", "Constant"),
+    ("Function", "Identifier"),
+    ("Keyword", "Statement"),
+    ("Label", "Statement"),
+    ("Operator", "Statement"),
+    ("Macro", "PreProc"),
+    ("Directory", "PreProc"),
+    ("SpecialChar", "Special"),
+    ("DiagnosticSignError", "DiagnosticError"),
+    ("DiagnosticSignWarn", "DiagnosticWarn"),
+    ("DiagnosticSignInfo", "DiagnosticInfo"),
+    ("DiagnosticSignHint", "DiagnosticHint"),
+    ("DiagnosticVirtualTextError", "DiagnosticError"),
+    ("DiagnosticVirtualTextWarn", "DiagnosticWarn"),
+    ("DiagnosticVirtualTextInfo", "DiagnosticInfo"),
+    ("DiagnosticVirtualTextHint", "DiagnosticHint"),
+    ("PmenuDoc", "Pmenu"),
+];
+
+/// The highlight groups used for rendering, e.g. `StatusLine` or `ErrorMsg`.
+///
+/// Group names are case-insensitive like in vim. A group either has its own style or links to
+/// another group.
+#[derive(Debug, Clone)]
+pub struct Highlights {
+    groups: HashMap<String, (String, Group)>,
+    /// Whether the terminal supports 24-bit colors, otherwise RGB colors are approximated with
+    /// the 256 color palette
+    truecolor: bool,
+}
+
+impl Highlights {
+    pub fn new(truecolor: bool) -> Self {
+        let mut highlights = Self {
+            groups: HashMap::new(),
+            truecolor,
+        };
+        highlights.reset();
+        highlights
+    }
+
+    /// Restores the default highlighting, removing all groups that aren't built in.
+    pub fn reset(&mut self) {
+        self.groups = DEFAULT_GROUPS
+            .iter()
+            .map(|(name, style)| {
+                (
+                    name.to_lowercase(),
+                    (name.to_string(), Group::Style(style.clone())),
+                )
+            })
+            .collect();
+        for (name, target) in DEFAULT_LINKS {
+            self.groups.insert(
+                name.to_lowercase(),
+                (name.to_string(), Group::Link(target.to_string())),
+            );
+        }
+    }
+
+    pub fn set_truecolor(&mut self, truecolor: bool) {
+        self.truecolor = truecolor;
+    }
+
+    /// Returns the style of the given group, following links and falling back to the colors of
+    /// `Normal` for colors that aren't set.
+    pub fn get(&self, name: &str) -> Style {
+        let mut style = self.resolve(name).cloned().unwrap_or_default();
+        if let Some(normal) = self.resolve("Normal") {
+            style.fg = style.fg.or(normal.fg);
+            style.bg = style.bg.or(normal.bg);
+        }
+        if !self.truecolor {
+            style.fg = style.fg.map(to_256_colors);
+            style.bg = style.bg.map(to_256_colors);
+        }
+        style
+    }
+
+    fn resolve(&self, name: &str) -> Option<&Style> {
+        let mut name = name.to_lowercase();
+        // Limit the number of links followed to not loop forever on cyclic links
+        for _ in 0..100 {
+            match &self.groups.get(&name)?.1 {
+                Group::Style(style) => return Some(style),
+                Group::Link(target) => name = target.to_lowercase(),
+            }
+        }
+        None
+    }
+
+    /// Sets the screen's colors and text attributes to those of the given group.
+    pub fn apply(&self, screen: &mut Screen, name: &str) {
+        let style = self.get(name);
+        screen.set_colors(
+            style.fg.unwrap_or(Color::Reset),
+            style.bg.unwrap_or(Color::Reset),
+        );
+        screen.set_attributes(style.attributes);
+    }
+
+    /// Executes the arguments of `:highlight`. Returns the text to show for listing commands.
+    pub fn command(&mut self, arguments: &str) -> Result<Option<String>, String> {
+        let arguments = arguments.split_whitespace().collect::<Vec<_>>();
+        match arguments.as_slice() {
+            [] => Ok(Some(self.list())),
+            ["clear"] => {
+                self.reset();
+                Ok(None)
+            }
+            ["clear", name] => {
+                self.set_group(name, Group::Style(Style::default()));
+                Ok(None)
+            }
+            ["link", from, to] => {
+                self.set_group(from, Group::Link(to.to_string()));
+                Ok(None)
+            }
+            [name] => match self.groups.get(&name.to_lowercase()) {
+                Some((name, group)) => Ok(Some(format_group(name, group))),
+                None => Err(format!("highlight group not found: {}", name)),
+            },
+            [name, attributes @ ..] => {
+                // Setting attributes of a linked group removes the link like in vim
+                let mut style = match self.groups.get(&name.to_lowercase()) {
+                    Some((_, Group::Style(style))) => style.clone(),
+                    _ => Style::default(),
+                };
+                for attribute in attributes {
+                    set_attribute(&mut style, attribute)?;
+                }
+                self.set_group(name, Group::Style(style));
+                Ok(None)
+            }
+        }
+    }
+
+    fn set_group(&mut self, name: &str, group: Group) {
+        let key = name.to_lowercase();
+        // Keep the spelling of the name a group was created with
+        let name = match self.groups.get(&key) {
+            Some((name, _)) => name.clone(),
+            None => name.to_owned(),
+        };
+        self.groups.insert(key, (name, group));
+    }
+
+    fn list(&self) -> String {
+        let mut groups = self.groups.values().collect::<Vec<_>>();
+        groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+        groups
+            .into_iter()
+            .map(|(name, group)| format_group(name, group))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn format_group(name: &str, group: &Group) -> String {
+    match group {
+        Group::Link(target) => format!("{:<16} links to {}", name, target),
+        Group::Style(style) => format!(
+            "{:<16} fg={} bg={} attr={}",
+            name,
+            format_color(style.fg),
+            format_color(style.bg),
+            format_attributes(style.attributes)
+        ),
+    }
+}
+
+fn format_attributes(attributes: Attributes) -> String {
+    let names = attributes.names();
+    if names.is_empty() {
+        "NONE".to_owned()
+    } else {
+        names.join(",")
+    }
+}
+
+/// Parses a single `key=value` argument of `:highlight`. The keys of vim's gui and cterm variants
+/// are accepted as aliases.
+fn set_attribute(style: &mut Style, attribute: &str) -> Result<(), String> {
+    let Some((key, value)) = attribute.split_once('=') else {
+        return Err(format!("missing equal sign: {}", attribute));
+    };
+
+    match key.to_lowercase().as_str() {
+        "fg" | "guifg" | "ctermfg" => style.fg = parse_color(value)?,
+        "bg" | "guibg" | "ctermbg" => style.bg = parse_color(value)?,
+        "attr" | "gui" | "cterm" => {
+            let attributes = &mut style.attributes;
+            *attributes = Attributes::NONE;
+            for name in value.split(',') {
+                match name.to_lowercase().as_str() {
+                    // Combining with the highlighting below doesn't happen anyway
+                    "none" | "nocombine" => {}
+                    "bold" => attributes.bold = true,
+                    "italic" => attributes.italic = true,
+                    // The terminal's plain underline stands in for the other kinds
+                    "underline" | "undercurl" | "underdouble" | "underdotted" | "underdashed" => {
+                        attributes.underline = true
+                    }
+                    "reverse" | "inverse" | "standout" => attributes.reverse = true,
+                    "strikethrough" => attributes.strikethrough = true,
+                    _ => return Err(format!("Illegal value: {}", attribute)),
+                }
+            }
+        }
+        _ => return Err(format!("Illegal argument: {}", attribute)),
+    }
+
+    Ok(())
+}
+
+/// Parses a color given as name, `#rrggbb` or number of the 256 color palette. `NONE` stands for
+/// the terminal's default color.
+pub fn parse_color(value: &str) -> Result<Option<Color>, String> {
+    let invalid = || format!("Cannot allocate color {}", value);
+
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        return Ok(Some(Color::Rgb {
+            r: component(0),
+            g: component(2),
+            b: component(4),
+        }));
+    }
+    if let Ok(index) = value.parse::<u8>() {
+        return Ok(Some(Color::AnsiValue(index)));
+    }
+
+    COLOR_NAMES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value))
+        .map(|(_, color)| Some(*color))
+        .ok_or_else(invalid)
+}
+
+fn format_color(color: Option<Color>) -> String {
+    match color {
+        None => "NONE".to_owned(),
+        Some(Color::Rgb { r, g, b }) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        Some(Color::AnsiValue(index)) => index.to_string(),
+        Some(color) => match COLOR_NAMES.iter().find(|(_, named)| *named == color) {
+            Some((name, _)) => name.to_string(),
+            None => format!("{:?}", color),
+        },
+    }
+}
+
+/// Approximates RGB colors with the closest color of the 256 color palette's color cube or
+/// grayscale ramp.
+fn to_256_colors(color: Color) -> Color {
+    let Color::Rgb { r, g, b } = color else {
+        return color;
+    };
+
+    // The color cube's levels are 0, 95, 135, 175, 215 and 255
+    let cube_index = |value: u8| -> u8 {
+        if value < 48 {
+            0
+        } else if value < 115 {
+            1
+        } else {
+            (value - 35) / 40
+        }
+    };
+    let cube_value = |index: u8| -> u8 {
+        if index == 0 {
+            0
+        } else {
+            55 + index * 40
+        }
+    };
+    let (ri, gi, bi) = (cube_index(r), cube_index(g), cube_index(b));
+    let cube = (cube_value(ri), cube_value(gi), cube_value(bi));
+
+    // The grayscale ramp goes from 8 to 238 in steps of 10
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_index = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray_value = 8 + gray_index * 10;
+
+    let distance = |(cr, cg, cb): (u8, u8, u8)| -> u32 {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+        d(cr, r) + d(cg, g) + d(cb, b)
+    };
+
+    if distance((gray_value, gray_value, gray_value)) < distance(cube) {
+        Color::AnsiValue(232 + gray_index)
+    } else {
+        Color::AnsiValue(16 + 36 * ri + 6 * gi + bi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_attributes() {
+        let mut highlights = Highlights::new(true);
+        highlights
+            .command("Todo fg=red attr=bold,undercurl")
+            .unwrap();
+        let style = highlights.get("todo");
+        assert_eq!(style.fg, Some(Color::Red));
+        assert_eq!(style.attributes.names(), ["bold", "underline"]);
+        assert_eq!(
+            highlights.command("Todo").unwrap().unwrap(),
+            "Todo             fg=red bg=NONE attr=bold,underline"
+        );
+
+        // Attributes replace the previous ones like in vim
+        highlights.command("Todo gui=reverse").unwrap();
+        assert_eq!(highlights.get("Todo").attributes.names(), ["reverse"]);
+        highlights.command("Todo cterm=NONE").unwrap();
+        assert!(highlights.get("Todo").attributes.is_empty());
+
+        assert!(highlights.command("Todo attr=blink").is_err());
+    }
+
+    #[test]
+    fn attributes_are_not_inherited_from_normal() {
+        let mut highlights = Highlights::new(true);
+        highlights.command("Normal attr=italic").unwrap();
+        assert!(highlights.get("Comment").attributes.is_empty());
+        assert!(highlights.get("ModeNormal").attributes.bold);
+        // Links share the attributes of their target
+        assert!(
+            highlights
+                .get("DiagnosticUnderlineError")
+                .attributes
+                .underline
+        );
+        highlights
+            .command("link DiagnosticUnderlineError Bold")
+            .unwrap();
+        assert!(highlights.get("DiagnosticUnderlineError").attributes.bold);
+    }
+}
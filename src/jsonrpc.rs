@@ -0,0 +1,142 @@
+use std::io::{self, BufRead, Write};
+
+use serde_json::{json, Value};
+
+/// A JSON-RPC 2.0 message as used by the language server protocol
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Request {
+        id: Value,
+        method: String,
+        params: Value,
+    },
+    Response {
+        id: Value,
+        result: Result<Value, ResponseError>,
+    },
+    Notification {
+        method: String,
+        params: Value,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseError {
+    pub code: i64,
+    pub message: String,
+}
+
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+impl Message {
+    pub fn from_value(value: Value) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid JSON-RPC message: {}", value),
+            )
+        };
+        let object = value.as_object().ok_or_else(invalid)?;
+        let params = object.get("params").cloned().unwrap_or(Value::Null);
+
+        match (object.get("id"), object.get("method")) {
+            (Some(id), Some(Value::String(method))) => Ok(Message::Request {
+                id: id.clone(),
+                method: method.clone(),
+                params,
+            }),
+            (None, Some(Value::String(method))) => Ok(Message::Notification {
+                method: method.clone(),
+                params,
+            }),
+            (Some(id), None) => {
+                let result = match object.get("error") {
+                    Some(error) => Err(ResponseError {
+                        code: error["code"].as_i64().unwrap_or(0),
+                        message: error["message"].as_str().unwrap_or_default().to_owned(),
+                    }),
+                    None => Ok(object.get("result").cloned().unwrap_or(Value::Null)),
+                };
+                Ok(Message::Response {
+                    id: id.clone(),
+                    result,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            Message::Request { id, method, params } => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "params": params,
+            }),
+            Message::Response {
+                id,
+                result: Ok(result),
+            } => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": result,
+            }),
+            Message::Response {
+                id,
+                result: Err(error),
+            } => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": error.code, "message": error.message },
+            }),
+            Message::Notification { method, params } => json!({
+                "jsonrpc": "2.0",
+                "method": method,
+                "params": params,
+            }),
+        }
+    }
+}
+
+/// Reads a single message framed by a `Content-Length` header. Returns `None` at the end of the
+/// stream.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Message>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        // Other headers like Content-Type are ignored
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut content = vec![0; content_length];
+    reader.read_exact(&mut content)?;
+
+    let value = serde_json::from_slice(&content)?;
+    Message::from_value(value).map(Some)
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Message) -> io::Result<()> {
+    let content = message.to_value().to_string();
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()
+}
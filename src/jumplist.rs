@@ -0,0 +1,106 @@
+use std::path::PathBuf;
+
+use crate::util::Position;
+
+/// How many positions the jump list remembers, like in vim
+const MAX_JUMPS: usize = 100;
+
+/// A position that was jumped away from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jump {
+    /// The file, or `None` for a buffer without a name
+    pub path: Option<PathBuf>,
+    pub position: Position<usize>,
+}
+
+/// The positions before jumps like searches or `:{line}`, which `Ctrl-O` and `Ctrl-I` go back
+/// and forth between. Like in vim, a line is only in the list once.
+#[derive(Debug, Default)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    /// The index of the entry that was jumped to with `Ctrl-O` or `Ctrl-I`, or the number of
+    /// entries if the cursor is at none of them
+    current: usize,
+}
+
+impl JumpList {
+    /// The entries, oldest first
+    pub fn jumps(&self) -> &[Jump] {
+        &self.jumps
+    }
+
+    /// Remembers the position before a jump
+    pub fn push(&mut self, jump: Jump) {
+        self.jumps
+            .retain(|old| old.path != jump.path || old.position.y != jump.position.y);
+        self.jumps.push(jump);
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.current = self.jumps.len();
+    }
+
+    /// Returns the entry before the current one for `Ctrl-O`. Going back from the newest entry
+    /// remembers `from`, the cursor position, so `Ctrl-I` can return to it.
+    pub fn back(&mut self, from: Jump) -> Option<&Jump> {
+        if self.current == self.jumps.len() {
+            self.push(from);
+            self.current = self.jumps.len() - 1;
+        }
+        if self.current == 0 {
+            return None;
+        }
+        self.current -= 1;
+        Some(&self.jumps[self.current])
+    }
+
+    /// Returns the entry after the current one for `Ctrl-I`
+    pub fn forward(&mut self) -> Option<&Jump> {
+        if self.current + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.current += 1;
+        Some(&self.jumps[self.current])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jump(y: usize) -> Jump {
+        Jump {
+            path: None,
+            position: Position::new(0, y),
+        }
+    }
+
+    #[test]
+    fn goes_back_and_forth() {
+        let mut jumps = JumpList::default();
+        jumps.push(jump(1));
+        jumps.push(jump(2));
+        assert_eq!(jumps.back(jump(3)), Some(&jump(2)));
+        assert_eq!(jumps.back(jump(2)), Some(&jump(1)));
+        assert_eq!(jumps.back(jump(1)), None);
+        assert_eq!(jumps.forward(), Some(&jump(2)));
+        assert_eq!(jumps.forward(), Some(&jump(3)));
+        assert_eq!(jumps.forward(), None);
+    }
+
+    #[test]
+    fn keeps_lines_once() {
+        let mut jumps = JumpList::default();
+        jumps.push(jump(1));
+        jumps.push(jump(2));
+        jumps.push(Jump {
+            position: Position::new(5, 1),
+            ..jump(1)
+        });
+        assert_eq!(jumps.jumps().len(), 2);
+        assert_eq!(jumps.jumps()[1].position, Position::new(5, 1));
+        // Going back from the newest entry's line doesn't add it again
+        assert_eq!(jumps.back(jump(1)), Some(&jump(2)));
+        assert_eq!(jumps.jumps().len(), 2);
+    }
+}
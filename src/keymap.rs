@@ -2,7 +2,11 @@ use std::collections::HashMap;
 
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-use crate::editor::Mode;
+use crate::{
+    editor::Mode,
+    keys::{format_keys, parse_keys},
+    motion::Operator,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum Modifiers {
@@ -51,22 +55,268 @@ pub enum Action {
     MoveLeft,
     MoveRight,
     InsertChar(char),
+    InsertTab,
     RemoveChar,
     DeleteChar,
     ExecuteCommand,
     InsertCharCommand(char),
     RemoveCharCommand,
+    DeleteCharCommand,
+    RemoveWordCommand,
+    RemoveLineCommand,
+    MoveLeftCommand,
+    MoveRightCommand,
+    MoveToStartCommand,
+    MoveToEndCommand,
+    HistoryPreviousCommand,
+    HistoryNextCommand,
+    CompleteCommand,
+    CompletePreviousCommand,
+    InsertRegisterCommand,
+    Search,
+    SearchNext,
+    SearchPrevious,
     MoveToStartOfLine,
     MoveToEndOfLine,
     MoveToFirstCharacterInLine,
+    LspHover,
+    LspComplete,
+    NextDiagnostic,
+    PreviousDiagnostic,
+    CompleteNext,
+    CompletePrevious,
+    CompletePaths,
+    /// Selects the register for the next yank, delete or put
+    SelectRegister,
+    YankLines,
+    PutAfter,
+    PutBefore,
+    SetMark,
+    JumpToMark,
+    JumpToMarkLine,
+    /// Goes back in the jump list
+    JumpOlder,
+    /// Goes forward in the jump list, or switches between the columns of the hex view
+    JumpNewer,
+    OpenEntry,
+    ParentDirectory,
+    ToggleHiddenEntries,
+    CycleSortOrder,
+    ChangeToListedDirectory,
+    FindFile,
+    /// Waits for the motion the operator works on
+    Operator(Operator),
+    Undo,
+    Redo,
+}
+
+/// The modes user mappings can be defined for
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MapMode {
+    Normal,
+    // TODO: mappings for visual mode can already be defined but are never triggered because
+    // visual mode isn't implemented yet
+    Visual,
+    Insert,
+    Command,
+}
+
+impl MapMode {
+    pub fn from_mode(mode: Mode) -> Self {
+        match mode {
+            Mode::Normal => MapMode::Normal,
+            Mode::Insert => MapMode::Insert,
+            Mode::Command => MapMode::Command,
+        }
+    }
+
+    fn to_char(self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Visual => 'v',
+            MapMode::Insert => 'i',
+            MapMode::Command => 'c',
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MapCommand {
+    Map { noremap: bool },
+    Unmap,
+}
+
+/// Resolves the name of a mapping command like `:nnoremap` or `:iunmap` to what it does and which
+/// modes it applies to.
+pub fn parse_map_command(name: &str) -> Option<(MapCommand, &'static [MapMode])> {
+    const MAP: &[MapMode] = &[MapMode::Normal, MapMode::Visual];
+    const MAP_BANG: &[MapMode] = &[MapMode::Insert, MapMode::Command];
+    const NORMAL: &[MapMode] = &[MapMode::Normal];
+    const VISUAL: &[MapMode] = &[MapMode::Visual];
+    const INSERT: &[MapMode] = &[MapMode::Insert];
+    const COMMAND: &[MapMode] = &[MapMode::Command];
+
+    let map = MapCommand::Map { noremap: false };
+    let noremap = MapCommand::Map { noremap: true };
+    let unmap = MapCommand::Unmap;
+
+    let result = match name {
+        "map" => (map, MAP),
+        "map!" => (map, MAP_BANG),
+        "nmap" | "nm" => (map, NORMAL),
+        "vmap" | "vm" => (map, VISUAL),
+        "imap" | "im" => (map, INSERT),
+        "cmap" | "cm" => (map, COMMAND),
+        "noremap" | "no" => (noremap, MAP),
+        "noremap!" | "no!" => (noremap, MAP_BANG),
+        "nnoremap" | "nn" => (noremap, NORMAL),
+        "vnoremap" | "vn" => (noremap, VISUAL),
+        "inoremap" | "ino" => (noremap, INSERT),
+        "cnoremap" | "cno" => (noremap, COMMAND),
+        "unmap" | "unm" => (unmap, MAP),
+        "unmap!" | "unm!" => (unmap, MAP_BANG),
+        "nunmap" | "nun" => (unmap, NORMAL),
+        "vunmap" | "vu" => (unmap, VISUAL),
+        "iunmap" | "iu" => (unmap, INSERT),
+        "cunmap" | "cu" => (unmap, COMMAND),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// A user defined mapping from one key sequence to another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub lhs: Vec<KeyEvent>,
+    pub rhs: Vec<KeyEvent>,
+    /// Whether the keys of the rhs are exempt from being remapped
+    pub noremap: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MappingLookup {
+    /// The keys are the start of at least one mapping, so more keys are needed to decide
+    Pending,
+    Found(Mapping),
+    NotFound,
+}
+
+/// A built-in sequence of keys and the actions it's bound to
+type Sequence = (Vec<KeyEvent>, Vec<Action>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceLookup {
+    /// The keys are the start of a built-in sequence, so more keys are needed to decide
+    Pending,
+    /// The first `usize` keys form a sequence bound to the actions
+    Found(usize, Vec<Action>),
+    NotFound,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KeyMap {
     mappings: HashMap<Key, Vec<Action>>,
+    /// Built-in actions bound to sequences of more than one key like `]d`
+    sequences: HashMap<Mode, Vec<Sequence>>,
+    user_mappings: HashMap<MapMode, Vec<Mapping>>,
 }
 
 impl KeyMap {
+    pub fn map(&mut self, mode: MapMode, mapping: Mapping) {
+        let mappings = self.user_mappings.entry(mode).or_default();
+        mappings.retain(|existing| existing.lhs != mapping.lhs);
+        mappings.push(mapping);
+    }
+
+    /// Removes the mapping with the given lhs and returns whether there was one.
+    pub fn unmap(&mut self, mode: MapMode, lhs: &[KeyEvent]) -> bool {
+        let Some(mappings) = self.user_mappings.get_mut(&mode) else {
+            return false;
+        };
+        let count = mappings.len();
+        mappings.retain(|mapping| mapping.lhs != lhs);
+        mappings.len() != count
+    }
+
+    /// Looks for a user mapping matching the start of the given keys. Unless the lookup timed
+    /// out, keys that could still become a longer mapping result in [`MappingLookup::Pending`].
+    pub fn find_mapping(&self, mode: MapMode, keys: &[KeyEvent], timed_out: bool) -> MappingLookup {
+        let Some(mappings) = self.user_mappings.get(&mode) else {
+            return MappingLookup::NotFound;
+        };
+
+        if !timed_out
+            && mappings
+                .iter()
+                .any(|mapping| mapping.lhs.len() > keys.len() && mapping.lhs.starts_with(keys))
+        {
+            return MappingLookup::Pending;
+        }
+
+        match mappings
+            .iter()
+            .filter(|mapping| keys.starts_with(&mapping.lhs))
+            .max_by_key(|mapping| mapping.lhs.len())
+        {
+            Some(mapping) => MappingLookup::Found(mapping.clone()),
+            None => MappingLookup::NotFound,
+        }
+    }
+
+    /// Looks for a built-in key sequence at the start of the given keys, like
+    /// [`KeyMap::find_mapping`] does for user mappings.
+    pub fn find_sequence(&self, mode: Mode, keys: &[KeyEvent], timed_out: bool) -> SequenceLookup {
+        let Some(sequences) = self.sequences.get(&mode) else {
+            return SequenceLookup::NotFound;
+        };
+
+        if let Some((lhs, actions)) = sequences.iter().find(|(lhs, _)| keys.starts_with(lhs)) {
+            return SequenceLookup::Found(lhs.len(), actions.clone());
+        }
+        if !timed_out && sequences.iter().any(|(lhs, _)| lhs.starts_with(keys)) {
+            return SequenceLookup::Pending;
+        }
+        SequenceLookup::NotFound
+    }
+
+    /// Returns all user mappings of the given modes whose lhs starts with `prefix`, sorted by mode
+    /// and lhs.
+    pub fn list_mappings(&self, modes: &[MapMode], prefix: &[KeyEvent]) -> Vec<String> {
+        let mut mappings = modes
+            .iter()
+            .filter_map(|mode| Some((*mode, self.user_mappings.get(mode)?)))
+            .flat_map(|(mode, mappings)| mappings.iter().map(move |mapping| (mode, mapping)))
+            .filter(|(_, mapping)| mapping.lhs.starts_with(prefix))
+            .map(|(mode, mapping)| {
+                (
+                    mode,
+                    format_keys(&mapping.lhs),
+                    mapping.noremap,
+                    format_keys(&mapping.rhs),
+                )
+            })
+            .collect::<Vec<_>>();
+        mappings.sort();
+
+        mappings
+            .into_iter()
+            .map(|(mode, lhs, noremap, rhs)| {
+                let rhs = if rhs.is_empty() {
+                    "<Nop>".to_owned()
+                } else {
+                    rhs
+                };
+                format!(
+                    "{}  {:<12} {}{}",
+                    mode.to_char(),
+                    lhs,
+                    if noremap { '*' } else { ' ' },
+                    rhs
+                )
+            })
+            .collect()
+    }
+
     pub fn handle(&self, mode: Mode, event: KeyEvent) -> Option<Vec<Action>> {
         // Pass through typed characters in Mode::Insert and Mode::Command
         if event.modifiers.is_empty() || event.modifiers.eq(&KeyModifiers::SHIFT) {
@@ -192,11 +442,15 @@ impl Default for KeyMap {
             Key::unmodified(Mode::Normal, KeyCode::Char('l')),
             vec![Action::MoveRight],
         );
-        // Mode::Insert -- KeyCode::Enter, KeyCode::BackSpace, KeyCode::Delete
+        // Mode::Insert -- KeyCode::Enter, KeyCode::Tab, KeyCode::BackSpace, KeyCode::Delete
         mappings.insert(
             Key::unmodified(Mode::Insert, KeyCode::Enter),
             vec![Action::InsertChar('\n')],
         );
+        mappings.insert(
+            Key::unmodified(Mode::Insert, KeyCode::Tab),
+            vec![Action::InsertTab],
+        );
         mappings.insert(
             Key::unmodified(Mode::Insert, KeyCode::Backspace),
             vec![Action::RemoveChar],
@@ -214,6 +468,83 @@ impl Default for KeyMap {
             Key::unmodified(Mode::Normal, KeyCode::Char('x')),
             vec![Action::DeleteChar],
         );
+        // Operators, which take a motion
+        mappings.insert(
+            Key::any(Mode::Normal, KeyCode::Char('!')),
+            vec![Action::Operator(Operator::Filter)],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Normal, KeyCode::Char('d')),
+            vec![Action::Operator(Operator::Delete)],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Normal, KeyCode::Char('y')),
+            vec![Action::Operator(Operator::Yank)],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Normal, KeyCode::Char('u')),
+            vec![Action::Undo],
+        );
+        mappings.insert(
+            Key::modified(Mode::Normal, KeyCode::Char('r'), KeyModifiers::CONTROL),
+            vec![Action::Redo],
+        );
+        // Registers
+        mappings.insert(
+            Key::any(Mode::Normal, KeyCode::Char('"')),
+            vec![Action::SelectRegister],
+        );
+        mappings.insert(
+            Key::modified(Mode::Normal, KeyCode::Char('Y'), KeyModifiers::SHIFT),
+            vec![Action::YankLines],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Normal, KeyCode::Char('p')),
+            vec![Action::PutAfter],
+        );
+        mappings.insert(
+            Key::modified(Mode::Normal, KeyCode::Char('P'), KeyModifiers::SHIFT),
+            vec![Action::PutBefore],
+        );
+        // Marks and the jump list
+        mappings.insert(
+            Key::unmodified(Mode::Normal, KeyCode::Char('m')),
+            vec![Action::SetMark],
+        );
+        mappings.insert(
+            Key::any(Mode::Normal, KeyCode::Char('`')),
+            vec![Action::JumpToMark],
+        );
+        mappings.insert(
+            Key::any(Mode::Normal, KeyCode::Char('\'')),
+            vec![Action::JumpToMarkLine],
+        );
+        mappings.insert(
+            Key::modified(Mode::Normal, KeyCode::Char('o'), KeyModifiers::CONTROL),
+            vec![Action::JumpOlder],
+        );
+        // Terminals send Tab for Ctrl-I
+        mappings.insert(
+            Key::unmodified(Mode::Normal, KeyCode::Tab),
+            vec![Action::JumpNewer],
+        );
+        mappings.insert(
+            Key::modified(Mode::Normal, KeyCode::Char('i'), KeyModifiers::CONTROL),
+            vec![Action::JumpNewer],
+        );
+        // Directory listings
+        mappings.insert(
+            Key::unmodified(Mode::Normal, KeyCode::Enter),
+            vec![Action::OpenEntry],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Normal, KeyCode::Char('-')),
+            vec![Action::ParentDirectory],
+        );
+        mappings.insert(
+            Key::modified(Mode::Normal, KeyCode::Char('p'), KeyModifiers::CONTROL),
+            vec![Action::FindFile],
+        );
         // Mode::Command
         mappings.insert(
             Key::unmodified(Mode::Command, KeyCode::Enter),
@@ -223,6 +554,83 @@ impl Default for KeyMap {
             Key::unmodified(Mode::Command, KeyCode::Backspace),
             vec![Action::RemoveCharCommand],
         );
+        mappings.insert(
+            Key::unmodified(Mode::Command, KeyCode::Delete),
+            vec![Action::DeleteCharCommand],
+        );
+        mappings.insert(
+            Key::modified(Mode::Command, KeyCode::Char('w'), KeyModifiers::CONTROL),
+            vec![Action::RemoveWordCommand],
+        );
+        mappings.insert(
+            Key::modified(Mode::Command, KeyCode::Char('u'), KeyModifiers::CONTROL),
+            vec![Action::RemoveLineCommand],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Command, KeyCode::Left),
+            vec![Action::MoveLeftCommand],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Command, KeyCode::Right),
+            vec![Action::MoveRightCommand],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Command, KeyCode::Home),
+            vec![Action::MoveToStartCommand],
+        );
+        mappings.insert(
+            Key::modified(Mode::Command, KeyCode::Char('b'), KeyModifiers::CONTROL),
+            vec![Action::MoveToStartCommand],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Command, KeyCode::End),
+            vec![Action::MoveToEndCommand],
+        );
+        mappings.insert(
+            Key::modified(Mode::Command, KeyCode::Char('e'), KeyModifiers::CONTROL),
+            vec![Action::MoveToEndCommand],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Command, KeyCode::Up),
+            vec![Action::HistoryPreviousCommand],
+        );
+        mappings.insert(
+            Key::modified(Mode::Command, KeyCode::Char('p'), KeyModifiers::CONTROL),
+            vec![Action::HistoryPreviousCommand],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Command, KeyCode::Down),
+            vec![Action::HistoryNextCommand],
+        );
+        mappings.insert(
+            Key::modified(Mode::Command, KeyCode::Char('n'), KeyModifiers::CONTROL),
+            vec![Action::HistoryNextCommand],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Command, KeyCode::Tab),
+            vec![Action::CompleteCommand],
+        );
+        mappings.insert(
+            Key::any(Mode::Command, KeyCode::BackTab),
+            vec![Action::CompletePreviousCommand],
+        );
+        mappings.insert(
+            Key::modified(Mode::Command, KeyCode::Char('r'), KeyModifiers::CONTROL),
+            vec![Action::InsertRegisterCommand],
+        );
+        // Search
+        mappings.insert(
+            Key::unmodified(Mode::Normal, KeyCode::Char('/')),
+            vec![Action::Search],
+        );
+        mappings.insert(
+            Key::unmodified(Mode::Normal, KeyCode::Char('n')),
+            vec![Action::SearchNext],
+        );
+        mappings.insert(
+            Key::modified(Mode::Normal, KeyCode::Char('N'), KeyModifiers::SHIFT),
+            vec![Action::SearchPrevious],
+        );
         // Advanced movements
         mappings.insert(
             Key::unmodified(Mode::Normal, KeyCode::Char('0')),
@@ -247,7 +655,45 @@ impl Default for KeyMap {
                 Action::ChangeMode(Mode::Insert),
             ],
         );
+        // Language server features
+        mappings.insert(
+            Key::modified(Mode::Normal, KeyCode::Char('K'), KeyModifiers::SHIFT),
+            vec![Action::LspHover],
+        );
+        mappings.insert(
+            Key::modified(Mode::Insert, KeyCode::Char(' '), KeyModifiers::CONTROL),
+            vec![Action::LspComplete],
+        );
+        // Completion
+        mappings.insert(
+            Key::modified(Mode::Insert, KeyCode::Char('n'), KeyModifiers::CONTROL),
+            vec![Action::CompleteNext],
+        );
+        mappings.insert(
+            Key::modified(Mode::Insert, KeyCode::Char('p'), KeyModifiers::CONTROL),
+            vec![Action::CompletePrevious],
+        );
+
+        let mut sequences = HashMap::new();
+        sequences.insert(
+            Mode::Normal,
+            vec![
+                (parse_keys("]d", ""), vec![Action::NextDiagnostic]),
+                (parse_keys("[d", ""), vec![Action::PreviousDiagnostic]),
+                (parse_keys("gh", ""), vec![Action::ToggleHiddenEntries]),
+                (parse_keys("gs", ""), vec![Action::CycleSortOrder]),
+                (parse_keys("cd", ""), vec![Action::ChangeToListedDirectory]),
+            ],
+        );
+        sequences.insert(
+            Mode::Insert,
+            vec![(parse_keys("<C-x><C-f>", ""), vec![Action::CompletePaths])],
+        );
 
-        Self { mappings }
+        Self {
+            mappings,
+            sequences,
+            user_mappings: HashMap::new(),
+        }
     }
 }
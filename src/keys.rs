@@ -0,0 +1,445 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MediaKeyCode, ModifierKeyCode};
+
+/// Names of all keys that aren't written as a single character. The first name of a key is the
+/// one used when formatting it, the others are only accepted when parsing.
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Space", KeyCode::Char(' ')),
+    ("lt", KeyCode::Char('<')),
+    ("gt", KeyCode::Char('>')),
+    ("Bar", KeyCode::Char('|')),
+    ("Bslash", KeyCode::Char('\\')),
+    ("Esc", KeyCode::Esc),
+    ("CR", KeyCode::Enter),
+    ("Enter", KeyCode::Enter),
+    ("Return", KeyCode::Enter),
+    ("Tab", KeyCode::Tab),
+    ("BS", KeyCode::Backspace),
+    ("Backspace", KeyCode::Backspace),
+    ("Del", KeyCode::Delete),
+    ("Delete", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Nul", KeyCode::Null),
+    ("Null", KeyCode::Null),
+    ("CapsLock", KeyCode::CapsLock),
+    ("ScrollLock", KeyCode::ScrollLock),
+    ("NumLock", KeyCode::NumLock),
+    ("PrintScreen", KeyCode::PrintScreen),
+    ("Pause", KeyCode::Pause),
+    ("Menu", KeyCode::Menu),
+    ("Begin", KeyCode::KeypadBegin),
+    ("MediaPlay", KeyCode::Media(MediaKeyCode::Play)),
+    ("MediaPause", KeyCode::Media(MediaKeyCode::Pause)),
+    ("MediaPlayPause", KeyCode::Media(MediaKeyCode::PlayPause)),
+    ("MediaReverse", KeyCode::Media(MediaKeyCode::Reverse)),
+    ("MediaStop", KeyCode::Media(MediaKeyCode::Stop)),
+    (
+        "MediaFastForward",
+        KeyCode::Media(MediaKeyCode::FastForward),
+    ),
+    ("MediaRewind", KeyCode::Media(MediaKeyCode::Rewind)),
+    ("MediaTrackNext", KeyCode::Media(MediaKeyCode::TrackNext)),
+    (
+        "MediaTrackPrevious",
+        KeyCode::Media(MediaKeyCode::TrackPrevious),
+    ),
+    ("MediaRecord", KeyCode::Media(MediaKeyCode::Record)),
+    ("VolumeDown", KeyCode::Media(MediaKeyCode::LowerVolume)),
+    ("VolumeUp", KeyCode::Media(MediaKeyCode::RaiseVolume)),
+    ("VolumeMute", KeyCode::Media(MediaKeyCode::MuteVolume)),
+    ("LeftShift", KeyCode::Modifier(ModifierKeyCode::LeftShift)),
+    (
+        "LeftControl",
+        KeyCode::Modifier(ModifierKeyCode::LeftControl),
+    ),
+    ("LeftAlt", KeyCode::Modifier(ModifierKeyCode::LeftAlt)),
+    ("LeftSuper", KeyCode::Modifier(ModifierKeyCode::LeftSuper)),
+    ("LeftHyper", KeyCode::Modifier(ModifierKeyCode::LeftHyper)),
+    ("LeftMeta", KeyCode::Modifier(ModifierKeyCode::LeftMeta)),
+    ("RightShift", KeyCode::Modifier(ModifierKeyCode::RightShift)),
+    (
+        "RightControl",
+        KeyCode::Modifier(ModifierKeyCode::RightControl),
+    ),
+    ("RightAlt", KeyCode::Modifier(ModifierKeyCode::RightAlt)),
+    ("RightSuper", KeyCode::Modifier(ModifierKeyCode::RightSuper)),
+    ("RightHyper", KeyCode::Modifier(ModifierKeyCode::RightHyper)),
+    ("RightMeta", KeyCode::Modifier(ModifierKeyCode::RightMeta)),
+    (
+        "IsoLevel3Shift",
+        KeyCode::Modifier(ModifierKeyCode::IsoLevel3Shift),
+    ),
+    (
+        "IsoLevel5Shift",
+        KeyCode::Modifier(ModifierKeyCode::IsoLevel5Shift),
+    ),
+];
+
+/// Modifier prefixes in the order they're written in when formatting a key. `A-` is accepted as
+/// an alias of `M-` when parsing.
+const MODIFIER_PREFIXES: &[(char, KeyModifiers)] = &[
+    ('C', KeyModifiers::CONTROL),
+    ('S', KeyModifiers::SHIFT),
+    ('M', KeyModifiers::ALT),
+    ('D', KeyModifiers::SUPER),
+    ('H', KeyModifiers::HYPER),
+    ('T', KeyModifiers::META),
+];
+
+/// Brings a key event into the form used for comparing keys: only code and modifiers are kept,
+/// and characters carry the shift modifier exactly if they are uppercase (which is what terminals
+/// report, while shifted symbols like `$` come without it). Shift+Tab is always `BackTab`.
+pub fn normalize_key(event: KeyEvent) -> KeyEvent {
+    let mut code = event.code;
+    let mut modifiers = event.modifiers;
+    match code {
+        KeyCode::Char(c) => modifiers.set(KeyModifiers::SHIFT, c.is_uppercase()),
+        KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => code = KeyCode::BackTab,
+        KeyCode::BackTab => modifiers.insert(KeyModifiers::SHIFT),
+        _ => {}
+    }
+    KeyEvent::new(code, modifiers)
+}
+
+/// Parses a key sequence in vim's key notation, e.g. `<C-w>j`, `<leader>w` or `:w<CR>`.
+/// `<leader>` is replaced by the keys of the given leader. `<Nop>` stands for no keys at all.
+/// Anything that isn't a valid `<...>` sequence is taken literally.
+pub fn parse_keys(notation: &str, leader: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+
+    let mut rest = notation;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = &rest[1..end];
+                if name.eq_ignore_ascii_case("leader") {
+                    keys.extend(parse_keys(leader, ""));
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+                if name.eq_ignore_ascii_case("nop") {
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+                if let Some(key) = parse_special_key(name) {
+                    keys.push(key);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+
+        keys.push(normalize_key(KeyEvent::new(
+            KeyCode::Char(c),
+            KeyModifiers::empty(),
+        )));
+        rest = &rest[c.len_utf8()..];
+    }
+
+    keys
+}
+
+/// Parses the inside of a `<...>` sequence, i.e. modifiers followed by a key name.
+fn parse_special_key(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::empty();
+    let mut name = name;
+    while name.len() > 2 && name.as_bytes()[1] == b'-' {
+        let prefix = name.as_bytes()[0].to_ascii_uppercase() as char;
+        modifiers |= match prefix {
+            'A' => KeyModifiers::ALT,
+            prefix => {
+                MODIFIER_PREFIXES
+                    .iter()
+                    .find(|(candidate, _)| *candidate == prefix)?
+                    .1
+            }
+        };
+        name = &name[2..];
+    }
+
+    let mut chars = name.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => {
+            if modifiers.contains(KeyModifiers::SHIFT) {
+                KeyCode::Char(to_single_char(c.to_uppercase(), c))
+            } else if !modifiers.is_empty() && c.is_uppercase() {
+                // Like in vim, <C-W> and <C-w> are the same key
+                KeyCode::Char(to_single_char(c.to_lowercase(), c))
+            } else {
+                KeyCode::Char(c)
+            }
+        }
+        _ => match KEY_NAMES
+            .iter()
+            .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+        {
+            Some((_, KeyCode::Tab)) if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            Some((_, code)) => *code,
+            None => {
+                let number = name.strip_prefix(['f', 'F'])?;
+                // Don't accept signs or leading zeros so every F key has exactly one name
+                if number.starts_with(['+', '0']) && number != "0" {
+                    return None;
+                }
+                KeyCode::F(number.parse::<u8>().ok()?)
+            }
+        },
+    };
+
+    Some(normalize_key(KeyEvent::new(code, modifiers)))
+}
+
+/// Returns the only character of a case conversion, or `fallback` if the conversion results in
+/// multiple characters (e.g. 'ß' becoming "SS").
+fn to_single_char(mut chars: impl Iterator<Item = char>, fallback: char) -> char {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => fallback,
+    }
+}
+
+/// Formats a key sequence in vim's key notation. This is the inverse of [`parse_keys`] (up to
+/// [`normalize_key`]).
+pub fn format_keys(keys: &[KeyEvent]) -> String {
+    keys.iter().map(|key| format_key(*key)).collect()
+}
+
+/// Formats a single key in vim's key notation, e.g. `a`, `<C-w>` or `<S-F5>`.
+pub fn format_key(key: KeyEvent) -> String {
+    let key = normalize_key(key);
+    let mut modifiers = key.modifiers;
+
+    let name = match key.code {
+        KeyCode::Char(c) => {
+            // Plain characters (including uppercase ones, whose case already implies shift) are
+            // written as they are
+            if modifiers.difference(KeyModifiers::SHIFT).is_empty()
+                && !matches!(c, ' ' | '<')
+                && !c.is_control()
+            {
+                return c.to_string();
+            }
+
+            // With other modifiers, shift is written explicitly because <C-A> means <C-a>
+            let c = if modifiers.contains(KeyModifiers::SHIFT) {
+                let lowercase = to_single_char(c.to_lowercase(), c);
+                if to_single_char(lowercase.to_uppercase(), lowercase) == c {
+                    lowercase
+                } else {
+                    c
+                }
+            } else {
+                c
+            };
+            match KEY_NAMES.iter().find(|(_, code)| *code == KeyCode::Char(c)) {
+                Some((name, _)) => name.to_string(),
+                None => c.to_string(),
+            }
+        }
+        KeyCode::BackTab => "Tab".to_owned(),
+        KeyCode::F(number) => format!("F{}", number),
+        code => match KEY_NAMES.iter().find(|(_, candidate)| *candidate == code) {
+            Some((name, _)) => name.to_string(),
+            None => unreachable!("key code {:?} has no name", code),
+        },
+    };
+
+    let mut result = String::from("<");
+    for (prefix, modifier) in MODIFIER_PREFIXES {
+        if modifiers.contains(*modifier) {
+            result.push(*prefix);
+            result.push('-');
+            modifiers.remove(*modifier);
+        }
+    }
+    result.push_str(&name);
+    result.push('>');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every key code crossterm can report. The match makes this fail to compile when crossterm
+    /// adds a variant, which then needs a name in [`KEY_NAMES`].
+    fn all_key_codes() -> Vec<KeyCode> {
+        let mut codes = Vec::new();
+        for code in [
+            KeyCode::Backspace,
+            KeyCode::Enter,
+            KeyCode::Left,
+            KeyCode::Right,
+            KeyCode::Up,
+            KeyCode::Down,
+            KeyCode::Home,
+            KeyCode::End,
+            KeyCode::PageUp,
+            KeyCode::PageDown,
+            KeyCode::Tab,
+            KeyCode::BackTab,
+            KeyCode::Delete,
+            KeyCode::Insert,
+            KeyCode::F(0),
+            KeyCode::Char('a'),
+            KeyCode::Null,
+            KeyCode::Esc,
+            KeyCode::CapsLock,
+            KeyCode::ScrollLock,
+            KeyCode::NumLock,
+            KeyCode::PrintScreen,
+            KeyCode::Pause,
+            KeyCode::Menu,
+            KeyCode::KeypadBegin,
+            KeyCode::Media(MediaKeyCode::Play),
+            KeyCode::Modifier(ModifierKeyCode::LeftShift),
+        ] {
+            match code {
+                KeyCode::F(_) => codes.extend((0..=u8::MAX).map(KeyCode::F)),
+                KeyCode::Char(_) => codes.extend(
+                    [
+                        'a', 'z', 'A', 'Z', '0', '$', '-', ' ', '<', '>', '|', '\\', '\u{1}', '\n',
+                        'é', 'É', 'ß', 'ẞ', 'ﬀ', '語',
+                    ]
+                    .map(KeyCode::Char),
+                ),
+                KeyCode::Media(_) => codes.extend(
+                    [
+                        MediaKeyCode::Play,
+                        MediaKeyCode::Pause,
+                        MediaKeyCode::PlayPause,
+                        MediaKeyCode::Reverse,
+                        MediaKeyCode::Stop,
+                        MediaKeyCode::FastForward,
+                        MediaKeyCode::Rewind,
+                        MediaKeyCode::TrackNext,
+                        MediaKeyCode::TrackPrevious,
+                        MediaKeyCode::Record,
+                        MediaKeyCode::LowerVolume,
+                        MediaKeyCode::RaiseVolume,
+                        MediaKeyCode::MuteVolume,
+                    ]
+                    .map(KeyCode::Media),
+                ),
+                KeyCode::Modifier(_) => codes.extend(
+                    [
+                        ModifierKeyCode::LeftShift,
+                        ModifierKeyCode::LeftControl,
+                        ModifierKeyCode::LeftAlt,
+                        ModifierKeyCode::LeftSuper,
+                        ModifierKeyCode::LeftHyper,
+                        ModifierKeyCode::LeftMeta,
+                        ModifierKeyCode::RightShift,
+                        ModifierKeyCode::RightControl,
+                        ModifierKeyCode::RightAlt,
+                        ModifierKeyCode::RightSuper,
+                        ModifierKeyCode::RightHyper,
+                        ModifierKeyCode::RightMeta,
+                        ModifierKeyCode::IsoLevel3Shift,
+                        ModifierKeyCode::IsoLevel5Shift,
+                    ]
+                    .map(KeyCode::Modifier),
+                ),
+                KeyCode::Backspace
+                | KeyCode::Enter
+                | KeyCode::Left
+                | KeyCode::Right
+                | KeyCode::Up
+                | KeyCode::Down
+                | KeyCode::Home
+                | KeyCode::End
+                | KeyCode::PageUp
+                | KeyCode::PageDown
+                | KeyCode::Tab
+                | KeyCode::BackTab
+                | KeyCode::Delete
+                | KeyCode::Insert
+                | KeyCode::Null
+                | KeyCode::Esc
+                | KeyCode::CapsLock
+                | KeyCode::ScrollLock
+                | KeyCode::NumLock
+                | KeyCode::PrintScreen
+                | KeyCode::Pause
+                | KeyCode::Menu
+                | KeyCode::KeypadBegin => codes.push(code),
+            }
+        }
+        codes
+    }
+
+    fn all_modifiers() -> Vec<KeyModifiers> {
+        (0..1 << MODIFIER_PREFIXES.len())
+            .map(|bits: usize| {
+                MODIFIER_PREFIXES
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| bits & 1 << i != 0)
+                    .fold(KeyModifiers::empty(), |all, (_, (_, modifier))| {
+                        all | *modifier
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_key_round_trips() {
+        for code in all_key_codes() {
+            for modifiers in all_modifiers() {
+                let key = KeyEvent::new(code, modifiers);
+                let notation = format_key(key);
+                assert_eq!(
+                    parse_keys(&notation, ""),
+                    vec![normalize_key(key)],
+                    "{:?} with {:?} formatted as {:?}",
+                    code,
+                    modifiers,
+                    notation
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sequences_round_trip() {
+        let keys = parse_keys(":w<CR><C-w>j<lt><Space><S-Tab><M-F12><C-S-a>", "");
+        assert_eq!(keys.len(), 10);
+        assert_eq!(
+            format_keys(&keys),
+            ":w<CR><C-w>j<lt><Space><S-Tab><M-F12><C-S-a>"
+        );
+    }
+
+    #[test]
+    fn aliases() {
+        let parse = |notation| parse_keys(notation, "");
+        assert_eq!(parse("<Return>"), parse("<CR>"));
+        assert_eq!(parse("<A-x>"), parse("<M-x>"));
+        assert_eq!(parse("<C-W>"), parse("<C-w>"));
+        assert_eq!(
+            parse("<S-Tab>"),
+            vec![normalize_key(KeyEvent::from(KeyCode::BackTab))]
+        );
+        assert_eq!(parse("<leader>w"), parse("w"));
+        assert_eq!(parse_keys("<leader>w", ","), parse(",w"));
+        assert_eq!(parse("a<Nop>b"), parse("ab"));
+    }
+
+    #[test]
+    fn invalid_notation_is_literal() {
+        for notation in ["<F01>", "<F+1>", "<F256>", "<X-a>", "<nokey>", "<C-"] {
+            let literal = notation
+                .chars()
+                .map(|c| normalize_key(KeyEvent::from(KeyCode::Char(c))))
+                .collect::<Vec<_>>();
+            assert_eq!(parse_keys(notation, ""), literal, "{}", notation);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use blessings::WindowBounds;
+use regex::Regex;
+
+use crate::{highlight::Highlights, screen::Screen};
+
+/// The offset of every this many lines is kept, so any line is found by reading at most this
+/// many lines from the closest kept offset
+const CHECKPOINT_INTERVAL: usize = 256;
+
+/// How much of the file is read at once
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Lines are cut off after this many bytes when they are read, so a file without line breaks
+/// can't fill the memory
+const MAX_LINE_LENGTH: usize = 1 << 16;
+
+/// Finds where the lines of a file start without reading all of it at once. The file is only
+/// scanned as far as the lines asked for so far.
+#[derive(Debug)]
+struct LineIndex {
+    /// The offsets of lines 0, CHECKPOINT_INTERVAL, 2 * CHECKPOINT_INTERVAL, ...
+    checkpoints: Vec<u64>,
+    /// How many bytes of the file have been scanned
+    scanned: u64,
+    /// How many lines start before `scanned`
+    lines: usize,
+    /// Whether the whole file has been scanned
+    complete: bool,
+    /// The last byte that was scanned
+    last_byte: Option<u8>,
+}
+
+impl LineIndex {
+    fn new() -> Self {
+        Self {
+            checkpoints: vec![0],
+            scanned: 0,
+            lines: 1,
+            complete: false,
+            last_byte: None,
+        }
+    }
+
+    /// Scans the file until the end of `line` has been found or the end of the file is reached
+    fn scan_to(&mut self, file: &mut File, line: usize) -> io::Result<()> {
+        let line = line.saturating_add(1);
+        if self.complete || line < self.lines {
+            return Ok(());
+        }
+
+        file.seek(SeekFrom::Start(self.scanned))?;
+        let mut chunk = vec![0; CHUNK_SIZE];
+        while line >= self.lines {
+            let length = file.read(&mut chunk)?;
+            if length == 0 {
+                // A line break at the end of the file ends the last line instead of starting
+                // another one
+                if self.last_byte == Some(b'\n') {
+                    self.lines -= 1;
+                }
+                self.complete = true;
+                break;
+            }
+            self.last_byte = Some(chunk[length - 1]);
+            for (i, _) in chunk[..length]
+                .iter()
+                .enumerate()
+                .filter(|(_, byte)| **byte == b'\n')
+            {
+                if self.lines.is_multiple_of(CHECKPOINT_INTERVAL) {
+                    self.checkpoints.push(self.scanned + i as u64 + 1);
+                }
+                self.lines += 1;
+            }
+            self.scanned += length as u64;
+        }
+        Ok(())
+    }
+}
+
+/// A read-only view of a file too large to be read into a buffer. Lines are read from the file
+/// when they are shown or searched, and nothing is highlighted.
+#[derive(Debug)]
+pub struct LargeFileView {
+    file: File,
+    index: LineIndex,
+    /// The line and character the cursor is on
+    cursor: (usize, usize),
+    /// The first line and character that are shown
+    scroll: (usize, usize),
+    bounds: WindowBounds,
+    /// The lines shown last time, starting with the line in the first element
+    visible: (usize, Vec<String>),
+}
+
+impl LargeFileView {
+    pub fn open(path: &Path, bounds: WindowBounds) -> io::Result<Self> {
+        let mut view = Self {
+            file: File::open(path)?,
+            index: LineIndex::new(),
+            cursor: (0, 0),
+            scroll: (0, 0),
+            bounds,
+            visible: (0, Vec::new()),
+        };
+        view.read_visible_lines()?;
+        Ok(view)
+    }
+
+    pub fn set_bounds(&mut self, bounds: WindowBounds) -> io::Result<()> {
+        self.bounds = bounds;
+        self.read_visible_lines()?;
+        self.set_cursor(self.cursor.0, self.cursor.1)
+    }
+
+    pub fn cursor_line(&self) -> usize {
+        self.cursor.0
+    }
+
+    /// Returns the number of lines, or `None` if the file hasn't been scanned to the end yet
+    pub fn line_count(&self) -> Option<usize> {
+        self.index.complete.then_some(self.index.lines)
+    }
+
+    /// Reads up to `count` lines starting with `first`. The index has to be scanned to the last of
+    /// them.
+    fn read_lines(&mut self, first: usize, count: usize) -> io::Result<Vec<String>> {
+        let checkpoint = first / CHECKPOINT_INTERVAL;
+        let mut line = checkpoint * CHECKPOINT_INTERVAL;
+        self.file
+            .seek(SeekFrom::Start(self.index.checkpoints[checkpoint]))?;
+
+        let mut lines = Vec::with_capacity(count);
+        let mut current = Vec::new();
+        let mut chunk = vec![0; CHUNK_SIZE.min(count * 256).max(4096)];
+        'read: loop {
+            let length = self.file.read(&mut chunk)?;
+            for &byte in &chunk[..length] {
+                if byte != b'\n' {
+                    if current.len() < MAX_LINE_LENGTH && line >= first {
+                        current.push(byte);
+                    }
+                    continue;
+                }
+                if line >= first {
+                    lines.push(decode_line(&current));
+                    current.clear();
+                    if lines.len() == count {
+                        break 'read;
+                    }
+                }
+                line += 1;
+            }
+            if length == 0 {
+                // The last line doesn't end with a line break
+                if line >= first && line < self.index.lines {
+                    lines.push(decode_line(&current));
+                }
+                break;
+            }
+        }
+        Ok(lines)
+    }
+
+    fn read_visible_lines(&mut self) -> io::Result<()> {
+        let count = self.bounds.height as usize;
+        self.index.scan_to(&mut self.file, self.scroll.0 + count)?;
+        self.visible = (self.scroll.0, self.read_lines(self.scroll.0, count)?);
+        Ok(())
+    }
+
+    /// Moves the cursor to the given line and character, clamping it to the file, and scrolls
+    /// to it
+    pub fn set_cursor(&mut self, line: usize, x: usize) -> io::Result<()> {
+        self.index.scan_to(&mut self.file, line)?;
+        let line = line.min(self.index.lines - 1);
+        let height = (self.bounds.height as usize).max(1);
+        if line < self.scroll.0 {
+            self.scroll.0 = line;
+        } else if line >= self.scroll.0 + height {
+            self.scroll.0 = line + 1 - height;
+        }
+        if self.visible.0 != self.scroll.0 {
+            self.read_visible_lines()?;
+        }
+
+        let length = self
+            .visible
+            .1
+            .get(line - self.scroll.0)
+            .map_or(0, |text| text.chars().count());
+        let x = x.min(length.saturating_sub(1));
+        let width = (self.bounds.width as usize).max(1);
+        if x < self.scroll.1 {
+            self.scroll.1 = x;
+        } else if x >= self.scroll.1 + width {
+            self.scroll.1 = x + 1 - width;
+        }
+        self.cursor = (line, x);
+        Ok(())
+    }
+
+    pub fn move_up(&mut self) -> io::Result<()> {
+        self.set_cursor(self.cursor.0.saturating_sub(1), self.cursor.1)
+    }
+
+    pub fn move_down(&mut self) -> io::Result<()> {
+        self.set_cursor(self.cursor.0 + 1, self.cursor.1)
+    }
+
+    pub fn move_left(&mut self) -> io::Result<()> {
+        self.set_cursor(self.cursor.0, self.cursor.1.saturating_sub(1))
+    }
+
+    pub fn move_right(&mut self) -> io::Result<()> {
+        self.set_cursor(self.cursor.0, self.cursor.1 + 1)
+    }
+
+    pub fn move_to_start_of_line(&mut self) -> io::Result<()> {
+        self.set_cursor(self.cursor.0, 0)
+    }
+
+    pub fn move_to_end_of_line(&mut self) -> io::Result<()> {
+        self.set_cursor(self.cursor.0, usize::MAX)
+    }
+
+    /// Moves the cursor to the next match of the regular expression after or before it,
+    /// wrapping around at the end of the file. The file is searched in blocks of lines, so only
+    /// a few of them are in memory at a time.
+    pub fn search(&mut self, regex: &Regex, forward: bool) -> Result<(), String> {
+        let (cursor_line, cursor_x) = self.cursor;
+        let cursor_block = cursor_line / CHECKPOINT_INTERVAL;
+
+        // When the block of the cursor is searched first, only matches on the searched side of
+        // the cursor count. The other side is searched after wrapping around.
+        let find = |lines: &[String], first: usize, first_block: bool| {
+            let mut matches = lines.iter().enumerate().flat_map(|(i, line)| {
+                regex.find_iter(line).map(move |m| {
+                    let x = line[..m.start()].chars().count();
+                    (first + i, x)
+                })
+            });
+            let is_candidate = |&(line, x): &(usize, usize)| {
+                !first_block
+                    || (forward && (line, x) > (cursor_line, cursor_x))
+                    || (!forward && (line, x) < (cursor_line, cursor_x))
+            };
+            if forward {
+                matches.find(is_candidate)
+            } else {
+                matches.filter(is_candidate).last()
+            }
+        };
+
+        let mut block = cursor_block;
+        let mut wrapped = false;
+        loop {
+            let first = block * CHECKPOINT_INTERVAL;
+            self.index
+                .scan_to(&mut self.file, first + CHECKPOINT_INTERVAL - 1)
+                .map_err(read_error)?;
+            if first < self.index.lines {
+                let lines = self
+                    .read_lines(first, CHECKPOINT_INTERVAL)
+                    .map_err(read_error)?;
+                if let Some((line, x)) = find(&lines, first, block == cursor_block && !wrapped) {
+                    return self.set_cursor(line, x).map_err(read_error);
+                }
+                if wrapped && block == cursor_block {
+                    return Err("Pattern not found".to_owned());
+                }
+            }
+
+            block = match forward {
+                true if first + CHECKPOINT_INTERVAL >= self.index.lines => 0,
+                true => block + 1,
+                false if block == 0 => {
+                    self.index
+                        .scan_to(&mut self.file, usize::MAX)
+                        .map_err(read_error)?;
+                    (self.index.lines - 1) / CHECKPOINT_INTERVAL
+                }
+                false => block - 1,
+            };
+            if block == cursor_block {
+                wrapped = true;
+            }
+        }
+    }
+
+    pub fn render(&self, screen: &mut Screen, highlights: &Highlights) {
+        screen.begin_window(0, 0, self.bounds.width, self.bounds.height);
+
+        highlights.apply(screen, "Normal");
+        let blank = " ".repeat(self.bounds.width as usize);
+        for row in 0..self.bounds.height {
+            screen.print_at(0, row, &blank);
+        }
+
+        for (row, line) in self.visible.1.iter().enumerate() {
+            let text = line
+                .chars()
+                .skip(self.scroll.1)
+                .take(self.bounds.width as usize)
+                .collect::<String>();
+            screen.print_at(0, row as u16, text);
+        }
+
+        screen.clear_colors();
+        screen.move_to(
+            (self.cursor.1 - self.scroll.1) as u16,
+            (self.cursor.0 - self.scroll.0) as u16,
+        );
+        screen.end_window();
+    }
+}
+
+fn read_error(error: io::Error) -> String {
+    format!("Can't read the file: {}", error)
+}
+
+/// Converts a line read from the file to text, dropping the CR of CRLF line endings. Invalid
+/// UTF-8 is replaced instead of failing since there's no encoding detection for large files.
+fn decode_line(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    String::from_utf8_lossy(bytes).into_owned()
+}
@@ -0,0 +1,1149 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    buffer::{Buffer, Change},
+    completion::{CompletionContext, CompletionItem, CompletionSource},
+    editor::LogLevel,
+    jsonrpc::{self, Message, ResponseError, METHOD_NOT_FOUND},
+    util::Position,
+};
+
+/// The unit a language server counts columns in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "utf-8" => Some(PositionEncoding::Utf8),
+            "utf-16" => Some(PositionEncoding::Utf16),
+            "utf-32" => Some(PositionEncoding::Utf32),
+            _ => None,
+        }
+    }
+
+    fn char_length(self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+
+    /// Returns the length of the text in this encoding's units
+    fn length(self, text: &str) -> usize {
+        text.chars().map(|c| self.char_length(c)).sum()
+    }
+
+    /// Converts a column given in characters to this encoding's units
+    pub fn encode_column(self, line: &str, column: usize) -> usize {
+        line.chars().take(column).map(|c| self.char_length(c)).sum()
+    }
+
+    /// Converts a column given in this encoding's units to characters. Columns pointing into the
+    /// middle of a character are rounded up like the specification demands.
+    pub fn decode_column(self, line: &str, column: usize) -> usize {
+        let mut units = 0;
+        for (index, c) in line.chars().enumerate() {
+            if units >= column {
+                return index;
+            }
+            units += self.char_length(c);
+        }
+        line.chars().count()
+    }
+
+    pub fn encode_position(self, lines: &[String], position: Position<usize>) -> Value {
+        let line = lines.get(position.y).map_or("", |line| line.as_str());
+        json!({
+            "line": position.y,
+            "character": self.encode_column(line, position.x),
+        })
+    }
+
+    /// Converts an LSP position to a position in characters, clamping it to the given lines
+    pub fn decode_position(self, lines: &[String], position: &Value) -> Position<usize> {
+        let y = (position["line"].as_u64().unwrap_or(0) as usize).min(lines.len() - 1);
+        let character = position["character"].as_u64().unwrap_or(0) as usize;
+        Position::new(self.decode_column(&lines[y], character), y)
+    }
+
+    /// Applies a list of LSP `TextEdit`s to a buffer.
+    pub fn apply_text_edits(self, buffer: &mut Buffer, edits: &[Value]) {
+        // All ranges refer to the document before any edit was applied, so they are converted
+        // first and applied from the back to keep the remaining ones valid
+        let mut edits = edits
+            .iter()
+            .map(|edit| {
+                let start = self.decode_position(&buffer.lines, &edit["range"]["start"]);
+                let end = self.decode_position(&buffer.lines, &edit["range"]["end"]);
+                (start, end, edit["newText"].as_str().unwrap_or_default())
+            })
+            .enumerate()
+            .collect::<Vec<_>>();
+        // Edits starting at the same position have to end up in the order they were given in
+        edits.sort_by_key(|(index, (start, _, _))| (start.y, start.x, *index));
+
+        for (_, (start, end, text)) in edits.into_iter().rev() {
+            buffer.replace_text(start, end, text);
+        }
+    }
+}
+
+/// The requests the editor sends, so responses can be matched to what they answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Initialize,
+    Hover,
+    Definition,
+    References,
+    Rename,
+    Completion {
+        position: Position<usize>,
+    },
+    SignatureHelp {
+        manual: bool,
+    },
+    /// Answered by opening the picker with the query typed
+    DocumentSymbol {
+        query: String,
+    },
+    Formatting,
+    CodeAction,
+    ExecuteCommand,
+}
+
+/// Something that happened on a language server's side which the editor has to act on
+#[derive(Debug, Clone)]
+pub enum Event {
+    Response {
+        request: Request,
+        result: Result<Value, String>,
+    },
+    /// The server wants the editor to apply a workspace edit and expects an answer with the given
+    /// request id
+    ApplyEdit {
+        id: Value,
+        edit: Value,
+    },
+    Message {
+        message: String,
+        level: LogLevel,
+    },
+    /// The server replaced the diagnostics of a document. `version` is the document version they
+    /// were computed for, if the server reported it.
+    Diagnostics {
+        uri: String,
+        version: Option<i64>,
+        diagnostics: Vec<Value>,
+    },
+    Exited(String),
+}
+
+/// A connection to a single language server
+pub struct Client {
+    /// The command the server was started with, used to refer to it in messages
+    pub name: String,
+    process: Option<Child>,
+    writer: Box<dyn Write>,
+    receiver: Receiver<io::Result<Message>>,
+    next_id: i64,
+    pending: HashMap<i64, Request>,
+    initialized: bool,
+    /// Messages sent before the server finished initializing
+    queued: Vec<Message>,
+    capabilities: Value,
+    root: PathBuf,
+    pub encoding: PositionEncoding,
+    exited: bool,
+}
+
+impl Client {
+    /// Starts a language server process and connects to it over stdio.
+    pub fn start(command: &str, root: &Path) -> io::Result<Self> {
+        let mut parts = command.split_whitespace();
+        let program = parts
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+        let mut process = Command::new(program)
+            .args(parts)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            // Servers log to stderr, which would end up on the editor's screen
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let reader = process.stdout.take().unwrap();
+        let writer = process.stdin.take().unwrap();
+        let mut client = Self::new(command, reader, writer, root);
+        client.process = Some(process);
+        Ok(client)
+    }
+
+    /// Connects to a language server over the given streams, e.g. a fake server for tests, and
+    /// starts initializing it.
+    pub fn new<R, W>(name: &str, reader: R, writer: W, root: &Path) -> Self
+    where
+        R: Read + Send + 'static,
+        W: Write + 'static,
+    {
+        // Messages are read on a separate thread so the render loop never blocks on the server
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            loop {
+                let message = match jsonrpc::read_message(&mut reader) {
+                    Ok(Some(message)) => Ok(message),
+                    Ok(None) => break,
+                    Err(e) => Err(e),
+                };
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self {
+            name: name.to_owned(),
+            process: None,
+            writer: Box::new(writer),
+            receiver,
+            next_id: 0,
+            pending: HashMap::new(),
+            initialized: false,
+            queued: Vec::new(),
+            capabilities: Value::Null,
+            root: root.to_path_buf(),
+            encoding: PositionEncoding::Utf16,
+            exited: false,
+        };
+        client.initialize();
+        client
+    }
+
+    fn initialize(&mut self) {
+        let root_uri = path_to_uri(&self.root);
+        let root_name = self
+            .root
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "root".to_owned());
+        let params = json!({
+            "processId": std::process::id(),
+            "clientInfo": { "name": "bvim", "version": env!("CARGO_PKG_VERSION") },
+            "rootPath": self.root.to_string_lossy(),
+            "rootUri": root_uri,
+            "workspaceFolders": [{ "uri": root_uri, "name": root_name }],
+            "capabilities": {
+                "general": { "positionEncodings": ["utf-32", "utf-8", "utf-16"] },
+                "textDocument": {
+                    "synchronization": { "didSave": true },
+                    "hover": { "contentFormat": ["plaintext", "markdown"] },
+                    "definition": { "linkSupport": true },
+                    "references": {},
+                    "rename": {},
+                    "completion": {
+                        "completionItem": {
+                            "snippetSupport": false,
+                            "documentationFormat": ["plaintext", "markdown"],
+                        },
+                    },
+                    "signatureHelp": {
+                        "signatureInformation": {
+                            "documentationFormat": ["plaintext", "markdown"],
+                        },
+                    },
+                    "documentSymbol": { "hierarchicalDocumentSymbolSupport": true },
+                    "formatting": {},
+                    "publishDiagnostics": { "versionSupport": true },
+                    "codeAction": {
+                        "codeActionLiteralSupport": {
+                            "codeActionKind": {
+                                "valueSet": [
+                                    "", "quickfix", "refactor", "refactor.extract",
+                                    "refactor.inline", "refactor.rewrite", "source",
+                                    "source.organizeImports",
+                                ],
+                            },
+                        },
+                    },
+                },
+                "workspace": {
+                    "applyEdit": true,
+                    "configuration": true,
+                    "workspaceFolders": true,
+                },
+                "window": { "showMessage": {} },
+            },
+        });
+
+        let id = self.next_id();
+        self.pending.insert(id, Request::Initialize);
+        // The initialize request is the only one that mustn't wait for initialization
+        self.write(Message::Request {
+            id: json!(id),
+            method: "initialize".to_owned(),
+            params,
+        });
+    }
+
+    fn next_id(&mut self) -> i64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    /// Returns whether the server announced the given capability, e.g. `hoverProvider`
+    pub fn supports(&self, capability: &str) -> bool {
+        !matches!(
+            self.capabilities.get(capability),
+            None | Some(Value::Null) | Some(Value::Bool(false))
+        )
+    }
+
+    pub fn capabilities(&self) -> &Value {
+        &self.capabilities
+    }
+
+    fn write(&mut self, message: Message) {
+        if self.exited {
+            return;
+        }
+        // A broken pipe means the server died, which the reader thread reports as well
+        if jsonrpc::write_message(&mut self.writer, &message).is_err() {
+            self.exited = true;
+        }
+    }
+
+    fn send(&mut self, message: Message) {
+        if self.initialized {
+            self.write(message);
+        } else {
+            self.queued.push(message);
+        }
+    }
+
+    pub fn request(&mut self, request: Request, method: &str, params: Value) {
+        let id = self.next_id();
+        self.pending.insert(id, request);
+        self.send(Message::Request {
+            id: json!(id),
+            method: method.to_owned(),
+            params,
+        });
+    }
+
+    pub fn notify(&mut self, method: &str, params: Value) {
+        self.send(Message::Notification {
+            method: method.to_owned(),
+            params,
+        });
+    }
+
+    pub fn respond(&mut self, id: Value, result: Result<Value, ResponseError>) {
+        self.send(Message::Response { id, result });
+    }
+
+    /// Handles all messages that arrived since the last call and returns the events the editor
+    /// has to handle.
+    pub fn poll(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Ok(message)) => self.handle_message(message, &mut events),
+                Ok(Err(e)) => {
+                    self.exited = true;
+                    events.push(Event::Exited(e.to_string()));
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    if !self.exited || self.process.is_some() {
+                        events.push(Event::Exited("connection closed".to_owned()));
+                    }
+                    self.exited = true;
+                    break;
+                }
+            }
+        }
+        events
+    }
+
+    fn handle_message(&mut self, message: Message, events: &mut Vec<Event>) {
+        match message {
+            Message::Response { id, result } => {
+                let Some(request) = id.as_i64().and_then(|id| self.pending.remove(&id)) else {
+                    return;
+                };
+                if request == Request::Initialize {
+                    self.initialized(result, events);
+                    return;
+                }
+                events.push(Event::Response {
+                    request,
+                    result: result.map_err(|e| e.message),
+                });
+            }
+            Message::Request { id, method, params } => match method.as_str() {
+                "workspace/applyEdit" => events.push(Event::ApplyEdit {
+                    id,
+                    edit: params["edit"].clone(),
+                }),
+                "workspace/configuration" => {
+                    // There is no configuration for servers yet, so every item is unset
+                    let count = params["items"].as_array().map_or(0, |items| items.len());
+                    self.respond(id, Ok(Value::Array(vec![Value::Null; count])));
+                }
+                "workspace/workspaceFolders" => {
+                    let folders = json!([{ "uri": path_to_uri(&self.root), "name": "root" }]);
+                    self.respond(id, Ok(folders));
+                }
+                "window/showMessageRequest" => {
+                    events.push(show_message(&params));
+                    self.respond(id, Ok(Value::Null));
+                }
+                "window/workDoneProgress/create"
+                | "client/registerCapability"
+                | "client/unregisterCapability" => self.respond(id, Ok(Value::Null)),
+                _ => self.respond(
+                    id,
+                    Err(ResponseError {
+                        code: METHOD_NOT_FOUND,
+                        message: format!("unsupported method: {}", method),
+                    }),
+                ),
+            },
+            Message::Notification { method, params } => match method.as_str() {
+                "window/showMessage" => events.push(show_message(&params)),
+                "textDocument/publishDiagnostics" => events.push(Event::Diagnostics {
+                    uri: params["uri"].as_str().unwrap_or_default().to_owned(),
+                    version: params["version"].as_i64(),
+                    diagnostics: params["diagnostics"]
+                        .as_array()
+                        .cloned()
+                        .unwrap_or_default(),
+                }),
+                _ => {}
+            },
+        }
+    }
+
+    fn initialized(&mut self, result: Result<Value, ResponseError>, events: &mut Vec<Event>) {
+        let result = match result {
+            Ok(result) => result,
+            Err(error) => {
+                events.push(Event::Exited(format!(
+                    "initialization failed: {}",
+                    error.message
+                )));
+                self.shutdown();
+                return;
+            }
+        };
+
+        self.capabilities = result["capabilities"].clone();
+        // Servers that don't pick an encoding use UTF-16
+        self.encoding = self.capabilities["positionEncoding"]
+            .as_str()
+            .and_then(PositionEncoding::from_name)
+            .unwrap_or(PositionEncoding::Utf16);
+        self.initialized = true;
+
+        self.notify("initialized", json!({}));
+        for message in std::mem::take(&mut self.queued) {
+            self.write(message);
+        }
+    }
+
+    /// Asks the server to shut down, killing it if it doesn't exit in time.
+    pub fn shutdown(&mut self) {
+        let deadline = Instant::now() + Duration::from_millis(500);
+
+        if !self.exited && self.initialized {
+            let id = self.next_id();
+            self.write(Message::Request {
+                id: json!(id),
+                method: "shutdown".to_owned(),
+                params: Value::Null,
+            });
+            // The exit notification may only be sent after the shutdown request was answered
+            loop {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match self.receiver.recv_timeout(timeout) {
+                    Ok(Ok(Message::Response { id: response, .. })) if response == json!(id) => {
+                        break
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(_))
+                    | Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            self.write(Message::Notification {
+                method: "exit".to_owned(),
+                params: Value::Null,
+            });
+        }
+        self.exited = true;
+
+        if let Some(mut process) = self.process.take() {
+            while Instant::now() < deadline {
+                if let Ok(Some(_)) = process.try_wait() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+
+    /// How the server wants to be told about changes: 0 for not at all, 1 for the full text and
+    /// 2 for incremental changes
+    fn sync_kind(&self) -> i64 {
+        match &self.capabilities["textDocumentSync"] {
+            Value::Number(kind) => kind.as_i64().unwrap_or(0),
+            sync => sync["change"].as_i64().unwrap_or(0),
+        }
+    }
+
+    pub fn did_open(&mut self, uri: &str, language_id: &str, buffer: &Buffer) {
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": language_id,
+                    "version": buffer.version,
+                    "text": buffer.lines.join("\n"),
+                },
+            }),
+        );
+    }
+
+    pub fn did_change(&mut self, uri: &str, buffer: &Buffer, changes: &[Change]) {
+        let content_changes = match self.sync_kind() {
+            1 => vec![json!({ "text": buffer.lines.join("\n") })],
+            2 => changes
+                .iter()
+                .map(|change| {
+                    let edit = &change.edit;
+                    let start = json!({
+                        "line": edit.start.y,
+                        "character": self.encoding.encode_column(&change.line, edit.start.x),
+                    });
+                    // The start of the edit's last line is the start column unless it removed line
+                    // breaks, in which case it's the start of the last removed line
+                    let end = match edit.removed.rfind('\n') {
+                        Some(index) => json!({
+                            "line": edit.old_end().y,
+                            "character": self.encoding.length(&edit.removed[index + 1..]),
+                        }),
+                        None => json!({
+                            "line": edit.start.y,
+                            "character": start["character"].as_u64().unwrap() as usize
+                                + self.encoding.length(&edit.removed),
+                        }),
+                    };
+                    json!({
+                        "range": { "start": start, "end": end },
+                        "text": edit.inserted,
+                    })
+                })
+                .collect(),
+            _ => return,
+        };
+
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": uri, "version": buffer.version },
+                "contentChanges": content_changes,
+            }),
+        );
+    }
+
+    pub fn did_save(&mut self, uri: &str) {
+        self.notify(
+            "textDocument/didSave",
+            json!({ "textDocument": { "uri": uri } }),
+        );
+    }
+
+    pub fn did_close(&mut self, uri: &str) {
+        self.notify(
+            "textDocument/didClose",
+            json!({ "textDocument": { "uri": uri } }),
+        );
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+}
+
+/// Completion through a language server. The candidates arrive later as the response to
+/// [`Request::Completion`].
+pub struct LspCompletion<'a> {
+    pub client: &'a mut Client,
+    pub uri: String,
+}
+
+impl CompletionSource for LspCompletion<'_> {
+    fn name(&self) -> &'static str {
+        "lsp"
+    }
+
+    fn complete(&mut self, context: &CompletionContext) -> Option<Vec<CompletionItem>> {
+        if !self.client.supports("completionProvider") {
+            return Some(Vec::new());
+        }
+        let position = self
+            .client
+            .encoding
+            .encode_position(&context.buffers[0].lines, context.cursor);
+        self.client.request(
+            Request::Completion {
+                position: context.cursor,
+            },
+            "textDocument/completion",
+            json!({ "textDocument": { "uri": self.uri }, "position": position }),
+        );
+        None
+    }
+}
+
+/// Converts the result of a completion request to the items of the completion popup, in the
+/// order the server wants them
+pub fn completion_items(result: &Value) -> Vec<CompletionItem> {
+    const KINDS: &[&str] = &[
+        "Text",
+        "Method",
+        "Function",
+        "Constructor",
+        "Field",
+        "Variable",
+        "Class",
+        "Interface",
+        "Module",
+        "Property",
+        "Unit",
+        "Value",
+        "Enum",
+        "Keyword",
+        "Snippet",
+        "Color",
+        "File",
+        "Reference",
+        "Folder",
+        "EnumMember",
+        "Constant",
+        "Struct",
+        "Event",
+        "Operator",
+        "TypeParameter",
+    ];
+
+    let mut items = match result {
+        Value::Array(items) => items.clone(),
+        result => result["items"].as_array().cloned().unwrap_or_default(),
+    };
+    items.sort_by_key(|item| {
+        item["sortText"]
+            .as_str()
+            .or(item["label"].as_str())
+            .unwrap_or_default()
+            .to_owned()
+    });
+
+    items
+        .iter()
+        .map(|item| {
+            let label = item["label"].as_str().unwrap_or_default();
+            let word = item["textEdit"]["newText"]
+                .as_str()
+                .or(item["insertText"].as_str())
+                .unwrap_or(label);
+            let documentation = [
+                item["detail"].as_str().unwrap_or_default().to_owned(),
+                markup_to_text(&item["documentation"]),
+            ]
+            .into_iter()
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+            CompletionItem {
+                word: word.to_owned(),
+                label: label.to_owned(),
+                kind: item["kind"]
+                    .as_u64()
+                    .and_then(|kind| KINDS.get((kind as usize).checked_sub(1)?))
+                    .map(|kind| kind.to_string()),
+                documentation: (!documentation.is_empty()).then_some(documentation),
+            }
+        })
+        .collect()
+}
+
+fn show_message(params: &Value) -> Event {
+    let level = match params["type"].as_i64() {
+        Some(1) => LogLevel::Error,
+        _ => LogLevel::Info,
+    };
+    Event::Message {
+        message: params["message"].as_str().unwrap_or_default().to_owned(),
+        level,
+    }
+}
+
+/// Converts the result of a document symbol request to `(name, kind, position)` triples, with
+/// the symbols nested in others following them
+pub fn document_symbols(result: &Value) -> Vec<(String, Option<String>, Value)> {
+    const KINDS: &[&str] = &[
+        "File",
+        "Module",
+        "Namespace",
+        "Package",
+        "Class",
+        "Method",
+        "Property",
+        "Field",
+        "Constructor",
+        "Enum",
+        "Interface",
+        "Function",
+        "Variable",
+        "Constant",
+        "String",
+        "Number",
+        "Boolean",
+        "Array",
+        "Object",
+        "Key",
+        "Null",
+        "EnumMember",
+        "Struct",
+        "Event",
+        "Operator",
+        "TypeParameter",
+    ];
+
+    fn collect(symbols: &[Value], result: &mut Vec<(String, Option<String>, Value)>) {
+        for symbol in symbols {
+            // DocumentSymbol has a selection range, SymbolInformation a location
+            let range = symbol
+                .get("selectionRange")
+                .or_else(|| symbol.get("location").map(|location| &location["range"]));
+            let (Some(name), Some(range)) = (symbol["name"].as_str(), range) else {
+                continue;
+            };
+            let kind = symbol["kind"]
+                .as_u64()
+                .and_then(|kind| KINDS.get((kind as usize).checked_sub(1)?))
+                .map(|kind| kind.to_string());
+            result.push((name.to_owned(), kind, range["start"].clone()));
+            if let Some(children) = symbol["children"].as_array() {
+                collect(children, result);
+            }
+        }
+    }
+
+    let mut symbols = Vec::new();
+    collect(result.as_array().map_or(&[], Vec::as_slice), &mut symbols);
+    symbols
+}
+
+/// Converts hover contents or documentation, which come in several shapes, to plain text
+pub fn markup_to_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(values) => values
+            .iter()
+            .map(markup_to_text)
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        // MarkupContent and MarkedString both keep their text in value
+        Value::Object(object) => object
+            .get("value")
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_owned(),
+        _ => String::new(),
+    }
+}
+
+/// Converts the different shapes of a definition or references result to a list of `(uri,
+/// range)` pairs.
+pub fn locations(result: &Value) -> Vec<(String, Value)> {
+    let location = |value: &Value| {
+        // Location and LocationLink use different names
+        let uri = value
+            .get("uri")
+            .or_else(|| value.get("targetUri"))?
+            .as_str()?;
+        let range = value
+            .get("range")
+            .or_else(|| value.get("targetSelectionRange"))?;
+        Some((uri.to_owned(), range.clone()))
+    };
+    match result {
+        Value::Array(values) => values.iter().filter_map(location).collect(),
+        Value::Object(_) => location(result).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Converts a path to a `file://` URI, making it absolute first.
+pub fn path_to_uri(path: &Path) -> String {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| match std::env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path.to_path_buf(),
+    });
+
+    let mut uri = "file://".to_owned();
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            byte => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%' && i + 2 < encoded.len() {
+            let hex = std::str::from_utf8(&encoded[i + 1..i + 3]).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(encoded[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{PipeWriter, Read},
+        sync::mpsc::Sender,
+    };
+
+    use super::*;
+
+    const URI: &str = "file:///project/src/main.rs";
+
+    /// A language server on the other end of a pair of pipes. It answers requests and publishes a
+    /// diagnostic for every version of a document it's told about, and hands every message it
+    /// receives to the test.
+    struct FakeServer {
+        received: Receiver<Message>,
+        thread: thread::JoinHandle<()>,
+    }
+
+    impl FakeServer {
+        fn start() -> (Client, FakeServer) {
+            let (client_reader, server_writer) = io::pipe().unwrap();
+            let (server_reader, client_writer) = io::pipe().unwrap();
+            let (sender, received) = mpsc::channel();
+            let thread = thread::spawn(move || serve(server_reader, server_writer, sender));
+            let client = Client::new("fake", client_reader, client_writer, &std::env::temp_dir());
+            (client, FakeServer { received, thread })
+        }
+
+        fn next_message(&self) -> Message {
+            self.received
+                .recv_timeout(Duration::from_secs(5))
+                .expect("the client didn't send a message")
+        }
+
+        /// Returns the method and params of the next message, which has to be a notification
+        fn next_notification(&self) -> (String, Value) {
+            match self.next_message() {
+                Message::Notification { method, params } => (method, params),
+                message => panic!("expected a notification, got {:?}", message),
+            }
+        }
+    }
+
+    fn serve(reader: impl Read, mut writer: PipeWriter, sender: Sender<Message>) {
+        let mut reader = BufReader::new(reader);
+        while let Ok(Some(message)) = jsonrpc::read_message(&mut reader) {
+            let _ = sender.send(message.clone());
+            let reply = match message {
+                Message::Request { id, method, params } => {
+                    let result = match method.as_str() {
+                        "initialize" => json!({
+                            "capabilities": {
+                                "positionEncoding": "utf-16",
+                                "textDocumentSync": { "openClose": true, "change": 2 },
+                                "hoverProvider": true,
+                                "definitionProvider": true,
+                            },
+                        }),
+                        "textDocument/hover" => json!({
+                            "contents": { "kind": "markdown", "value": "fn main()" },
+                            "range": params["position"].clone(),
+                        }),
+                        "textDocument/definition" => json!([{
+                            "targetUri": params["textDocument"]["uri"],
+                            "targetRange": {
+                                "start": { "line": 0, "character": 0 },
+                                "end": { "line": 2, "character": 1 },
+                            },
+                            "targetSelectionRange": {
+                                "start": { "line": 0, "character": 3 },
+                                "end": { "line": 0, "character": 7 },
+                            },
+                        }]),
+                        _ => Value::Null,
+                    };
+                    Message::Response {
+                        id,
+                        result: Ok(result),
+                    }
+                }
+                Message::Notification { method, params }
+                    if method == "textDocument/didOpen" || method == "textDocument/didChange" =>
+                {
+                    let document = &params["textDocument"];
+                    Message::Notification {
+                        method: "textDocument/publishDiagnostics".to_owned(),
+                        params: json!({
+                            "uri": document["uri"],
+                            "version": document["version"],
+                            "diagnostics": [{
+                                "range": {
+                                    "start": { "line": 1, "character": 8 },
+                                    "end": { "line": 1, "character": 10 },
+                                },
+                                "severity": 2,
+                                "message": "unused variable",
+                            }],
+                        }),
+                    }
+                }
+                Message::Notification { method, .. } if method == "exit" => return,
+                _ => continue,
+            };
+            if jsonrpc::write_message(&mut writer, &reply).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Polls the client until `done` returns true for it and the events so far
+    fn poll_until(
+        client: &mut Client,
+        mut done: impl FnMut(&Client, &[Event]) -> bool,
+    ) -> Vec<Event> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut events = Vec::new();
+        while !done(client, &events) {
+            assert!(
+                Instant::now() < deadline,
+                "timed out with events {:?}",
+                events
+            );
+            events.extend(client.poll());
+            thread::sleep(Duration::from_millis(5));
+        }
+        events
+    }
+
+    fn buffer(text: &str) -> Buffer {
+        let mut buffer = Buffer::new();
+        buffer.lines = text.split('\n').map(str::to_owned).collect();
+        buffer
+    }
+
+    #[test]
+    fn initializes_and_sends_queued_messages_afterwards() {
+        let (mut client, server) = FakeServer::start();
+        // Opened before the server answered, so it has to wait for initialization
+        client.did_open(URI, "rust", &buffer("fn main() {}"));
+
+        let Message::Request { method, params, .. } = server.next_message() else {
+            panic!("expected the initialize request");
+        };
+        assert_eq!(method, "initialize");
+        assert_eq!(params["clientInfo"]["name"], "bvim");
+        assert_eq!(
+            params["rootUri"],
+            path_to_uri(&std::env::temp_dir()).as_str()
+        );
+
+        poll_until(&mut client, |client, _| client.is_initialized());
+        assert_eq!(client.encoding, PositionEncoding::Utf16);
+        assert!(client.supports("hoverProvider"));
+        assert!(!client.supports("renameProvider"));
+
+        assert_eq!(server.next_notification().0, "initialized");
+        let (method, params) = server.next_notification();
+        assert_eq!(method, "textDocument/didOpen");
+        assert_eq!(params["textDocument"]["uri"], URI);
+        assert_eq!(params["textDocument"]["languageId"], "rust");
+        assert_eq!(params["textDocument"]["text"], "fn main() {}");
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn sends_incremental_changes_in_utf16() {
+        let (mut client, server) = FakeServer::start();
+        poll_until(&mut client, |client, _| client.is_initialized());
+        server.next_message();
+        server.next_message();
+
+        let mut buffer = buffer("fn main() {\n    let 😀 = 1;\n}");
+        buffer.record_changes(true);
+        // After the emoji, which takes two UTF-16 units
+        buffer.insert_text(Position::new(9, 1), "x");
+        // Joins the first two lines
+        buffer.remove_text(Position::new(11, 0), Position::new(0, 1));
+        // Replaces text spanning both remaining lines
+        buffer.replace_text(Position::new(4, 0), Position::new(1, 1), "a\nb");
+        let changes = buffer.take_changes();
+        client.did_change(URI, &buffer, &changes);
+
+        let (method, params) = server.next_notification();
+        assert_eq!(method, "textDocument/didChange");
+        assert_eq!(params["textDocument"]["version"], 3);
+        let range = |start: (u64, u64), end: (u64, u64)| {
+            json!({
+                "start": { "line": start.0, "character": start.1 },
+                "end": { "line": end.0, "character": end.1 },
+            })
+        };
+        let changes = params["contentChanges"].as_array().unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0]["range"], range((1, 10), (1, 10)));
+        assert_eq!(changes[0]["text"], "x");
+        assert_eq!(changes[1]["range"], range((0, 11), (1, 0)));
+        assert_eq!(changes[1]["text"], "");
+        assert_eq!(changes[2]["range"], range((0, 4), (1, 1)));
+        assert_eq!(changes[2]["text"], "a\nb");
+        assert_eq!(buffer.lines, ["fn ma", "b"]);
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn matches_responses_to_requests() {
+        let (mut client, _server) = FakeServer::start();
+        poll_until(&mut client, |client, _| client.is_initialized());
+
+        let position = json!({ "line": 0, "character": 4 });
+        client.request(
+            Request::Hover,
+            "textDocument/hover",
+            json!({ "textDocument": { "uri": URI }, "position": position }),
+        );
+        client.request(
+            Request::Definition,
+            "textDocument/definition",
+            json!({ "textDocument": { "uri": URI }, "position": position }),
+        );
+        let events = poll_until(&mut client, |_, events| events.len() >= 2);
+
+        let Event::Response {
+            request: Request::Hover,
+            result: Ok(hover),
+        } = &events[0]
+        else {
+            panic!("expected the hover response, got {:?}", events[0]);
+        };
+        assert_eq!(markup_to_text(&hover["contents"]), "fn main()");
+
+        let Event::Response {
+            request: Request::Definition,
+            result: Ok(definition),
+        } = &events[1]
+        else {
+            panic!("expected the definition response, got {:?}", events[1]);
+        };
+        let locations = locations(definition);
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].0, URI);
+        assert_eq!(locations[0].1["start"]["character"], 3);
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn reports_published_diagnostics() {
+        let (mut client, _server) = FakeServer::start();
+        let mut buffer = buffer("fn main() {\n    let x = 1;\n}");
+        buffer.version = 4;
+        client.did_open(URI, "rust", &buffer);
+
+        let events = poll_until(&mut client, |_, events| !events.is_empty());
+        let Event::Diagnostics {
+            uri,
+            version,
+            diagnostics,
+        } = &events[0]
+        else {
+            panic!("expected diagnostics, got {:?}", events[0]);
+        };
+        assert_eq!(uri, URI);
+        assert_eq!(*version, Some(4));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["message"], "unused variable");
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn shuts_down_and_exits() {
+        let (mut client, server) = FakeServer::start();
+        poll_until(&mut client, |client, _| client.is_initialized());
+        client.shutdown();
+
+        // The server stops after exit, which closes the connection
+        server.thread.join().unwrap();
+        let methods = server
+            .received
+            .try_iter()
+            .map(|message| match message {
+                Message::Request { method, .. } | Message::Notification { method, .. } => method,
+                Message::Response { .. } => "response".to_owned(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(methods, ["initialize", "initialized", "shutdown", "exit"]);
+        // Without an error event, since the client expected it
+        assert!(client.poll().is_empty());
+    }
+}
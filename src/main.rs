@@ -1,18 +1,47 @@
 use std::{
     env::args,
     io::{stdout, Write},
+    panic::{self, AssertUnwindSafe},
     process::exit,
+    thread,
 };
 
 use crossterm::{
+    cursor::{SetCursorStyle, Show},
+    event::{DisableFocusChange, DisableMouseCapture},
     terminal::{self, LeaveAlternateScreen},
     QueueableCommand,
 };
 use editor::Editor;
 
 mod buffer;
+mod cmdline;
+mod completion;
+mod config;
+mod diagnostics;
+mod directory;
 mod editor;
+mod encoding;
+mod fileio;
+mod finder;
+mod fuzzy;
+mod hex;
+mod highlight;
+mod jsonrpc;
+mod jumplist;
 mod keymap;
+mod keys;
+mod largefile;
+mod lsp;
+mod make;
+mod motion;
+mod options;
+mod picker;
+mod quickfix;
+mod register;
+mod screen;
+mod swap;
+mod syntax;
 mod util;
 mod window;
 
@@ -20,11 +49,15 @@ const HELP_MESSAGE: &str = "\
 USAGE: bvim [OPTIONS] [file]
 
 Options:
-  -h, --help  Print this help message \
+  -h, --help     Print this help message
+  -R             Open the file read-only
+  -s <scriptin>  Type the keys written in key notation in <scriptin> \
 ";
 
 fn main() {
     let mut filepath: Option<String> = None;
+    let mut script: Option<String> = None;
+    let mut readonly = false;
 
     let mut args = args();
     args.next(); // void program path
@@ -39,6 +72,30 @@ fn main() {
             exit(0);
         }
 
+        if arg == "-R" {
+            readonly = true;
+            continue;
+        }
+
+        if arg == "-s" {
+            let path = match args.next() {
+                Some(path) => path,
+                None => {
+                    eprintln!("bvim: error: argument missing after -s");
+                    exit(-1);
+                }
+            };
+            match std::fs::read_to_string(&path) {
+                // Line breaks are typed as Enter, like the rest of the file
+                Ok(contents) => script = Some(contents.replace("\r\n", "\n").replace('\n', "<CR>")),
+                Err(e) => {
+                    eprintln!("bvim: error: cannot read script file {}: {}", path, e);
+                    exit(-1);
+                }
+            }
+            continue;
+        }
+
         if filepath.is_some() {
             eprintln!("bvim: error: multiple files supplied");
             exit(-1);
@@ -46,14 +103,97 @@ fn main() {
         filepath = Some(arg);
     }
 
-    let mut editor = Editor::new(filepath).unwrap();
+    // Restore the terminal before the panic message is printed so it's readable. Panics of
+    // other threads don't stop the editor, which shows them in the message line instead.
+    let main_thread = thread::current().id();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let thread = thread::current();
+        if thread.id() != main_thread {
+            let payload = info
+                .payload()
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("Box<dyn Any>");
+            let location = info
+                .location()
+                .map(|location| format!(" at {}", location))
+                .unwrap_or_default();
+            editor::report_thread_panic(format!(
+                "thread '{}' panicked{}: {}",
+                thread.name().unwrap_or("<unnamed>"),
+                location,
+                payload
+            ));
+            return;
+        }
+        restore_terminal();
+        default_hook(info);
+    }));
+
+    let guard = TerminalGuard;
+    let mut editor = match Editor::new(filepath, readonly) {
+        Ok(editor) => editor,
+        Err(e) => {
+            drop(guard);
+            eprintln!("bvim: error: {}", e);
+            exit(1);
+        }
+    };
+    if let Some(script) = script {
+        editor.feed_keys(&script);
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| editor.run()));
+    drop(guard);
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            eprintln!("bvim: error: {}", e);
+            emergency_save(&editor);
+            exit(1);
+        }
+        Err(_) => {
+            emergency_save(&editor);
+            exit(101);
+        }
+    }
+}
+
+/// Restores the terminal when dropped, so it's also restored if the editor fails
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// Leaves the alternate screen and raw mode and disables mouse capture and focus reporting.
+/// Errors are ignored as this runs when things already went wrong.
+fn restore_terminal() {
+    let mut stdout = stdout();
+    let _ = stdout.queue(DisableMouseCapture);
+    let _ = stdout.queue(DisableFocusChange);
+    let _ = stdout.queue(SetCursorStyle::DefaultUserShape);
+    let _ = stdout.queue(Show);
+    let _ = stdout.queue(LeaveAlternateScreen);
+    let _ = stdout.flush();
+    let _ = terminal::disable_raw_mode();
+}
 
-    match editor.run() {
-        Ok(()) => {
-            stdout().queue(LeaveAlternateScreen).unwrap();
-            stdout().flush().unwrap();
-            terminal::disable_raw_mode().unwrap();
+/// Writes the modified buffers of an editor that crashed to the recovery directory and tells
+/// the user where they went
+fn emergency_save(editor: &Editor) {
+    match editor.emergency_save() {
+        Ok(paths) if paths.is_empty() => {}
+        Ok(paths) => {
+            eprintln!("bvim: unsaved changes were written to:");
+            for path in paths {
+                eprintln!("  {}", path.display());
+            }
         }
-        e => e.unwrap(),
+        Err(e) => eprintln!("bvim: error: unable to save unsaved changes: {}", e),
     }
 }
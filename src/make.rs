@@ -0,0 +1,410 @@
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread,
+};
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::{
+    quickfix::{Entry, ErrorFormat},
+    util::Position,
+};
+
+/// A run of 'makeprg' in the background. Its output is read on separate threads and turned into
+/// quickfix entries as it arrives.
+pub struct Make {
+    child: Child,
+    /// Delivers the lines of stdout and stderr, `None` once both are closed
+    receiver: Option<Receiver<String>>,
+    parser: OutputParser,
+    entries: Vec<Entry>,
+}
+
+impl Make {
+    /// Runs the command with the shell. Lines of output that no other format matches are parsed
+    /// with `format`.
+    pub fn start(command: &str, format: ErrorFormat) -> io::Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (sender, receiver) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            forward_lines(stdout, sender.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forward_lines(stderr, sender);
+        }
+        Ok(Self {
+            child,
+            receiver: Some(receiver),
+            parser: OutputParser::new(format),
+            entries: Vec::new(),
+        })
+    }
+
+    /// Parses the output that arrived since the last call and returns the lines that aren't
+    /// messages, e.g. cargo's progress
+    pub fn poll(&mut self) -> Vec<String> {
+        let Some(receiver) = &self.receiver else {
+            return Vec::new();
+        };
+        let mut other = Vec::new();
+        loop {
+            match receiver.try_recv() {
+                Ok(line) => match self.parser.parse_line(&line) {
+                    Parsed::Entry(entry) => self.entries.push(entry),
+                    Parsed::Nothing => {}
+                    Parsed::Other => other.push(line),
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.receiver = None;
+                    break;
+                }
+            }
+        }
+        other
+    }
+
+    /// Whether all output was read, which means the program is done
+    pub fn is_done(&self) -> bool {
+        self.receiver.is_none()
+    }
+
+    /// Waits for the program to exit and returns its exit status and the entries found in its
+    /// output
+    pub fn finish(&mut self) -> io::Result<(ExitStatus, Vec<Entry>)> {
+        let status = self.child.wait()?;
+        Ok((status, std::mem::take(&mut self.entries)))
+    }
+}
+
+impl Drop for Make {
+    /// Stops the program if the editor quits while it's running
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// The index of the entry to jump to once the build is done, which is the first error. Warnings
+/// often come before it, e.g. from crates built earlier.
+pub fn first_error(entries: &[Entry]) -> Option<usize> {
+    entries.iter().position(|entry| entry.kind == Some('E'))
+}
+
+fn forward_lines<R: Read + Send + 'static>(reader: R, sender: Sender<String>) {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// What a line of output turned out to be
+#[derive(Debug, PartialEq, Eq)]
+enum Parsed {
+    Entry(Entry),
+    /// A message without a location or the first line of one that continues on the next
+    Nothing,
+    Other,
+}
+
+/// Turns the output of a build into quickfix entries. It understands cargo's
+/// `--message-format=json`, the human readable format of rustc and cargo, and falls back to
+/// 'errorformat' for everything else.
+struct OutputParser {
+    format: ErrorFormat,
+    /// `error[E0425]: cannot find value `x` in this scope`
+    header: Regex,
+    /// ` --> src/main.rs:2:5`
+    location: Regex,
+    /// The kind and text of the last `error: ...` line, which rustc follows with a line
+    /// pointing at the location
+    pending: Option<(char, String)>,
+}
+
+impl OutputParser {
+    fn new(format: ErrorFormat) -> Self {
+        Self {
+            format,
+            header: Regex::new(r"^(error|warning|note|help)(?:\[\w+\])?: (.*)$").unwrap(),
+            location: Regex::new(r"^\s*--> (.+):(\d+):(\d+)$").unwrap(),
+            pending: None,
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) -> Parsed {
+        if line.starts_with('{') {
+            if let Ok(message) = serde_json::from_str::<Value>(line) {
+                return match cargo_message(&message) {
+                    Some(entry) => Parsed::Entry(entry),
+                    None => Parsed::Nothing,
+                };
+            }
+        }
+
+        if let Some(captures) = self.header.captures(line) {
+            let kind = message_kind(&captures[1]);
+            self.pending = Some((kind, captures[2].to_owned()));
+            return Parsed::Nothing;
+        }
+        if let Some(captures) = self.location.captures(line) {
+            // Only the first location after a message is where it happened
+            if let Some((kind, text)) = self.pending.take() {
+                let number = |i: usize| captures[i].parse::<usize>().unwrap_or(1).saturating_sub(1);
+                return Parsed::Entry(Entry {
+                    path: absolute(Path::new(&captures[1])),
+                    position: Position::new(number(3), number(2)),
+                    text,
+                    kind: Some(kind),
+                });
+            }
+            return Parsed::Nothing;
+        }
+
+        match self.format.parse_line(line) {
+            Some(entry) => Parsed::Entry(entry),
+            None => Parsed::Other,
+        }
+    }
+}
+
+/// Converts a `compiler-message` of cargo's JSON output to an entry at its primary span.
+/// Messages without a location, like the number of warnings emitted, are left out.
+fn cargo_message(message: &Value) -> Option<Entry> {
+    if message["reason"] != "compiler-message" {
+        return None;
+    }
+    let diagnostic = &message["message"];
+    let span = diagnostic["spans"]
+        .as_array()?
+        .iter()
+        .find(|span| span["is_primary"] == true)?;
+    let number = |value: &Value| value.as_u64().unwrap_or(1).saturating_sub(1) as usize;
+
+    // File names are relative to the workspace root, which is the package's directory or one of
+    // its parents
+    let file_name = Path::new(span["file_name"].as_str()?);
+    let manifest = message["manifest_path"].as_str().map(Path::new);
+    let path = manifest
+        .and_then(Path::parent)
+        .and_then(|directory| {
+            directory
+                .ancestors()
+                .map(|directory| directory.join(file_name))
+                .find(|path| path.exists())
+        })
+        .unwrap_or_else(|| absolute(file_name));
+
+    Some(Entry {
+        path,
+        position: Position::new(number(&span["column_start"]), number(&span["line_start"])),
+        text: diagnostic["message"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned(),
+        kind: Some(message_kind(
+            diagnostic["level"].as_str().unwrap_or_default(),
+        )),
+    })
+}
+
+fn message_kind(level: &str) -> char {
+    match level {
+        "warning" => 'W',
+        "note" => 'N',
+        "help" => 'I',
+        // Including internal compiler errors
+        _ => 'E',
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// `cargo build --message-format=json` of a workspace with the packages `app` and `util` in
+    /// `/tmp/mkws`, where `util` has a warning and `app` three errors
+    const CARGO_JSON: &str = include_str!("../tests/fixtures/cargo-build.json");
+    /// The same build without `--message-format`, as cargo prints it to stderr
+    const CARGO_HUMAN: &str = include_str!("../tests/fixtures/cargo-build.txt");
+    const RECORDED_ROOT: &str = "/tmp/mkws";
+
+    /// A copy of the recorded workspace's sources, so paths can be resolved against it
+    struct Workspace(PathBuf);
+
+    impl Workspace {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("bvim-{}-{}", name, std::process::id()));
+            for file in ["app/src/main.rs", "app/src/parse.rs", "util/src/lib.rs"] {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, "").unwrap();
+            }
+            Self(root)
+        }
+
+        /// The JSON output as if the build had run in this workspace
+        fn cargo_json(&self) -> String {
+            CARGO_JSON.replace(RECORDED_ROOT, &self.0.to_string_lossy())
+        }
+    }
+
+    impl Drop for Workspace {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn parser() -> OutputParser {
+        OutputParser::new(ErrorFormat::new(&["%f:%l:%c: %m".to_owned()]).unwrap())
+    }
+
+    fn entry(path: PathBuf, line: usize, column: usize, kind: char, text: &str) -> Entry {
+        Entry {
+            path,
+            position: Position::new(column - 1, line - 1),
+            text: text.to_owned(),
+            kind: Some(kind),
+        }
+    }
+
+    /// The messages of the recorded build, with paths relative to the workspace
+    fn expected(root: &Path) -> Vec<Entry> {
+        vec![
+            entry(
+                root.join("util/src/lib.rs"),
+                2,
+                9,
+                'W',
+                "unused variable: `unused`",
+            ),
+            entry(
+                root.join("app/src/main.rs"),
+                5,
+                20,
+                'E',
+                "cannot find value `missing` in this scope",
+            ),
+            entry(
+                root.join("app/src/parse.rs"),
+                3,
+                18,
+                'E',
+                "mismatched types",
+            ),
+            entry(root.join("app/src/main.rs"), 4, 22, 'E', "mismatched types"),
+        ]
+    }
+
+    #[test]
+    fn parses_cargo_json() {
+        let workspace = Workspace::new("json");
+        let mut parser = parser();
+        let mut entries = Vec::new();
+        for line in workspace.cargo_json().lines() {
+            match parser.parse_line(line) {
+                Parsed::Entry(entry) => entries.push(entry),
+                Parsed::Nothing => {}
+                Parsed::Other => panic!("not recognized as a message: {}", line),
+            }
+        }
+        // File names are relative to the workspace root, not to the package
+        assert_eq!(entries, expected(&workspace.0));
+        assert_eq!(first_error(&entries), Some(1));
+    }
+
+    #[test]
+    fn parses_rustc_output() {
+        let mut parser = parser();
+        let mut entries = Vec::new();
+        let mut other = Vec::new();
+        for line in CARGO_HUMAN.lines() {
+            match parser.parse_line(line) {
+                Parsed::Entry(entry) => entries.push(entry),
+                Parsed::Nothing => {}
+                Parsed::Other => other.push(line),
+            }
+        }
+        // cargo prints paths relative to the directory it runs in
+        assert_eq!(entries, expected(&std::env::current_dir().unwrap()));
+        assert_eq!(first_error(&entries), Some(1));
+        assert!(other.contains(&"   Compiling app v0.1.0 (/tmp/mkws/app)"));
+    }
+
+    #[test]
+    fn falls_back_to_errorformat() {
+        let mut parser = parser();
+        assert_eq!(
+            parser.parse_line("src/lib.rs:3:7: something odd"),
+            Parsed::Entry(Entry {
+                path: absolute(Path::new("src/lib.rs")),
+                position: Position::new(6, 2),
+                text: "something odd".to_owned(),
+                kind: None,
+            })
+        );
+        assert_eq!(parser.parse_line("Finished in 0.1s"), Parsed::Other);
+        // Not every line starting with a brace is a message of cargo
+        assert_eq!(parser.parse_line("{ not json"), Parsed::Other);
+    }
+
+    #[test]
+    fn first_error_skips_warnings() {
+        let path = PathBuf::from("/src/lib.rs");
+        let mut entries = vec![entry(path.clone(), 1, 1, 'W', "warning")];
+        assert_eq!(first_error(&entries), None);
+        entries.push(entry(path.clone(), 2, 1, 'N', "note"));
+        entries.push(entry(path.clone(), 3, 1, 'E', "error"));
+        entries.push(entry(path, 4, 1, 'E', "error"));
+        assert_eq!(first_error(&entries), Some(2));
+    }
+
+    #[test]
+    fn runs_the_command_and_reads_both_streams() {
+        let workspace = Workspace::new("run");
+        let output = workspace.0.join("output.json");
+        fs::write(&output, workspace.cargo_json()).unwrap();
+        let command = format!(
+            "echo '   Compiling app' >&2; cat '{}'; exit 101",
+            output.display()
+        );
+        let mut make = Make::start(&command, ErrorFormat::new(&[]).unwrap()).unwrap();
+
+        let mut other = Vec::new();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !make.is_done() {
+            assert!(std::time::Instant::now() < deadline, "make didn't finish");
+            other.extend(make.poll());
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+        other.extend(make.poll());
+        let (status, entries) = make.finish().unwrap();
+
+        assert_eq!(status.code(), Some(101));
+        assert_eq!(other, ["   Compiling app"]);
+        assert_eq!(entries, expected(&workspace.0));
+    }
+}
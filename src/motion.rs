@@ -0,0 +1,225 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Commands that work on the lines a motion moves over, like `!` and `d`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operator {
+    /// `!`, which filters the lines through a command
+    Filter,
+    Delete,
+    Yank,
+}
+
+impl Operator {
+    /// The key that starts the operator, and repeated works on whole lines like `dd`
+    fn key(self) -> char {
+        match self {
+            Operator::Filter => '!',
+            Operator::Delete => 'd',
+            Operator::Yank => 'y',
+        }
+    }
+}
+
+/// The motions operators accept. They all move over whole lines, like in vim.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Motion {
+    /// The operator's key typed again: the cursor line and the lines below it for a count
+    Lines,
+    /// `j`, `+` or Enter
+    Down,
+    /// `k` or `-`
+    Up,
+    /// `G`, to the last line or the line given by the count
+    LastLine,
+    /// `gg`, to the first line or the line given by the count
+    FirstLine,
+    /// `'` followed by the name of a mark, to the mark's line
+    MarkLine(char),
+}
+
+/// What the keys typed after an operator amount to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Parsed {
+    /// More keys are needed, e.g. after the `g` of `gg`
+    Pending,
+    /// The motion and its count, which multiplies the counts before and after the operator
+    Motion(Motion, Option<usize>),
+    /// The key isn't a motion or cancels the operator, like Esc
+    Cancel,
+}
+
+/// An operator waiting for its motion, which is read a key at a time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingOperator {
+    pub operator: Operator,
+    /// The register selected with `"` before the operator
+    pub register: Option<char>,
+    count: Option<usize>,
+    /// The count typed after the operator, like the 3 of `d3j`
+    motion_count: Option<usize>,
+    /// The first key of a motion of two keys, `g` or `'`
+    prefix: Option<char>,
+}
+
+impl PendingOperator {
+    pub fn new(operator: Operator, count: Option<usize>, register: Option<char>) -> Self {
+        Self {
+            operator,
+            register,
+            count,
+            motion_count: None,
+            prefix: None,
+        }
+    }
+
+    /// Reads the next key typed after the operator
+    pub fn key(&mut self, event: KeyEvent) -> Parsed {
+        let control = event.modifiers.contains(KeyModifiers::CONTROL);
+        let c = match event.code {
+            KeyCode::Char(c) if !control => Some(c),
+            _ => None,
+        };
+
+        if let Some(prefix) = self.prefix.take() {
+            let motion = match (prefix, c) {
+                ('g', Some('g')) => Motion::FirstLine,
+                ('\'', Some(name)) => Motion::MarkLine(name),
+                _ => return Parsed::Cancel,
+            };
+            return self.motion(motion);
+        }
+
+        if let Some(digit) = c.and_then(|c| c.to_digit(10)) {
+            if digit != 0 || self.motion_count.is_some() {
+                let count = self.motion_count.unwrap_or(0);
+                self.motion_count = Some(count.saturating_mul(10).saturating_add(digit as usize));
+                return Parsed::Pending;
+            }
+        }
+
+        let motion = match (event.code, c) {
+            (_, Some(c)) if c == self.operator.key() => Motion::Lines,
+            (_, Some('j' | '+')) | (KeyCode::Down | KeyCode::Enter, _) => Motion::Down,
+            (_, Some('k' | '-')) | (KeyCode::Up, _) => Motion::Up,
+            (KeyCode::Char('n' | 'j'), None) if control => Motion::Down,
+            (KeyCode::Char('p'), None) if control => Motion::Up,
+            (_, Some('G')) => Motion::LastLine,
+            (_, Some(prefix @ ('g' | '\''))) => {
+                self.prefix = Some(prefix);
+                return Parsed::Pending;
+            }
+            _ => return Parsed::Cancel,
+        };
+        self.motion(motion)
+    }
+
+    fn motion(&self, motion: Motion) -> Parsed {
+        let count = match (self.count, self.motion_count) {
+            (Some(count), Some(motion_count)) => Some(count.saturating_mul(motion_count)),
+            (count, motion_count) => count.or(motion_count),
+        };
+        Parsed::Motion(motion, count)
+    }
+}
+
+/// Returns the line a motion moves to from the cursor line in a buffer of `line_count` lines,
+/// or `None` if it can't move, like `j` on the last line. Marks are looked up by the editor.
+pub fn target_line(
+    motion: Motion,
+    count: Option<usize>,
+    cursor: usize,
+    line_count: usize,
+) -> Option<usize> {
+    let last = line_count - 1;
+    let steps = count.unwrap_or(1).max(1);
+    match motion {
+        Motion::Lines => Some(cursor.saturating_add(steps - 1).min(last)),
+        Motion::Down if cursor < last => Some(cursor.saturating_add(steps).min(last)),
+        Motion::Up if cursor > 0 => Some(cursor.saturating_sub(steps)),
+        Motion::Down | Motion::Up => None,
+        Motion::LastLine => Some(count.map_or(last, |line| line.max(1) - 1).min(last)),
+        Motion::FirstLine => Some(count.map_or(0, |line| line.max(1) - 1).min(last)),
+        Motion::MarkLine(_) => None,
+    }
+}
+
+/// Formats the lines from `first` to `last` as the range of a command line started at `first`,
+/// like vim does for `!{motion}`
+pub fn format_range(first: usize, last: usize, line_count: usize) -> String {
+    if first == last {
+        ".".to_owned()
+    } else if last == line_count - 1 {
+        ".,$".to_owned()
+    } else {
+        format!(".,.+{}", last - first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::parse_keys;
+
+    fn parse(operator: Operator, count: Option<usize>, keys: &str) -> Vec<Parsed> {
+        let mut pending = PendingOperator::new(operator, count, None);
+        parse_keys(keys, "")
+            .into_iter()
+            .map(|key| pending.key(key))
+            .collect()
+    }
+
+    #[test]
+    fn parses_motions_with_counts() {
+        use Parsed::*;
+        assert_eq!(
+            parse(Operator::Filter, None, "!"),
+            [Motion(super::Motion::Lines, None)]
+        );
+        assert_eq!(
+            parse(Operator::Delete, Some(2), "3j"),
+            [Pending, Motion(super::Motion::Down, Some(6))]
+        );
+        assert_eq!(
+            parse(Operator::Yank, None, "10gg"),
+            [
+                Pending,
+                Pending,
+                Pending,
+                Motion(super::Motion::FirstLine, Some(10))
+            ]
+        );
+        assert_eq!(
+            parse(Operator::Filter, None, "'a"),
+            [Pending, Motion(super::Motion::MarkLine('a'), None)]
+        );
+        assert_eq!(parse(Operator::Filter, None, "<Esc>"), [Cancel]);
+        assert_eq!(parse(Operator::Filter, None, "gj"), [Pending, Cancel]);
+        // 0 is no count but not a motion either
+        assert_eq!(parse(Operator::Filter, None, "0"), [Cancel]);
+    }
+
+    #[test]
+    fn finds_the_target_line() {
+        assert_eq!(target_line(Motion::Lines, Some(3), 5, 10), Some(7));
+        assert_eq!(target_line(Motion::Lines, Some(30), 5, 10), Some(9));
+        assert_eq!(target_line(Motion::Down, None, 5, 10), Some(6));
+        assert_eq!(target_line(Motion::Down, Some(30), 5, 10), Some(9));
+        assert_eq!(target_line(Motion::Down, None, 9, 10), None);
+        assert_eq!(target_line(Motion::Up, Some(2), 5, 10), Some(3));
+        assert_eq!(target_line(Motion::Up, None, 0, 10), None);
+        assert_eq!(target_line(Motion::LastLine, None, 5, 10), Some(9));
+        assert_eq!(target_line(Motion::LastLine, Some(3), 5, 10), Some(2));
+        assert_eq!(target_line(Motion::FirstLine, None, 5, 10), Some(0));
+        assert_eq!(
+            target_line(Motion::FirstLine, Some(usize::MAX), 5, 10),
+            Some(9)
+        );
+    }
+
+    #[test]
+    fn formats_ranges_like_vim() {
+        assert_eq!(format_range(4, 4, 10), ".");
+        assert_eq!(format_range(4, 6, 10), ".,.+2");
+        assert_eq!(format_range(4, 9, 10), ".,$");
+    }
+}
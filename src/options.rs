@@ -0,0 +1,680 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{directory, encoding};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OptionScope {
+    Global,
+    Buffer,
+    Window,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OptionType {
+    Bool,
+    Int,
+    String,
+    List,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionValue {
+    Bool(bool),
+    Int(i64),
+    String(String),
+    List(Vec<String>),
+}
+
+impl OptionValue {
+    fn get_type(&self) -> OptionType {
+        match self {
+            OptionValue::Bool(_) => OptionType::Bool,
+            OptionValue::Int(_) => OptionType::Int,
+            OptionValue::String(_) => OptionType::String,
+            OptionValue::List(_) => OptionType::List,
+        }
+    }
+}
+
+impl Display for OptionValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptionValue::Bool(value) => write!(f, "{}", value),
+            OptionValue::Int(value) => write!(f, "{}", value),
+            OptionValue::String(value) => write!(f, "{}", value),
+            OptionValue::List(values) => write!(f, "{}", values.join(",")),
+        }
+    }
+}
+
+// Defaults have to be constructible in a const context, which OptionValue isn't because of its
+// String and Vec variants
+#[derive(Debug)]
+enum DefaultValue {
+    Bool(bool),
+    Int(i64),
+    String(&'static str),
+    List(&'static [&'static str]),
+}
+
+impl DefaultValue {
+    fn to_value(&self) -> OptionValue {
+        match self {
+            DefaultValue::Bool(value) => OptionValue::Bool(*value),
+            DefaultValue::Int(value) => OptionValue::Int(*value),
+            DefaultValue::String(value) => OptionValue::String(value.to_string()),
+            DefaultValue::List(values) => {
+                OptionValue::List(values.iter().map(|value| value.to_string()).collect())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OptionInfo {
+    pub name: &'static str,
+    pub short_name: Option<&'static str>,
+    pub scope: OptionScope,
+    default: DefaultValue,
+    /// The values a string option is restricted to, if any
+    values: Option<&'static [&'static str]>,
+    /// Whether a string option may be set to an empty string
+    allow_empty: bool,
+}
+
+impl OptionInfo {
+    const fn new(
+        name: &'static str,
+        short_name: Option<&'static str>,
+        scope: OptionScope,
+        default: DefaultValue,
+    ) -> Self {
+        Self {
+            name,
+            short_name,
+            scope,
+            default,
+            values: None,
+            allow_empty: true,
+        }
+    }
+
+    const fn with_values(mut self, values: &'static [&'static str]) -> Self {
+        self.values = Some(values);
+        self
+    }
+
+    const fn non_empty(mut self) -> Self {
+        self.allow_empty = false;
+        self
+    }
+
+    pub fn get_type(&self) -> OptionType {
+        self.default.to_value().get_type()
+    }
+
+    pub fn default_value(&self) -> OptionValue {
+        self.default.to_value()
+    }
+}
+
+const OPTIONS: &[OptionInfo] = &[
+    // Global options
+    OptionInfo::new(
+        "autoread",
+        Some("ar"),
+        OptionScope::Global,
+        DefaultValue::Bool(false),
+    ),
+    OptionInfo::new(
+        "backup",
+        Some("bk"),
+        OptionScope::Global,
+        DefaultValue::Bool(false),
+    ),
+    OptionInfo::new(
+        "backupcopy",
+        Some("bkc"),
+        OptionScope::Global,
+        DefaultValue::String("auto"),
+    )
+    .with_values(&["auto", "yes", "no"]),
+    OptionInfo::new(
+        "backupext",
+        Some("bex"),
+        OptionScope::Global,
+        DefaultValue::String("~"),
+    )
+    // An empty extension would make the backup the file itself
+    .non_empty(),
+    OptionInfo::new(
+        "dirhidden",
+        None,
+        OptionScope::Global,
+        DefaultValue::Bool(false),
+    ),
+    OptionInfo::new(
+        "dirsort",
+        None,
+        OptionScope::Global,
+        DefaultValue::String("name"),
+    )
+    .with_values(directory::SORT_ORDERS),
+    OptionInfo::new(
+        "errorformat",
+        Some("efm"),
+        OptionScope::Global,
+        DefaultValue::List(&[
+            "%f:%l:%c: %trror: %m",
+            "%f:%l:%c: %tarning: %m",
+            "%f:%l:%c: %m",
+            "%f:%l: %m",
+        ]),
+    ),
+    OptionInfo::new(
+        "fileencodings",
+        Some("fencs"),
+        OptionScope::Global,
+        DefaultValue::List(&["ucs-bom", "utf-8", "latin1"]),
+    ),
+    OptionInfo::new(
+        "frametime",
+        None,
+        OptionScope::Global,
+        DefaultValue::Int(16),
+    ),
+    OptionInfo::new(
+        "grepformat",
+        Some("gfm"),
+        OptionScope::Global,
+        DefaultValue::List(&["%f:%l:%c:%m", "%f:%l:%m"]),
+    ),
+    OptionInfo::new(
+        "grepprg",
+        Some("gp"),
+        OptionScope::Global,
+        DefaultValue::String("internal"),
+    ),
+    OptionInfo::new(
+        "history",
+        Some("hi"),
+        OptionScope::Global,
+        DefaultValue::Int(200),
+    ),
+    OptionInfo::new(
+        "largefile",
+        Some("lf"),
+        OptionScope::Global,
+        DefaultValue::Int(100),
+    ),
+    OptionInfo::new(
+        "makeprg",
+        Some("mp"),
+        OptionScope::Global,
+        DefaultValue::String("cargo build --message-format=json"),
+    ),
+    OptionInfo::new(
+        "mapleader",
+        None,
+        OptionScope::Global,
+        DefaultValue::String("\\"),
+    ),
+    OptionInfo::new(
+        "maxmapdepth",
+        Some("mmd"),
+        OptionScope::Global,
+        DefaultValue::Int(1000),
+    ),
+    OptionInfo::new(
+        "modelines",
+        Some("mls"),
+        OptionScope::Global,
+        DefaultValue::Int(5),
+    ),
+    OptionInfo::new(
+        "pumheight",
+        Some("ph"),
+        OptionScope::Global,
+        DefaultValue::Int(0),
+    ),
+    OptionInfo::new(
+        "showcmd",
+        Some("sc"),
+        OptionScope::Global,
+        DefaultValue::Bool(true),
+    ),
+    OptionInfo::new(
+        "termguicolors",
+        Some("tgc"),
+        OptionScope::Global,
+        DefaultValue::Bool(false),
+    ),
+    OptionInfo::new(
+        "timeout",
+        Some("to"),
+        OptionScope::Global,
+        DefaultValue::Bool(true),
+    ),
+    OptionInfo::new(
+        "timeoutlen",
+        Some("tm"),
+        OptionScope::Global,
+        DefaultValue::Int(1000),
+    ),
+    OptionInfo::new(
+        "updatetime",
+        Some("ut"),
+        OptionScope::Global,
+        DefaultValue::Int(4000),
+    ),
+    OptionInfo::new(
+        "virtualtext",
+        Some("vt"),
+        OptionScope::Global,
+        DefaultValue::Bool(true),
+    ),
+    OptionInfo::new(
+        "writebackup",
+        Some("wb"),
+        OptionScope::Global,
+        DefaultValue::Bool(true),
+    ),
+    // Buffer-local options
+    OptionInfo::new("bomb", None, OptionScope::Buffer, DefaultValue::Bool(false)),
+    OptionInfo::new(
+        "endofline",
+        Some("eol"),
+        OptionScope::Buffer,
+        DefaultValue::Bool(true),
+    ),
+    OptionInfo::new(
+        "expandtab",
+        Some("et"),
+        OptionScope::Buffer,
+        DefaultValue::Bool(true),
+    ),
+    OptionInfo::new(
+        "fileencoding",
+        Some("fenc"),
+        OptionScope::Buffer,
+        DefaultValue::String("utf-8"),
+    )
+    .with_values(encoding::ENCODINGS),
+    OptionInfo::new(
+        "fileformat",
+        Some("ff"),
+        OptionScope::Buffer,
+        DefaultValue::String("unix"),
+    )
+    .with_values(&["unix", "dos", "mac"]),
+    OptionInfo::new(
+        "filetype",
+        Some("ft"),
+        OptionScope::Buffer,
+        DefaultValue::String(""),
+    ),
+    OptionInfo::new(
+        "fixendofline",
+        Some("fixeol"),
+        OptionScope::Buffer,
+        DefaultValue::Bool(true),
+    ),
+    OptionInfo::new(
+        "modeline",
+        Some("ml"),
+        OptionScope::Buffer,
+        DefaultValue::Bool(true),
+    ),
+    OptionInfo::new(
+        "modifiable",
+        Some("ma"),
+        OptionScope::Buffer,
+        DefaultValue::Bool(true),
+    ),
+    OptionInfo::new(
+        "readonly",
+        Some("ro"),
+        OptionScope::Buffer,
+        DefaultValue::Bool(false),
+    ),
+    OptionInfo::new(
+        "swapfile",
+        Some("swf"),
+        OptionScope::Buffer,
+        DefaultValue::Bool(true),
+    ),
+    OptionInfo::new(
+        "tabstop",
+        Some("ts"),
+        OptionScope::Buffer,
+        DefaultValue::Int(4),
+    ),
+    // Window-local options
+    OptionInfo::new(
+        "number",
+        Some("nu"),
+        OptionScope::Window,
+        DefaultValue::Bool(false),
+    ),
+    OptionInfo::new(
+        "relativenumber",
+        Some("rnu"),
+        OptionScope::Window,
+        DefaultValue::Bool(false),
+    ),
+    OptionInfo::new(
+        "signcolumn",
+        Some("scl"),
+        OptionScope::Window,
+        DefaultValue::String("auto"),
+    )
+    .with_values(&["auto", "yes", "no"]),
+];
+
+pub fn all_options() -> &'static [OptionInfo] {
+    OPTIONS
+}
+
+pub fn find_option(name: &str) -> Option<&'static OptionInfo> {
+    OPTIONS
+        .iter()
+        .find(|info| info.name == name || info.short_name == Some(name))
+}
+
+/// A set of option values.
+///
+/// The global set holds a value for every option (for local options this is the value new buffers
+/// and windows start out with), while the sets owned by buffers and windows only hold the options
+/// of their respective scope.
+#[derive(Debug, Clone)]
+pub struct Options {
+    values: HashMap<&'static str, OptionValue>,
+}
+
+impl Options {
+    pub fn new_global() -> Self {
+        Self {
+            values: OPTIONS
+                .iter()
+                .map(|info| (info.name, info.default_value()))
+                .collect(),
+        }
+    }
+
+    pub fn new_local(scope: OptionScope) -> Self {
+        Self {
+            values: OPTIONS
+                .iter()
+                .filter(|info| info.scope == scope)
+                .map(|info| (info.name, info.default_value()))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> &OptionValue {
+        match self.values.get(name) {
+            Some(value) => value,
+            None => panic!("option '{}' is not part of this option set", name),
+        }
+    }
+
+    pub fn get_bool(&self, name: &str) -> bool {
+        match self.get(name) {
+            OptionValue::Bool(value) => *value,
+            value => panic!("option '{}' is not a bool: {:?}", name, value),
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> i64 {
+        match self.get(name) {
+            OptionValue::Int(value) => *value,
+            value => panic!("option '{}' is not an int: {:?}", name, value),
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> &str {
+        match self.get(name) {
+            OptionValue::String(value) => value,
+            value => panic!("option '{}' is not a string: {:?}", name, value),
+        }
+    }
+
+    pub fn get_list(&self, name: &str) -> &[String] {
+        match self.get(name) {
+            OptionValue::List(values) => values,
+            value => panic!("option '{}' is not a list: {:?}", name, value),
+        }
+    }
+
+    pub fn set(&mut self, info: &OptionInfo, value: OptionValue) {
+        debug_assert_eq!(info.get_type(), value.get_type());
+        self.values.insert(info.name, value);
+    }
+
+    /// Applies a single `:set` operation and returns the new value.
+    pub fn apply(
+        &mut self,
+        info: &OptionInfo,
+        operation: &SetOperation,
+    ) -> Result<OptionValue, String> {
+        let current = self.get(info.name).clone();
+        let value = match (operation, current) {
+            (SetOperation::Show, current) => return Ok(current),
+            (SetOperation::Reset, _) => info.default_value(),
+            (SetOperation::Enable, OptionValue::Bool(_)) => OptionValue::Bool(true),
+            (SetOperation::Disable, OptionValue::Bool(_)) => OptionValue::Bool(false),
+            (SetOperation::Toggle, OptionValue::Bool(value)) => OptionValue::Bool(!value),
+            (SetOperation::Assign(value), current) => parse_value(info, current.get_type(), value)?,
+            (SetOperation::Add(value), OptionValue::Int(current)) => OptionValue::Int(
+                current
+                    .checked_add(parse_int(info, value)?)
+                    .ok_or_else(|| format!("Invalid value: {}+={}", info.name, value))?,
+            ),
+            (SetOperation::Subtract(value), OptionValue::Int(current)) => OptionValue::Int(
+                current
+                    .checked_sub(parse_int(info, value)?)
+                    .ok_or_else(|| format!("Invalid value: {}-={}", info.name, value))?,
+            ),
+            (SetOperation::Add(value), OptionValue::String(current)) => {
+                OptionValue::String(current + value)
+            }
+            (SetOperation::Add(value), OptionValue::List(mut current)) => {
+                current.extend(parse_list(value));
+                OptionValue::List(current)
+            }
+            (SetOperation::Subtract(value), OptionValue::List(mut current)) => {
+                let removed = parse_list(value);
+                current.retain(|item| !removed.contains(item));
+                OptionValue::List(current)
+            }
+            (SetOperation::Enable, _) => {
+                // `:set tabstop` shows the value of non-bool options just like vim does
+                return Ok(self.get(info.name).clone());
+            }
+            _ => return Err(format!("Invalid argument: {}", info.name)),
+        };
+
+        if let OptionValue::Int(value) = value {
+            if value < 0 {
+                return Err(format!("Argument must be positive: {}", info.name));
+            }
+        }
+        if let OptionValue::String(value) = &value {
+            if value.is_empty() && !info.allow_empty {
+                return Err(format!("Empty value not allowed: {}", info.name));
+            }
+        }
+        if let (OptionValue::String(value), Some(values)) = (&value, info.values) {
+            if !values.contains(&value.as_str()) {
+                return Err(format!("Invalid argument: {}={}", info.name, value));
+            }
+        }
+
+        self.set(info, value.clone());
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetOperation {
+    Show,
+    Enable,
+    Disable,
+    Toggle,
+    Reset,
+    Assign(String),
+    Add(String),
+    Subtract(String),
+}
+
+/// Parses a single argument of `:set`, e.g. `number`, `nonumber`, `tabstop=8` or `tabstop?`.
+pub fn parse_set_argument(argument: &str) -> Result<(&'static OptionInfo, SetOperation), String> {
+    let unknown = || format!("Unknown option: {}", argument);
+
+    if let Some(index) = argument.find(['=', ':']) {
+        let (name, value) = argument.split_at(index);
+        let value = value[1..].to_owned();
+        let (name, operation) = if let Some(name) = name.strip_suffix('+') {
+            (name, SetOperation::Add(value))
+        } else if let Some(name) = name.strip_suffix('-') {
+            (name, SetOperation::Subtract(value))
+        } else {
+            (name, SetOperation::Assign(value))
+        };
+        let info = find_option(name).ok_or_else(unknown)?;
+        return Ok((info, operation));
+    }
+
+    if let Some(name) = argument.strip_suffix('?') {
+        return Ok((find_option(name).ok_or_else(unknown)?, SetOperation::Show));
+    }
+    if let Some(name) = argument.strip_suffix('!') {
+        return Ok((find_option(name).ok_or_else(unknown)?, SetOperation::Toggle));
+    }
+    if let Some(name) = argument.strip_suffix('&') {
+        return Ok((find_option(name).ok_or_else(unknown)?, SetOperation::Reset));
+    }
+    if let Some(info) = find_option(argument) {
+        return Ok((info, SetOperation::Enable));
+    }
+
+    // Only bool options can be prefixed with "no" and "inv"
+    let (info, operation) = if let Some(name) = argument.strip_prefix("no") {
+        (find_option(name), SetOperation::Disable)
+    } else if let Some(name) = argument.strip_prefix("inv") {
+        (find_option(name), SetOperation::Toggle)
+    } else {
+        (None, SetOperation::Show)
+    };
+    match info {
+        Some(info) if info.get_type() == OptionType::Bool => Ok((info, operation)),
+        Some(info) => Err(format!("Invalid argument: {}", info.name)),
+        None => Err(unknown()),
+    }
+}
+
+/// Splits the arguments of `:set` at unescaped whitespace. A backslash escapes the following
+/// character, which allows values to contain spaces.
+pub fn split_set_arguments(arguments: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+
+    let mut chars = arguments.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    current.push(c);
+                }
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    result.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        result.push(current);
+    }
+
+    result
+}
+
+/// Finds a modeline like `# vim: set ts=8 noet :` or `// vim: ts=8 noet` in a line and returns
+/// its options in the format of `:set`'s arguments. `vi:`, `ex:` and `bvim:` work as well.
+pub fn parse_modeline(line: &str) -> Option<String> {
+    let (_, rest) = ["bvim:", "vim:", "vi:", "ex:"]
+        .iter()
+        .flat_map(|marker| {
+            line.match_indices(marker)
+                .filter(|(index, _)| {
+                    // The marker has to stand on its own, only `vim:` may start the line
+                    match line[..*index].chars().next_back() {
+                        Some(c) => c.is_whitespace(),
+                        None => marker.ends_with("vim:"),
+                    }
+                })
+                .map(|(index, marker)| (index, &line[index + marker.len()..]))
+        })
+        .min_by_key(|(index, _)| *index)?;
+    let rest = rest.trim_start();
+
+    // The second form ends at the first unescaped colon and allows any text after it
+    let options = match rest
+        .strip_prefix("set ")
+        .or_else(|| rest.strip_prefix("se "))
+    {
+        Some(options) => {
+            let mut end = options.len();
+            let mut escaped = false;
+            for (index, c) in options.char_indices() {
+                match c {
+                    ':' if !escaped => {
+                        end = index;
+                        break;
+                    }
+                    '\\' => escaped = !escaped,
+                    _ => escaped = false,
+                }
+            }
+            options[..end].replace("\\:", ":")
+        }
+        // The first form separates options by colons or whitespace
+        None => rest.replace(':', " "),
+    };
+
+    Some(options.trim().to_owned())
+}
+
+pub fn format_option(info: &OptionInfo, value: &OptionValue) -> String {
+    match value {
+        OptionValue::Bool(true) => info.name.to_owned(),
+        OptionValue::Bool(false) => format!("no{}", info.name),
+        value => format!("{}={}", info.name, value),
+    }
+}
+
+fn parse_value(info: &OptionInfo, kind: OptionType, value: &str) -> Result<OptionValue, String> {
+    match kind {
+        OptionType::Bool => Err(format!("Invalid argument: {}={}", info.name, value)),
+        OptionType::Int => Ok(OptionValue::Int(parse_int(info, value)?)),
+        OptionType::String => Ok(OptionValue::String(value.to_owned())),
+        OptionType::List => Ok(OptionValue::List(parse_list(value))),
+    }
+}
+
+fn parse_int(info: &OptionInfo, value: &str) -> Result<i64, String> {
+    value
+        .parse::<i64>()
+        .map_err(|_| format!("Number required after =: {}={}", info.name, value))
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_owned())
+        .collect()
+}
@@ -0,0 +1,533 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    buffer::Buffer,
+    cmdline::{self, CommandLineKind, History},
+    fuzzy,
+    highlight::Highlights,
+    jumplist::JumpList,
+    register::Register,
+    screen::Screen,
+    util::{self, Position},
+};
+
+/// The sources `:pick` accepts. Help tags are missing because the editor has no help yet.
+pub const SOURCES: &[&str] = &[
+    "buffers",
+    "commands",
+    "files",
+    "jumps",
+    "marks",
+    "registers",
+    "searches",
+    "symbols",
+];
+
+/// How much of a file is read for the preview
+const PREVIEW_BYTES: usize = 64 * 1024;
+
+/// What picking an item does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Open the file in the current window
+    File(PathBuf),
+    /// Move the cursor in the current buffer
+    Position(Position<usize>),
+    /// Open the file and move the cursor in it
+    Location(PathBuf, Position<usize>),
+    /// Start a command line with the text typed
+    CommandLine(String),
+    /// Search for the pattern
+    Search(String),
+    /// Jump to the entry of the quickfix list with the index
+    Quickfix(usize),
+    /// Put the text of the register after the cursor
+    Put(char),
+}
+
+#[derive(Debug, Clone)]
+pub struct PickerItem {
+    /// The text matched against the query and shown in the list
+    pub label: String,
+    /// What the item is, e.g. `Function`, shown next to the label
+    pub kind: Option<String>,
+    /// The file and line shown in the preview
+    pub preview: Option<(PathBuf, usize)>,
+    pub target: Target,
+}
+
+impl PickerItem {
+    pub fn new(label: String, target: Target) -> Self {
+        Self {
+            label,
+            kind: None,
+            preview: None,
+            target,
+        }
+    }
+}
+
+/// Something that can provide the items of the picker
+pub trait PickerSource {
+    fn name(&self) -> &'static str;
+
+    /// Returns the items that became available since the last call
+    fn poll(&mut self) -> Vec<PickerItem>;
+
+    /// Whether all items were delivered. Sources that find their items in the background return
+    /// `false` until they're done, and the picker keeps polling them.
+    fn is_complete(&self) -> bool {
+        true
+    }
+
+    /// Whether the items have a preview, which takes the right half of the picker
+    fn has_preview(&self) -> bool {
+        false
+    }
+}
+
+/// A source whose items are known up front
+pub struct Items {
+    name: &'static str,
+    items: Vec<PickerItem>,
+    has_preview: bool,
+}
+
+impl Items {
+    pub fn new(name: &'static str, items: Vec<PickerItem>) -> Self {
+        let has_preview = items.iter().any(|item| item.preview.is_some());
+        Self {
+            name,
+            items,
+            has_preview,
+        }
+    }
+}
+
+impl PickerSource for Items {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn poll(&mut self) -> Vec<PickerItem> {
+        std::mem::take(&mut self.items)
+    }
+
+    fn has_preview(&self) -> bool {
+        self.has_preview
+    }
+}
+
+/// Lists the open buffers
+pub fn buffers(buffers: &[&Buffer]) -> Items {
+    let items = buffers
+        .iter()
+        .map(|buffer| match &buffer.path {
+            Some(path) => PickerItem {
+                preview: Some((path.clone(), 0)),
+                ..PickerItem::new(util::display_path(path), Target::File(path.clone()))
+            },
+            None => PickerItem::new(
+                "[No Name]".to_owned(),
+                Target::Position(Position::new(0, 0)),
+            ),
+        })
+        .collect();
+    Items::new("buffers", items)
+}
+
+/// Lists the Ex commands. Picking one starts a command line with it typed.
+pub fn commands() -> Items {
+    let items = cmdline::COMMANDS
+        .iter()
+        .map(|name| PickerItem::new(name.to_string(), Target::CommandLine(format!("{} ", name))))
+        .collect();
+    Items::new("commands", items)
+}
+
+/// Lists the search history, the most recent search first
+pub fn searches(history: &History) -> Items {
+    let items = history
+        .entries(CommandLineKind::Search)
+        .iter()
+        .rev()
+        .map(|pattern| PickerItem::new(pattern.clone(), Target::Search(pattern.clone())))
+        .collect();
+    Items::new("searches", items)
+}
+
+/// Lists the registers that aren't empty. Picking one puts its text after the cursor.
+pub fn registers(registers: &[(char, Register)]) -> Items {
+    let items = registers
+        .iter()
+        .map(|(name, register)| {
+            PickerItem::new(
+                format!("\"{}  {}", name, register.display()),
+                Target::Put(*name),
+            )
+        })
+        .collect();
+    Items::new("registers", items)
+}
+
+/// Lists the marks of the buffer
+pub fn marks(buffer: &Buffer) -> Items {
+    let mut marks = buffer.marks.iter().collect::<Vec<_>>();
+    marks.sort_by_key(|(name, _)| **name);
+    let items = marks
+        .into_iter()
+        .map(|(name, position)| {
+            let text = buffer.lines.get(position.y).map_or("", |line| line.trim());
+            let label = format!("{}  {}:{}  {}", name, position.y + 1, position.x + 1, text);
+            PickerItem {
+                preview: buffer.path.clone().map(|path| (path, position.y)),
+                ..PickerItem::new(label, Target::Position(*position))
+            }
+        })
+        .collect();
+    Items::new("marks", items)
+}
+
+/// Lists the jump list, newest entry first. The lines of the current buffer are shown with
+/// their text.
+pub fn jumps(jumps: &JumpList, buffer: &Buffer) -> Items {
+    let items = jumps
+        .jumps()
+        .iter()
+        .rev()
+        .map(|jump| {
+            let position = jump.position;
+            let location = match &jump.path {
+                Some(path) => format!("{}:", util::display_path(path)),
+                None => String::new(),
+            };
+            let mut label = format!("{}{}:{}", location, position.y + 1, position.x + 1);
+            if jump.path == buffer.path {
+                if let Some(line) = buffer.lines.get(position.y) {
+                    label = format!("{}  {}", label, line.trim());
+                }
+            }
+            let target = match &jump.path {
+                Some(path) => Target::Location(path.clone(), position),
+                None => Target::Position(position),
+            };
+            PickerItem {
+                preview: jump.path.clone().map(|path| (path, position.y)),
+                ..PickerItem::new(label, target)
+            }
+        })
+        .collect();
+    Items::new("jumps", items)
+}
+
+/// A floating list of items that can be narrowed down by typing, with a preview of the selected
+/// item if the source has one
+pub struct Picker {
+    source: Box<dyn PickerSource>,
+    query: String,
+    items: Vec<PickerItem>,
+    /// Scores and indices of the items matching the query, best match first
+    matches: Vec<(i64, usize)>,
+    /// Index into `matches`
+    selected: usize,
+    /// The item the preview was read for and its lines, starting at the previewed line
+    preview: Option<(usize, Vec<String>)>,
+}
+
+impl Picker {
+    pub fn new(source: Box<dyn PickerSource>, query: &str) -> Self {
+        let mut picker = Self {
+            source,
+            query: query.to_owned(),
+            items: Vec::new(),
+            matches: Vec::new(),
+            selected: 0,
+            preview: None,
+        };
+        picker.poll();
+        picker
+    }
+
+    /// Takes the items the source found since the last call
+    pub fn poll(&mut self) {
+        let first = self.items.len();
+        self.items.extend(self.source.poll());
+        if self.items.len() > first {
+            self.add_matches(first);
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn set_query(&mut self, query: String) {
+        self.query = query;
+        self.matches.clear();
+        self.selected = 0;
+        self.add_matches(0);
+    }
+
+    /// Scores the items from index `first` on and merges the matching ones into the matches. The
+    /// earlier matches are already sorted, so each batch a source delivers only costs scoring
+    /// the batch. The selected item stays selected.
+    fn add_matches(&mut self, first: usize) {
+        let mut added = (first..self.items.len())
+            .filter_map(|i| Some((fuzzy::score(&self.query, &self.items[i].label)?, i)))
+            .collect::<Vec<_>>();
+        // Without a query the items keep the source's order
+        if self.query.is_empty() {
+            self.matches.append(&mut added);
+        } else {
+            // Stable, so equally good items keep the source's order, and earlier items come first
+            // on ties in the merge
+            added.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+            let previous = std::mem::take(&mut self.matches);
+            let mut selected = None;
+            let mut added = added.into_iter().peekable();
+            for (index, item) in previous.into_iter().enumerate() {
+                while let Some(new) = added.next_if(|(score, _)| *score > item.0) {
+                    self.matches.push(new);
+                }
+                if index == self.selected {
+                    selected = Some(self.matches.len());
+                }
+                self.matches.push(item);
+            }
+            self.matches.extend(added);
+            self.selected = selected.unwrap_or(0);
+        }
+        self.selected = self.selected.min(self.matches.len().saturating_sub(1));
+        self.update_preview();
+    }
+
+    /// Moves the selection by the given number of items, stopping at both ends
+    pub fn select(&mut self, offset: isize) {
+        let last = self.matches.len().saturating_sub(1) as isize;
+        self.selected = (self.selected as isize + offset).clamp(0, last) as usize;
+        self.update_preview();
+    }
+
+    pub fn selected(&self) -> Option<&PickerItem> {
+        self.matches
+            .get(self.selected)
+            .map(|(_, i)| &self.items[*i])
+    }
+
+    fn update_preview(&mut self) {
+        let selected = self.matches.get(self.selected).map(|(_, i)| *i);
+        if self.preview.as_ref().map(|(index, _)| *index) == selected {
+            return;
+        }
+        self.preview = selected.and_then(|index| {
+            let (path, line) = self.items[index].preview.as_ref()?;
+            Some((index, read_preview(path, *line)))
+        });
+    }
+
+    /// Renders the picker in the middle of an area of the given size and returns where the
+    /// cursor goes
+    pub fn render(
+        &self,
+        screen: &mut Screen,
+        highlights: &Highlights,
+        area: (u16, u16),
+    ) -> (u16, u16) {
+        let (area_width, area_height) = (area.0 as usize, area.1 as usize);
+        let width = (area_width * 4 / 5).max(area_width.min(20));
+        let height = (area_height * 4 / 5).max(area_height.min(5));
+        let left = (area_width - width) / 2;
+        let top = (area_height - height) / 2;
+        if width < 4 || height < 2 {
+            return (0, 0);
+        }
+
+        // The first row has the query and how many of the items found so far match
+        let mut count = format!(
+            "{} {}/{}",
+            self.source.name(),
+            self.matches.len(),
+            self.items.len()
+        );
+        if !self.source.is_complete() {
+            count.push_str(" (searching)");
+        }
+        let prompt = format!("> {}", self.query);
+        let prompt_width = width.saturating_sub(count.chars().count() + 2);
+        let prompt = prompt.chars().take(prompt_width).collect::<String>();
+        highlights.apply(screen, "Pmenu");
+        let text = format!("{:<prompt_width$} {} ", prompt, count);
+        screen.print_at(
+            left as u16,
+            top as u16,
+            text.chars().take(width).collect::<String>(),
+        );
+        let cursor = (
+            (left + prompt.chars().count()).min(left + width - 1) as u16,
+            top as u16,
+        );
+
+        // The matches take the left half of the rows below it and the preview the right half
+        let rows = height - 1;
+        let list_width = if self.source.has_preview() {
+            width / 2
+        } else {
+            width
+        };
+        let preview_width = width - list_width;
+        let first = (self.selected + 1).saturating_sub(rows);
+        for row in 0..rows {
+            let index = first + row;
+            let y = (top + 1 + row) as u16;
+            let item = self.matches.get(index).map(|(_, i)| &self.items[*i]);
+            let selected = item.is_some() && index == self.selected;
+            let label = item.map_or("", |item| item.label.as_str());
+            let kind = item
+                .and_then(|item| item.kind.as_deref())
+                .unwrap_or_default();
+
+            // Long labels are cut at the start, where paths are the least distinctive
+            let label_space = list_width.saturating_sub(kind.chars().count() + 3);
+            let skipped = label.chars().count().saturating_sub(label_space);
+            let label = label.chars().skip(skipped).collect::<String>();
+            highlights.apply(screen, if selected { "PmenuSel" } else { "Pmenu" });
+            let text = format!(" {:<width$} ", label, width = label_space);
+            screen.print_at(left as u16, y, text);
+            highlights.apply(
+                screen,
+                if selected {
+                    "PmenuKindSel"
+                } else {
+                    "PmenuKind"
+                },
+            );
+            let text = format!("{:<width$}", kind, width = list_width - label_space - 2);
+            screen.print_at((left + label_space + 2) as u16, y, text);
+
+            if preview_width > 0 {
+                highlights.apply(screen, "PmenuDoc");
+                let line = self
+                    .preview
+                    .as_ref()
+                    .and_then(|(_, lines)| lines.get(row))
+                    .map_or("", String::as_str);
+                let line = line.chars().take(preview_width - 1).collect::<String>();
+                let text = format!("{:<width$} ", line, width = preview_width - 1);
+                screen.print_at((left + list_width) as u16, y, text);
+            }
+        }
+        screen.clear_colors();
+        cursor
+    }
+}
+
+/// Reads the lines of a file from `line` on for the preview. Binary files and errors get a note
+/// instead.
+fn read_preview(path: &Path, line: usize) -> Vec<String> {
+    let mut bytes = Vec::new();
+    let result =
+        File::open(path).and_then(|file| file.take(PREVIEW_BYTES as u64).read_to_end(&mut bytes));
+    if let Err(e) = result {
+        return vec![format!("Can't read the file: {}", e)];
+    }
+    if bytes.contains(&0) {
+        return vec!["Binary file".to_owned()];
+    }
+    String::from_utf8_lossy(&bytes)
+        .lines()
+        .skip(line)
+        .map(|line| line.replace('\t', "    "))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Delivers its items a batch per poll, like a source searching in the background
+    struct Batches(Vec<Vec<&'static str>>);
+
+    impl PickerSource for Batches {
+        fn name(&self) -> &'static str {
+            "batches"
+        }
+
+        fn poll(&mut self) -> Vec<PickerItem> {
+            if self.0.is_empty() {
+                return Vec::new();
+            }
+            self.0
+                .remove(0)
+                .into_iter()
+                .map(|label| PickerItem::new(label.to_owned(), Target::CommandLine(String::new())))
+                .collect()
+        }
+
+        fn is_complete(&self) -> bool {
+            self.0.is_empty()
+        }
+    }
+
+    fn labels(picker: &Picker) -> Vec<&str> {
+        picker
+            .matches
+            .iter()
+            .map(|(_, i)| picker.items[*i].label.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn merges_batches_in_order() {
+        let batches = vec![
+            vec!["src/main.rs", "README.md", "src/m/a/i/n.rs"],
+            vec!["main.rs", "Cargo.toml"],
+            vec!["src/domain.rs", "main"],
+        ];
+        let all = batches.concat();
+        let mut picker = Picker::new(Box::new(Batches(batches)), "main");
+        while !picker.source.is_complete() {
+            picker.poll();
+        }
+
+        let sorted = |query: &str| {
+            let mut matches = all
+                .iter()
+                .filter_map(|label| Some((fuzzy::score(query, label)?, *label)))
+                .collect::<Vec<_>>();
+            matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+            matches
+                .into_iter()
+                .map(|(_, label)| label)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(&picker), sorted("main"));
+        assert_eq!(labels(&picker)[..2], ["main", "main.rs"]);
+
+        picker.set_query("rs".to_owned());
+        assert_eq!(labels(&picker), sorted("rs"));
+    }
+
+    #[test]
+    fn keeps_the_order_of_the_source_without_a_query() {
+        let mut picker = Picker::new(Box::new(Batches(vec![vec!["b", "a"], vec!["c"]])), "");
+        picker.poll();
+        assert_eq!(labels(&picker), ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn keeps_the_selected_item_selected() {
+        let batches = vec![vec!["xmain", "xxmain"], vec!["main", "zzzmain"]];
+        let mut picker = Picker::new(Box::new(Batches(batches)), "main");
+        picker.select(1);
+        assert_eq!(picker.selected().unwrap().label, "xxmain");
+
+        // A better match shows up above it
+        picker.poll();
+        assert_eq!(labels(&picker)[0], "main");
+        assert_eq!(picker.selected().unwrap().label, "xxmain");
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use regex::Regex;
+
+use crate::{
+    finder,
+    util::{self, Position},
+};
+
+/// A location in a file with a message, e.g. a match of `:grep` or an error of a compiler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub position: Position<usize>,
+    pub text: String,
+    /// `E` for errors, `W` for warnings etc. if the output said what it is
+    pub kind: Option<char>,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:",
+            util::display_path(&self.path),
+            self.position.y + 1,
+            self.position.x + 1
+        )?;
+        match self.kind {
+            Some('E') => write!(f, " error:")?,
+            Some('W') => write!(f, " warning:")?,
+            Some('I') => write!(f, " info:")?,
+            Some('N') => write!(f, " note:")?,
+            _ => {}
+        }
+        write!(f, " {}", self.text)
+    }
+}
+
+/// The list filled by `:grep` and friends, which `:cnext` and `:cprev` go through
+#[derive(Debug, Default)]
+pub struct QuickfixList {
+    entries: Vec<Entry>,
+    current: usize,
+}
+
+impl QuickfixList {
+    pub fn new(entries: Vec<Entry>) -> Self {
+        Self {
+            entries,
+            current: 0,
+        }
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The index of the entry that was jumped to last
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Makes the entry at `index` the current one and returns it
+    pub fn select(&mut self, index: usize) -> Result<&Entry, String> {
+        if self.entries.is_empty() {
+            return Err("No Errors".to_owned());
+        }
+        let entry = self
+            .entries
+            .get(index)
+            .ok_or_else(|| "No more items".to_owned())?;
+        self.current = index;
+        Ok(entry)
+    }
+
+    /// Returns the index of the entry `offset` entries away from the current one
+    pub fn offset(&self, offset: isize) -> Result<usize, String> {
+        if self.entries.is_empty() {
+            return Err("No Errors".to_owned());
+        }
+        self.current
+            .checked_add_signed(offset)
+            .filter(|index| *index < self.entries.len())
+            .ok_or_else(|| "No more items".to_owned())
+    }
+}
+
+/// Parses the output of programs like compilers or grep into entries, like vim's 'errorformat'.
+/// Each format is matched against whole lines and may contain:
+///
+/// - `%f`: the file name
+/// - `%l`: the line number
+/// - `%c`: the column number
+/// - `%m`: the message
+/// - `%t`: a character telling the kind of message, e.g. `e` for errors
+/// - `%%`: a literal `%`
+///
+/// The first format that matches a line wins. Lines that no format matches are left out.
+pub struct ErrorFormat {
+    formats: Vec<Regex>,
+}
+
+impl ErrorFormat {
+    pub fn new(formats: &[String]) -> Result<Self, String> {
+        let formats = formats
+            .iter()
+            .map(|format| {
+                let regex = format_to_regex(format)?;
+                Regex::new(&regex).map_err(|_| format!("Invalid errorformat: {}", format))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { formats })
+    }
+
+    pub fn parse(&self, output: &str) -> Vec<Entry> {
+        output
+            .lines()
+            .filter_map(|line| self.parse_line(line))
+            .collect()
+    }
+
+    pub fn parse_line(&self, line: &str) -> Option<Entry> {
+        let captures = self
+            .formats
+            .iter()
+            .find_map(|format| format.captures(line))?;
+        let number = |name| {
+            captures
+                .name(name)
+                .and_then(|number| number.as_str().parse::<usize>().ok())
+                .map_or(0, |number| number.saturating_sub(1))
+        };
+        let path = PathBuf::from(captures.name("f")?.as_str());
+        Some(Entry {
+            path: std::path::absolute(&path).unwrap_or(path),
+            position: Position::new(number("c"), number("l")),
+            text: captures
+                .name("m")
+                .map_or("", |text| text.as_str())
+                .trim()
+                .to_owned(),
+            kind: captures
+                .name("t")
+                .and_then(|kind| kind.as_str().chars().next())
+                .map(|kind| kind.to_ascii_uppercase()),
+        })
+    }
+}
+
+fn format_to_regex(format: &str) -> Result<String, String> {
+    let mut regex = String::from("^");
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            regex.push_str(&regex::escape(&c.to_string()));
+            continue;
+        }
+        match chars.next() {
+            Some('f') => regex.push_str(r"(?P<f>.+?)"),
+            Some('l') => regex.push_str(r"(?P<l>\d+)"),
+            Some('c') => regex.push_str(r"(?P<c>\d+)"),
+            Some('m') => regex.push_str(r"(?P<m>.*)"),
+            Some('t') => regex.push_str(r"(?P<t>.)"),
+            Some('%') => regex.push('%'),
+            Some(c) => return Err(format!("Unsupported errorformat item: %{}", c)),
+            None => return Err(format!("Invalid errorformat: {}", format)),
+        }
+    }
+    regex.push('$');
+    Ok(regex)
+}
+
+/// Puts the arguments into the command of a program like 'grepprg' where it says `$*`, or
+/// appends them
+pub fn command_line(program: &str, arguments: &str) -> String {
+    if program.contains("$*") {
+        program.replace("$*", arguments)
+    } else if arguments.is_empty() {
+        program.to_owned()
+    } else {
+        format!("{} {}", program, arguments)
+    }
+}
+
+/// Splits the arguments of `:grep` into the pattern and the paths to search. A pattern
+/// containing spaces can be put in single or double quotes.
+pub fn split_grep_arguments(arguments: &str) -> Result<(String, Vec<PathBuf>), String> {
+    let (pattern, rest) = match arguments.chars().next() {
+        Some(quote @ ('"' | '\'')) => arguments[1..]
+            .split_once(quote)
+            .ok_or_else(|| "Missing closing quote".to_owned())?,
+        _ => arguments
+            .split_once(char::is_whitespace)
+            .unwrap_or((arguments, "")),
+    };
+    let paths = rest.split_whitespace().map(util::expand_home).collect();
+    Ok((pattern.to_owned(), paths))
+}
+
+/// Searches the files at `paths` for the regex, using a thread per core. Directories are searched
+/// recursively, leaving out the files the file finder leaves out, and binary files are skipped.
+/// There's an entry for each line with a match, ordered by file and line.
+pub fn grep(regex: &Regex, paths: &[PathBuf]) -> Vec<Entry> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+        if path.is_dir() {
+            finder::walk(&path, |name| {
+                files.push(path.join(name));
+                true
+            });
+        } else {
+            files.push(path);
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let threads = thread::available_parallelism().map_or(1, |count| count.get());
+    let mut entries = thread::scope(|scope| {
+        let workers = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut entries = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = files.get(index) else {
+                            break;
+                        };
+                        entries.extend(grep_file(regex, path).into_iter().map(|e| (index, e)));
+                    }
+                    entries
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_default())
+            .collect::<Vec<_>>()
+    });
+    // The entries of a file are already in order
+    entries.sort_by_key(|(index, _)| *index);
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+fn grep_file(regex: &Regex, path: &Path) -> Vec<Entry> {
+    let Ok(bytes) = fs::read(path) else {
+        return Vec::new();
+    };
+    if bytes[..bytes.len().min(8192)].contains(&0) {
+        return Vec::new();
+    }
+    String::from_utf8_lossy(&bytes)
+        .lines()
+        .enumerate()
+        .filter_map(|(y, line)| {
+            let found = regex.find(line)?;
+            Some(Entry {
+                path: path.to_path_buf(),
+                position: Position::new(line[..found.start()].chars().count(), y),
+                text: line.trim().to_owned(),
+                kind: None,
+            })
+        })
+        .collect()
+}
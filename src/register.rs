@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+/// Text that was yanked or deleted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Register {
+    /// The text, lines being separated by `\n`. Linewise text has no line break at the end.
+    pub text: String,
+    /// Whether the text consists of whole lines, which are put below or above the cursor line
+    pub linewise: bool,
+}
+
+impl Register {
+    /// The text as `:registers` shows it, with line breaks as `^J`
+    pub fn display(&self) -> String {
+        let text = self.text.replace('\n', "^J");
+        if self.linewise {
+            text + "^J"
+        } else {
+            text
+        }
+    }
+}
+
+/// The registers that yanked and deleted text goes to. Like in vim, `"` holds the text of the
+/// last yank or delete, `0` the last yanked text, `1` to `9` the last deleted lines, `-` the last
+/// deletion within a line, and `a` to `z` are for the user, who appends to them with `A` to `Z`.
+#[derive(Debug, Default)]
+pub struct Registers {
+    registers: HashMap<char, Register>,
+}
+
+/// Returns whether text can be yanked or deleted into the register
+pub fn is_writable(name: char) -> bool {
+    matches!(name, '"' | '-' | '0'..='9' | 'a'..='z' | 'A'..='Z')
+}
+
+/// Fails for registers that text can't be yanked or deleted into. `None` is the unnamed one.
+pub fn check_writable(name: Option<char>) -> Result<(), String> {
+    match name {
+        Some(name) if !is_writable(name) => Err(format!("Invalid register name: {}", name)),
+        _ => Ok(()),
+    }
+}
+
+impl Registers {
+    /// Returns the register with the name, `A` to `Z` being the same as `a` to `z`
+    pub fn get(&self, name: char) -> Option<&Register> {
+        self.registers.get(&name.to_ascii_lowercase())
+    }
+
+    /// Stores yanked text in the register with the name, or in `0` without one
+    pub fn yank(&mut self, name: Option<char>, register: Register) {
+        match name {
+            Some(name) if name != '"' => self.store(name, register),
+            _ => self.store('0', register),
+        }
+    }
+
+    /// Stores deleted text in the register with the name. Without one, lines and text spanning
+    /// multiple lines are shifted into `1`, and smaller deletions go to `-`.
+    pub fn delete(&mut self, name: Option<char>, register: Register) {
+        match name {
+            Some(name) if name != '"' => self.store(name, register),
+            _ if register.linewise || register.text.contains('\n') => {
+                for i in (1..9).rev() {
+                    let from = char::from(b'0' + i);
+                    if let Some(shifted) = self.registers.remove(&from) {
+                        self.registers.insert(char::from(b'1' + i), shifted);
+                    }
+                }
+                self.store('1', register);
+            }
+            _ => self.store('-', register),
+        }
+    }
+
+    /// Lists the registers that aren't empty in the order `:registers` shows them
+    pub fn list(&self) -> Vec<(char, &Register)> {
+        let mut registers = self
+            .registers
+            .iter()
+            .map(|(name, register)| (*name, register))
+            .collect::<Vec<_>>();
+        registers.sort_by_key(|(name, _)| match name {
+            '"' => (0, *name),
+            '0'..='9' => (1, *name),
+            'a'..='z' => (2, *name),
+            _ => (3, *name),
+        });
+        registers
+    }
+
+    /// Sets the register, or appends to it for `A` to `Z`, and makes `"` refer to the result
+    fn store(&mut self, name: char, register: Register) {
+        let register = match self.registers.get(&name.to_ascii_lowercase()) {
+            Some(old) if name.is_ascii_uppercase() => {
+                // Like in vim, the result consists of lines if either part does
+                let linewise = old.linewise || register.linewise;
+                let separator = if linewise { "\n" } else { "" };
+                Register {
+                    text: format!("{}{}{}", old.text, separator, register.text),
+                    linewise,
+                }
+            }
+            _ => register,
+        };
+        self.registers.insert('"', register.clone());
+        self.registers.insert(name.to_ascii_lowercase(), register);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Register {
+        Register {
+            text: text.to_owned(),
+            linewise: true,
+        }
+    }
+
+    fn chars(text: &str) -> Register {
+        Register {
+            text: text.to_owned(),
+            linewise: false,
+        }
+    }
+
+    #[test]
+    fn yanks_into_0_and_named_registers() {
+        let mut registers = Registers::default();
+        registers.yank(None, lines("one"));
+        registers.yank(Some('a'), chars("two"));
+        assert_eq!(registers.get('0'), Some(&lines("one")));
+        assert_eq!(registers.get('a'), Some(&chars("two")));
+        assert_eq!(registers.get('"'), Some(&chars("two")));
+    }
+
+    #[test]
+    fn shifts_deleted_lines() {
+        let mut registers = Registers::default();
+        for i in 1..=10 {
+            registers.delete(None, lines(&i.to_string()));
+        }
+        registers.delete(None, chars("word"));
+        assert_eq!(registers.get('1'), Some(&lines("10")));
+        assert_eq!(registers.get('9'), Some(&lines("2")));
+        assert_eq!(registers.get('-'), Some(&chars("word")));
+        assert_eq!(registers.get('"'), Some(&chars("word")));
+    }
+
+    #[test]
+    fn appends_to_named_registers() {
+        let mut registers = Registers::default();
+        registers.yank(Some('a'), chars("one"));
+        registers.yank(Some('A'), chars("two"));
+        assert_eq!(registers.get('a'), Some(&chars("onetwo")));
+        registers.delete(Some('A'), lines("three"));
+        assert_eq!(registers.get('A'), Some(&lines("onetwo\nthree")));
+        assert_eq!(registers.get('"'), Some(&lines("onetwo\nthree")));
+    }
+
+    #[test]
+    fn lists_registers_in_order() {
+        let mut registers = Registers::default();
+        registers.yank(Some('b'), chars("b"));
+        registers.delete(None, chars("-"));
+        registers.yank(None, chars("0"));
+        let names = registers
+            .list()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<String>();
+        assert_eq!(names, "\"0b-");
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, stdout, Write},
+};
+
+use blessings::{ClearType, CursorStyle};
+use crossterm::{
+    cursor::MoveTo,
+    style::{Attribute, Color, Colors, Print, SetAttribute, SetColors},
+    terminal, QueueableCommand,
+};
+
+/// Text attributes of highlight groups
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Attributes {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+    pub strikethrough: bool,
+}
+
+impl Attributes {
+    pub const NONE: Self = Self {
+        bold: false,
+        italic: false,
+        underline: false,
+        reverse: false,
+        strikethrough: false,
+    };
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::NONE
+    }
+
+    /// The attributes' names like in `:highlight`
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.bold, "bold"),
+            (self.italic, "italic"),
+            (self.underline, "underline"),
+            (self.reverse, "reverse"),
+            (self.strikethrough, "strikethrough"),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| name)
+        .collect()
+    }
+
+    fn sgr(&self) -> impl Iterator<Item = Attribute> {
+        [
+            (self.bold, Attribute::Bold),
+            (self.italic, Attribute::Italic),
+            (self.underline, Attribute::Underlined),
+            (self.reverse, Attribute::Reverse),
+            (self.strikethrough, Attribute::CrossedOut),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, attribute)| attribute)
+    }
+}
+
+/// A character as it's drawn on the terminal
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Cell {
+    c: char,
+    colors: (Color, Color),
+    attributes: Attributes,
+}
+
+/// The screen of blessings, which can only show colors, with support for text attributes.
+///
+/// Blessings only writes the cells that changed to the terminal when a frame is shown, so text
+/// printed with attributes is recorded and drawn again with them on top of the frame. Cells that
+/// had attributes in the last frame are drawn again as well, since blessings doesn't know that
+/// they have to be redrawn if their text stays the same.
+pub struct Screen {
+    inner: blessings::Screen,
+    size: (u16, u16),
+    colors: (Color, Color),
+    attributes: Attributes,
+    /// The origin and size of the window that's being drawn, if any
+    window: Option<(u16, u16, u16, u16)>,
+    /// The cells of the current frame that have attributes or had them in the last frame
+    cells: HashMap<(u16, u16), Cell>,
+    /// The positions of the cells the last frame drew with attributes
+    drawn: HashSet<(u16, u16)>,
+}
+
+impl Screen {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            inner: blessings::Screen::new()?,
+            size: terminal::size()?,
+            colors: (Color::Reset, Color::Reset),
+            attributes: Attributes::NONE,
+            window: None,
+            cells: HashMap::new(),
+            drawn: HashSet::new(),
+        })
+    }
+
+    pub fn begin(&mut self) -> io::Result<()> {
+        self.inner.begin()
+    }
+
+    pub fn end(&mut self) -> io::Result<()> {
+        self.inner.end()
+    }
+
+    /// Shows the frame on the terminal and starts the next one
+    pub fn show(&mut self) -> io::Result<()> {
+        self.inner.show()?;
+        if !self.cells.is_empty() {
+            self.draw_cells()?;
+        }
+        self.drawn = self
+            .cells
+            .drain()
+            .filter(|(_, cell)| !cell.attributes.is_empty())
+            .map(|(position, _)| position)
+            .collect();
+        Ok(())
+    }
+
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.inner.resize(width, height);
+        self.size = (width, height);
+        self.drawn.retain(|(x, y)| *x < width && *y < height);
+    }
+
+    pub fn clear(&mut self, clear_type: ClearType) {
+        let all = matches!(clear_type, ClearType::All);
+        self.inner.clear(clear_type);
+        if all {
+            self.cells.clear();
+            let blank = Cell {
+                c: ' ',
+                colors: (Color::Reset, Color::Reset),
+                attributes: Attributes::NONE,
+            };
+            for position in &self.drawn {
+                self.cells.insert(*position, blank);
+            }
+        }
+    }
+
+    pub fn get_cursor(&self) -> (u16, u16) {
+        self.inner.get_cursor()
+    }
+
+    pub fn move_to(&mut self, x: u16, y: u16) {
+        self.inner.move_to(x, y);
+    }
+
+    pub fn print<S: AsRef<str>>(&mut self, text: S) {
+        let text = text.as_ref();
+        let (x, y) = self.inner.get_cursor();
+        let (left, top) = self.window.map_or((0, 0), |(x, y, _, _)| (x, y));
+        self.record(x.saturating_sub(left), y.saturating_sub(top), text);
+        self.inner.print(text);
+    }
+
+    pub fn print_at<S: AsRef<str>>(&mut self, x: u16, y: u16, text: S) {
+        let text = text.as_ref();
+        self.record(x, y, text);
+        self.inner.print_at(x, y, text);
+    }
+
+    pub fn set_colors(&mut self, fg: Color, bg: Color) {
+        self.inner.set_colors(fg, bg);
+        self.colors = (fg, bg);
+    }
+
+    /// Sets the attributes of the text printed from now on
+    pub fn set_attributes(&mut self, attributes: Attributes) {
+        self.attributes = attributes;
+    }
+
+    /// Resets the colors and the attributes to the terminal's defaults
+    pub fn clear_colors(&mut self) {
+        self.inner.clear_colors();
+        self.colors = (Color::Reset, Color::Reset);
+        self.attributes = Attributes::NONE;
+    }
+
+    pub fn set_cursor_style(&mut self, style: CursorStyle) {
+        self.inner.set_cursor_style(style);
+    }
+
+    pub fn begin_window(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        self.inner.begin_window(x, y, width, height);
+        self.window = Some((x, y, width, height));
+    }
+
+    pub fn end_window(&mut self) {
+        self.inner.end_window();
+        self.window = None;
+    }
+
+    /// Remembers the cells of text printed at a position relative to the current window that
+    /// have to be drawn on top of the frame
+    fn record(&mut self, x: u16, y: u16, text: &str) {
+        if self.attributes.is_empty() && self.cells.is_empty() && self.drawn.is_empty() {
+            return;
+        }
+        let (left, top, width, height) = self.window.unwrap_or((0, 0, self.size.0, self.size.1));
+        if y >= height || top + y >= self.size.1 {
+            return;
+        }
+        let width = width.min(self.size.0.saturating_sub(left));
+        for (i, c) in text.chars().enumerate() {
+            let Some(x) = u16::try_from(i).ok().and_then(|i| x.checked_add(i)) else {
+                break;
+            };
+            if x >= width {
+                break;
+            }
+            let position = (left + x, top + y);
+            if self.attributes.is_empty()
+                && !self.cells.contains_key(&position)
+                && !self.drawn.contains(&position)
+            {
+                continue;
+            }
+            let cell = Cell {
+                c,
+                colors: self.colors,
+                attributes: self.attributes,
+            };
+            self.cells.insert(position, cell);
+        }
+    }
+
+    /// Draws the recorded cells over what blessings has shown and puts the cursor back
+    fn draw_cells(&self) -> io::Result<()> {
+        let mut cells = self.cells.iter().collect::<Vec<_>>();
+        cells.sort_by_key(|((x, y), _)| (*y, *x));
+
+        let mut stdout = stdout();
+        let mut next_position = None;
+        let mut style = None;
+        for (&(x, y), cell) in cells {
+            if next_position != Some((x, y)) {
+                stdout.queue(MoveTo(x, y))?;
+            }
+            if style != Some((cell.colors, cell.attributes)) {
+                stdout.queue(SetAttribute(Attribute::Reset))?;
+                stdout.queue(SetColors(Colors::new(cell.colors.0, cell.colors.1)))?;
+                for attribute in cell.attributes.sgr() {
+                    stdout.queue(SetAttribute(attribute))?;
+                }
+                style = Some((cell.colors, cell.attributes));
+            }
+            stdout.queue(Print(cell.c))?;
+            next_position = Some((x + 1, y));
+        }
+
+        let (x, y) = self.inner.get_cursor();
+        stdout.queue(SetAttribute(Attribute::Reset))?;
+        stdout.queue(MoveTo(x, y))?;
+        stdout.flush()
+    }
+}
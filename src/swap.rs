@@ -0,0 +1,126 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+};
+
+use crate::{buffer::Buffer, config};
+
+/// The first line of every swap file, followed by `key value` lines, an empty line and the
+/// buffer's contents
+const MAGIC: &str = "bvim swap file 1";
+
+/// Returns where the swap file of the given file is kept: in the `swap` directory of the state
+/// directory, named after the file's absolute path with `%` in place of slashes.
+pub fn swap_path(path: &Path) -> Option<PathBuf> {
+    let path = fs::canonicalize(path)
+        .or_else(|_| std::env::current_dir().map(|dir| dir.join(path)))
+        .ok()?;
+    let name = path.to_string_lossy().replace('/', "%");
+    Some(
+        config::state_dir()?
+            .join("swap")
+            .join(format!("{}.swp", name)),
+    )
+}
+
+/// The contents of a swap file left by another or a crashed instance
+#[derive(Debug, Clone)]
+pub struct SwapInfo {
+    pub path: PathBuf,
+    /// The process that owns the swap file
+    pub pid: u32,
+    /// Whether the buffer had unsaved changes when the swap file was written
+    pub modified: bool,
+    pub lines: Vec<String>,
+}
+
+impl SwapInfo {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a bvim swap file");
+        let (header, text) = content.split_once("\n\n").ok_or_else(invalid)?;
+        let mut header = header.lines();
+        if header.next() != Some(MAGIC) {
+            return Err(invalid());
+        }
+
+        let mut info = Self {
+            path: path.to_path_buf(),
+            pid: 0,
+            modified: false,
+            lines: text.split('\n').map(str::to_owned).collect(),
+        };
+        for line in header {
+            match line.split_once(' ') {
+                Some(("pid", pid)) => info.pid = pid.parse().map_err(|_| invalid())?,
+                Some(("modified", modified)) => info.modified = modified == "1",
+                _ => {}
+            }
+        }
+        Ok(info)
+    }
+
+    /// Whether the process that wrote the swap file is still running, i.e. the file is being
+    /// edited in another instance
+    pub fn is_owner_running(&self) -> bool {
+        if self.pid == process::id() {
+            return false;
+        }
+        if Path::new("/proc/self").exists() {
+            return Path::new("/proc").join(self.pid.to_string()).exists();
+        }
+        Command::new("kill")
+            .args(["-0", &self.pid.to_string()])
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+/// The swap file of the current buffer, which is rewritten with the buffer's contents after
+/// changes so they can be recovered after a crash
+#[derive(Debug)]
+pub struct SwapFile {
+    path: PathBuf,
+    /// The buffer version that was last written
+    version: i32,
+}
+
+impl SwapFile {
+    pub fn create(path: PathBuf, buffer: &Buffer) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut swap = Self { path, version: 0 };
+        swap.write(buffer)?;
+        Ok(swap)
+    }
+
+    /// Whether the buffer changed since the swap file was last written
+    pub fn is_outdated(&self, buffer: &Buffer) -> bool {
+        self.version != buffer.version
+    }
+
+    pub fn write(&mut self, buffer: &Buffer) -> io::Result<()> {
+        let mut content = format!(
+            "{}\npid {}\nmodified {}\n",
+            MAGIC,
+            process::id(),
+            if buffer.changed { 1 } else { 0 }
+        );
+        if let Some(path) = &buffer.path {
+            content.push_str(&format!("path {}\n", path.display()));
+        }
+        content.push('\n');
+        content.push_str(&buffer.lines.join("\n"));
+
+        fs::write(&self.path, content)?;
+        self.version = buffer.version;
+        Ok(())
+    }
+
+    pub fn remove(self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+}
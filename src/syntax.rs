@@ -0,0 +1,458 @@
+use std::{fs, path::Path, rc::Rc};
+
+use regex::Regex;
+
+use crate::config;
+
+/// Syntax definitions that are compiled into bvim. Definitions in the `syntax` directory of the
+/// config directory replace built-in ones with the same name.
+const BUILTIN_SYNTAXES: &[(&str, &str)] = &[
+    ("rust.syntax", include_str!("../syntax/rust.syntax")),
+    ("toml.syntax", include_str!("../syntax/toml.syntax")),
+    ("markdown.syntax", include_str!("../syntax/markdown.syntax")),
+    ("sh.syntax", include_str!("../syntax/sh.syntax")),
+    (
+        "directory.syntax",
+        include_str!("../syntax/directory.syntax"),
+    ),
+];
+
+#[derive(Debug)]
+enum RuleAction {
+    None,
+    Push(usize),
+    Pop,
+}
+
+#[derive(Debug)]
+struct Rule {
+    regex: Regex,
+    group: Option<Rc<str>>,
+    action: RuleAction,
+}
+
+#[derive(Debug)]
+struct Context {
+    name: String,
+    /// Group of all text in this context that isn't matched by a rule with its own group
+    group: Option<Rc<str>>,
+    rules: Vec<Rule>,
+}
+
+/// A highlighted part of a line, given as byte range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub group: Rc<str>,
+}
+
+/// The stack of contexts at a certain point of a buffer, the last one being the active one
+type State = Vec<usize>;
+
+/// A language definition describing how to highlight it.
+///
+/// Definitions are written in a line based format similar to sublime-syntax files: text is
+/// matched by the rules of the active context, the earliest match winning and the first rule
+/// winning ties. Rules can push another context onto the context stack or pop the active one.
+///
+/// ```text
+/// # comments start with a '#'
+/// name        <name>
+/// extensions  <extension>...
+/// firstline   <regex>                   (detects files without a known extension)
+/// context     <name> [<group>]          (the first context has to be called 'main')
+/// match       <group> <regex>
+/// push        <context> <group> <regex>
+/// pop         <group> <regex>
+/// ```
+///
+/// A group of `-` stands for no group. If a rule's regex contains a capture group, only the
+/// text of the first capture group is highlighted with the rule's group.
+#[derive(Debug)]
+pub struct SyntaxDefinition {
+    pub name: String,
+    extensions: Vec<String>,
+    first_line: Option<Regex>,
+    contexts: Vec<Context>,
+}
+
+impl SyntaxDefinition {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut name = None;
+        let mut extensions = Vec::new();
+        let mut first_line = None;
+        let mut contexts: Vec<Context> = Vec::new();
+        // Push targets can only be resolved once all contexts are known
+        let mut pushes: Vec<(usize, usize, usize, String)> = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: String| format!("line {}: {}", line_number, message);
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, arguments) = match line.split_once(char::is_whitespace) {
+                Some((keyword, arguments)) => (keyword, arguments.trim_start()),
+                None => (line, ""),
+            };
+
+            match keyword {
+                "name" => name = Some(arguments.to_owned()),
+                "extensions" => {
+                    extensions = arguments.split_whitespace().map(str::to_owned).collect()
+                }
+                "firstline" => first_line = Some(compile(arguments).map_err(error)?),
+                "context" => {
+                    let (context_name, group) = match arguments.split_once(char::is_whitespace) {
+                        Some((context_name, group)) => (context_name, parse_group(group.trim())),
+                        None => (arguments, None),
+                    };
+                    if context_name.is_empty() {
+                        return Err(error("context name missing".to_owned()));
+                    }
+                    if contexts.is_empty() && context_name != "main" {
+                        return Err(error(
+                            "the first context has to be called 'main'".to_owned(),
+                        ));
+                    }
+                    contexts.push(Context {
+                        name: context_name.to_owned(),
+                        group,
+                        rules: Vec::new(),
+                    });
+                }
+                "match" | "push" | "pop" => {
+                    let context_index = contexts
+                        .len()
+                        .checked_sub(1)
+                        .ok_or_else(|| error(format!("'{}' outside of a context", keyword)))?;
+
+                    let words = if keyword == "push" { 2 } else { 1 };
+                    let (words, pattern) = split_words(arguments, words)
+                        .ok_or_else(|| error(format!("not enough arguments for '{}'", keyword)))?;
+                    let group = parse_group(words[words.len() - 1]);
+                    let action = match keyword {
+                        "push" => {
+                            let rule_index = contexts[context_index].rules.len();
+                            pushes.push((
+                                line_number,
+                                context_index,
+                                rule_index,
+                                words[0].to_owned(),
+                            ));
+                            RuleAction::Push(0)
+                        }
+                        "pop" => RuleAction::Pop,
+                        _ => RuleAction::None,
+                    };
+
+                    contexts[context_index].rules.push(Rule {
+                        regex: compile(pattern).map_err(error)?,
+                        group,
+                        action,
+                    });
+                }
+                keyword => return Err(error(format!("unknown keyword '{}'", keyword))),
+            }
+        }
+
+        for (line_number, context_index, rule_index, target) in pushes {
+            let target_index = contexts
+                .iter()
+                .position(|context| context.name == target)
+                .ok_or_else(|| format!("line {}: unknown context '{}'", line_number, target))?;
+            contexts[context_index].rules[rule_index].action = RuleAction::Push(target_index);
+        }
+
+        if contexts.is_empty() {
+            return Err("no context defined".to_owned());
+        }
+
+        Ok(Self {
+            name: name.ok_or_else(|| "no name defined".to_owned())?,
+            extensions,
+            first_line,
+            contexts,
+        })
+    }
+
+    fn initial_state(&self) -> State {
+        vec![0]
+    }
+
+    /// Highlights a single line starting in the given state, which is updated to the state at the
+    /// end of the line.
+    fn highlight_line(&self, line: &str, state: &mut State) -> Vec<Span> {
+        let mut spans: Vec<Span> = Vec::new();
+        let mut push_span = |start: usize, end: usize, group: &Option<Rc<str>>| {
+            let Some(group) = group else {
+                return;
+            };
+            if start == end {
+                return;
+            }
+            // Merge with the previous span if possible to keep the number of spans low
+            if let Some(last) = spans.last_mut() {
+                if last.end == start && last.group == *group {
+                    last.end = end;
+                    return;
+                }
+            }
+            spans.push(Span {
+                start,
+                end,
+                group: group.clone(),
+            });
+        };
+
+        let mut position = 0;
+        while position < line.len() {
+            let context = &self.contexts[*state.last().unwrap()];
+
+            // Find the earliest match, earlier rules winning ties. Empty matches are ignored as
+            // they would never advance the position.
+            let mut best: Option<(&Rule, regex::Captures)> = None;
+            for rule in &context.rules {
+                let Some(captures) = rule.regex.captures_at(line, position) else {
+                    continue;
+                };
+                let whole = captures.get(0).unwrap();
+                if whole.is_empty() {
+                    continue;
+                }
+                let better = match &best {
+                    Some((_, best)) => whole.start() < best.get(0).unwrap().start(),
+                    None => true,
+                };
+                if better {
+                    let start = whole.start();
+                    best = Some((rule, captures));
+                    if start == position {
+                        break;
+                    }
+                }
+            }
+
+            let Some((rule, captures)) = best else {
+                push_span(position, line.len(), &context.group);
+                break;
+            };
+            let whole = captures.get(0).unwrap();
+            push_span(position, whole.start(), &context.group);
+
+            let group = rule.group.clone().or_else(|| context.group.clone());
+            match captures.get(1) {
+                Some(capture) => {
+                    push_span(whole.start(), capture.start(), &context.group);
+                    push_span(capture.start(), capture.end(), &group);
+                    push_span(capture.end(), whole.end(), &context.group);
+                }
+                None => push_span(whole.start(), whole.end(), &group),
+            }
+
+            match rule.action {
+                RuleAction::None => {}
+                RuleAction::Push(context) => state.push(context),
+                RuleAction::Pop => {
+                    if state.len() > 1 {
+                        state.pop();
+                    }
+                }
+            }
+            position = whole.end();
+        }
+
+        spans
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| format!("invalid regex '{}': {}", pattern, e))
+}
+
+fn parse_group(group: &str) -> Option<Rc<str>> {
+    match group {
+        "" | "-" => None,
+        group => Some(Rc::from(group)),
+    }
+}
+
+/// Splits off the first `count` whitespace separated words and returns them together with the
+/// rest of the text.
+fn split_words(text: &str, count: usize) -> Option<(Vec<&str>, &str)> {
+    let mut words = Vec::new();
+    let mut rest = text;
+    for _ in 0..count {
+        let (word, remainder) = rest.split_once(char::is_whitespace)?;
+        words.push(word);
+        rest = remainder.trim_start();
+    }
+    if rest.is_empty() {
+        return None;
+    }
+    Some((words, rest))
+}
+
+/// All syntax definitions known to the editor
+#[derive(Debug, Default)]
+pub struct SyntaxSet {
+    syntaxes: Vec<Rc<SyntaxDefinition>>,
+}
+
+impl SyntaxSet {
+    /// Loads the built-in syntax definitions and those in the config directory. Definitions that
+    /// fail to load are skipped and their errors returned.
+    pub fn load() -> (Self, Vec<String>) {
+        let mut set = Self::default();
+        let mut errors = Vec::new();
+
+        for (name, source) in BUILTIN_SYNTAXES {
+            match SyntaxDefinition::parse(source) {
+                Ok(syntax) => set.add(syntax),
+                Err(message) => errors.push(format!("{}: {}", name, message)),
+            }
+        }
+
+        let Some(directory) = config::config_dir().map(|dir| dir.join("syntax")) else {
+            return (set, errors);
+        };
+        let Ok(entries) = fs::read_dir(&directory) else {
+            return (set, errors);
+        };
+        let mut paths = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "syntax")
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            let result = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|source| SyntaxDefinition::parse(&source));
+            match result {
+                Ok(syntax) => set.add(syntax),
+                Err(message) => errors.push(format!("{}: {}", path.display(), message)),
+            }
+        }
+
+        (set, errors)
+    }
+
+    fn add(&mut self, syntax: SyntaxDefinition) {
+        self.syntaxes
+            .retain(|existing| existing.name != syntax.name);
+        self.syntaxes.push(Rc::new(syntax));
+    }
+
+    pub fn find(&self, name: &str) -> Option<Rc<SyntaxDefinition>> {
+        self.syntaxes
+            .iter()
+            .find(|syntax| syntax.name == name)
+            .cloned()
+    }
+
+    /// Guesses the file type from the file's extension or, if that doesn't work, its first line.
+    pub fn detect(&self, path: Option<&Path>, first_line: &str) -> Option<&str> {
+        let extension = path
+            .and_then(|path| path.extension())
+            .and_then(|extension| extension.to_str());
+        if let Some(extension) = extension {
+            if let Some(syntax) = self
+                .syntaxes
+                .iter()
+                .find(|syntax| syntax.extensions.iter().any(|e| e == extension))
+            {
+                return Some(&syntax.name);
+            }
+        }
+
+        self.syntaxes
+            .iter()
+            .find(|syntax| {
+                syntax
+                    .first_line
+                    .as_ref()
+                    .is_some_and(|regex| regex.is_match(first_line))
+            })
+            .map(|syntax| syntax.name.as_str())
+    }
+}
+
+#[derive(Debug)]
+struct LineCache {
+    start_state: State,
+    end_state: State,
+    spans: Vec<Span>,
+}
+
+/// Highlights a buffer incrementally.
+///
+/// The highlighting of a line only depends on its contents and the state at its start, so after
+/// an edit only the changed lines and the lines whose start state changed because of it have to
+/// be highlighted again.
+#[derive(Debug)]
+pub struct Highlighter {
+    syntax: Rc<SyntaxDefinition>,
+    /// The cached highlighting of every line of the buffer, `None` for lines that changed
+    lines: Vec<Option<LineCache>>,
+    /// All lines before this one are known to be up to date
+    first_unchecked: usize,
+}
+
+impl Highlighter {
+    pub fn new(syntax: Rc<SyntaxDefinition>, line_count: usize) -> Self {
+        Self {
+            syntax,
+            lines: (0..line_count).map(|_| None).collect(),
+            first_unchecked: 0,
+        }
+    }
+
+    pub fn edited(&mut self, edit: &crate::buffer::Edit) {
+        let first = edit.start.y;
+        let old_last = edit.old_end().y;
+        let new_last = edit.new_end().y;
+        self.lines
+            .splice(first..=old_last, (first..=new_last).map(|_| None));
+        self.first_unchecked = self.first_unchecked.min(first);
+    }
+
+    pub fn update(&mut self, lines: &[String], last_line: usize) {
+        let last_line = last_line.min(lines.len().saturating_sub(1));
+        while self.first_unchecked <= last_line {
+            let index = self.first_unchecked;
+            let start_state = match index {
+                0 => self.syntax.initial_state(),
+                _ => self.lines[index - 1].as_ref().unwrap().end_state.clone(),
+            };
+
+            let up_to_date = matches!(
+                &self.lines[index],
+                Some(cache) if cache.start_state == start_state
+            );
+            if !up_to_date {
+                let mut state = start_state.clone();
+                let spans = self.syntax.highlight_line(&lines[index], &mut state);
+                self.lines[index] = Some(LineCache {
+                    start_state,
+                    end_state: state,
+                    spans,
+                });
+            }
+
+            self.first_unchecked += 1;
+        }
+    }
+
+    pub fn spans(&self, line: usize) -> &[Span] {
+        match self.lines.get(line) {
+            Some(Some(cache)) => &cache.spans,
+            _ => &[],
+        }
+    }
+}
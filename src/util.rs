@@ -1,4 +1,6 @@
-#[derive(Debug, Clone, Copy)]
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Position<T: Copy> {
     pub x: T,
     pub y: T,
@@ -9,3 +11,27 @@ impl<T: Copy> Position<T> {
         Self { x, y }
     }
 }
+
+/// Returns how a file name is shown: relative to the working directory if the file is inside of
+/// it, with anything that isn't valid UTF-8 replaced
+pub fn display_path(path: &Path) -> String {
+    let relative = std::env::current_dir()
+        .ok()
+        .and_then(|directory| path.strip_prefix(directory).ok().map(Path::to_path_buf));
+    match relative {
+        Some(relative) if relative.as_os_str().is_empty() => ".".to_owned(),
+        Some(relative) => relative.to_string_lossy().into_owned(),
+        None => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// Replaces a leading `~` of a path with the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    let home = std::env::var_os("HOME");
+    match (path.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            PathBuf::from(home).join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
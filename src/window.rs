@@ -1,7 +1,18 @@
-use blessings::{Screen, WindowBounds};
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use blessings::WindowBounds;
 use crossterm::event::MouseButton;
 
-use crate::{buffer::Buffer, util::Position};
+use crate::{
+    buffer::Buffer,
+    highlight::Highlights,
+    options::{OptionScope, Options},
+    screen::Screen,
+    util::Position,
+};
 
 #[derive(Debug)]
 pub struct Window {
@@ -9,6 +20,9 @@ pub struct Window {
     scroll: Position<usize>,
     cursor: Position<usize>,
     bounds: WindowBounds,
+    options: Options,
+    /// The working directory set with `:lcd`, which takes precedence over the others
+    local_directory: Option<PathBuf>,
 }
 impl Window {
     pub fn new(buffer: Buffer, bounds: WindowBounds) -> Self {
@@ -20,9 +34,19 @@ impl Window {
             scroll,
             cursor,
             bounds,
+            options: Options::new_local(OptionScope::Window),
+            local_directory: None,
         }
     }
 
+    pub fn get_local_directory(&self) -> Option<&Path> {
+        self.local_directory.as_deref()
+    }
+
+    pub fn set_local_directory(&mut self, directory: Option<PathBuf>) {
+        self.local_directory = directory;
+    }
+
     pub fn get_buffer(&self) -> &Buffer {
         &self.buffer
     }
@@ -31,27 +55,138 @@ impl Window {
         &mut self.buffer
     }
 
+    /// Replaces the window's buffer, moving the cursor to its start, and returns the old one.
+    pub fn set_buffer(&mut self, buffer: Buffer) -> Buffer {
+        self.cursor = Position::new(0, 0);
+        self.scroll = Position::new(0, 0);
+        std::mem::replace(&mut self.buffer, buffer)
+    }
+
+    pub fn get_cursor(&self) -> Position<usize> {
+        self.cursor
+    }
+
+    /// Moves the cursor to the given position, clamping it to the buffer's contents, and scrolls
+    /// to it.
+    pub fn set_cursor(&mut self, position: Position<usize>) {
+        self.cursor.y = position.y.min(self.buffer.lines.len() - 1);
+        self.cursor.x = position.x.min(self.buffer.line_length(self.cursor.y));
+
+        if self.cursor.y < self.scroll.y {
+            self.scroll.y = self.cursor.y;
+        }
+        if self.cursor.x < self.scroll.x {
+            self.scroll.x = self.cursor.x;
+        }
+        self.scroll_to_cursor();
+    }
+
+    pub fn get_options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn get_options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    /// Width of the sign column and the line number column together
+    fn gutter_width(&self) -> usize {
+        self.sign_column_width() + self.number_column_width()
+    }
+
+    /// Width of the column showing diagnostic signs, which is only shown for `signcolumn=auto`
+    /// if there are any diagnostics
+    fn sign_column_width(&self) -> usize {
+        match self.options.get_string("signcolumn") {
+            "yes" => 2,
+            "auto" if !self.buffer.diagnostics.is_empty() => 2,
+            _ => 0,
+        }
+    }
+
+    /// Width of the line number column including the separating space, or 0 if line numbers are
+    /// disabled
+    fn number_column_width(&self) -> usize {
+        if self.options.get_bool("number") || self.options.get_bool("relativenumber") {
+            self.buffer.lines.len().to_string().len().max(3) + 1
+        } else {
+            0
+        }
+    }
+
+    /// Width of the area the buffer's text is rendered into
+    fn text_width(&self) -> usize {
+        (self.bounds.width as usize)
+            .saturating_sub(self.gutter_width())
+            .max(1)
+    }
+
     pub fn set_bounds(&mut self, bounds: WindowBounds) {
         self.bounds = bounds;
 
         // Fix scroll after resize if necessary
+        self.scroll_to_cursor();
+    }
+
+    /// Adjusts the scroll position so the cursor is visible again after something other than a
+    /// cursor movement changed the visible area
+    pub fn scroll_to_cursor(&mut self) {
         // TODO: enforce a relative relation between cursor and window instead of just clamping it
         if self.cursor.y - self.scroll.y >= self.bounds.height as usize {
             self.scroll.y = self.cursor.y - self.bounds.height as usize + 1;
         }
-        if self.cursor.x - self.scroll.x >= self.bounds.width as usize {
-            self.scroll.x = self.cursor.x - self.bounds.width as usize + 1;
+        if self.cursor.x - self.scroll.x >= self.text_width() {
+            self.scroll.x = self.cursor.x - self.text_width() + 1;
         }
     }
 
-    pub fn render(&self, screen: &mut Screen) {
+    /// Renders the window. `virtual_text` enables showing diagnostic messages after the end of
+    /// their lines.
+    pub fn render(&mut self, screen: &mut Screen, highlights: &Highlights, virtual_text: bool) {
+        self.buffer
+            .update_highlighting(self.scroll.y + self.bounds.height as usize);
+
         screen.begin_window(0, 0, self.bounds.width, self.bounds.height);
 
+        let gutter_width = self.gutter_width();
+        let sign_width = self.sign_column_width();
+
+        // Fill the whole window so the background color of Normal is used everywhere
+        highlights.apply(screen, "Normal");
+        let blank = " ".repeat(self.bounds.width as usize);
+        for row in 0..self.bounds.height {
+            screen.print_at(0, row, &blank);
+        }
+
         for i in 0..(self.bounds.height as usize).min(self.buffer.lines.len() - self.scroll.y) {
+            if sign_width > 0 {
+                self.render_sign(screen, highlights, self.scroll.y + i, i as u16);
+            }
+            if gutter_width > sign_width {
+                self.render_line_number(
+                    screen,
+                    highlights,
+                    self.scroll.y + i,
+                    i as u16,
+                    sign_width,
+                    gutter_width - sign_width,
+                );
+            }
+
+            let line_length = self.buffer.line_length(self.scroll.y + i);
+            if virtual_text {
+                self.render_virtual_text(
+                    screen,
+                    highlights,
+                    self.scroll.y + i,
+                    i as u16,
+                    line_length,
+                );
+            }
+
             if self.buffer.lines[self.scroll.y + i].len() == 0 {
                 continue;
             }
-            let line_length = self.buffer.line_length(self.scroll.y + i);
             if line_length <= self.scroll.x {
                 continue;
             }
@@ -75,7 +210,7 @@ impl Window {
             // might have just been made irrelevant by caching the following line_length
             // calculation from before
             // > let line_length = self.buffer.line_length(self.scroll.y + i);
-            let right = self.scroll.x + self.bounds.width as usize;
+            let right = self.scroll.x + self.text_width();
             let end = if line_length <= right {
                 self.buffer.lines[self.scroll.y + i].len()
             } else {
@@ -87,25 +222,151 @@ impl Window {
             };
             /*let end = self.buffer.lines[self.scroll.y + i]
             .char_indices()
-            .nth(self.scroll.x + self.bounds.width as usize)
+            .nth(self.scroll.x + self.text_width())
             .unwrap_or((self.buffer.lines[self.scroll.y + i].len(), ' '))
             .0;*/
 
-            screen.print_at(
-                0,
+            self.render_text(
+                screen,
+                highlights,
+                self.scroll.y + i,
                 i as u16,
-                &self.buffer.lines[self.scroll.y + i][start..end],
+                start..end,
+                gutter_width as u16,
             );
         }
 
+        screen.clear_colors();
         screen.move_to(
-            (self.cursor.x - self.scroll.x) as u16,
+            (gutter_width + self.cursor.x - self.scroll.x) as u16,
             (self.cursor.y - self.scroll.y) as u16,
         );
 
         screen.end_window();
     }
 
+    /// Renders the given byte range of a line with its syntax highlighting and diagnostics
+    fn render_text(
+        &self,
+        screen: &mut Screen,
+        highlights: &Highlights,
+        line: usize,
+        row: u16,
+        range: Range<usize>,
+        x: u16,
+    ) {
+        let text = &self.buffer.lines[line];
+
+        // One group per byte keeps overlaying diagnostics on top of the syntax simple
+        let mut groups = vec![None; text.len()];
+        for span in self.buffer.highlight_spans(line) {
+            groups[span.start..span.end].fill(Some(span.group.to_string()));
+        }
+        let mut diagnostics = self.buffer.diagnostics.on_line(line).collect::<Vec<_>>();
+        // Draw the most severe diagnostics last so they win where they overlap
+        diagnostics.sort_by_key(|diagnostic| std::cmp::Reverse(diagnostic.severity));
+        let line_length = self.buffer.line_length(line);
+        for diagnostic in diagnostics {
+            let Some((start, mut end)) = diagnostic.columns(line, line_length) else {
+                continue;
+            };
+            // Make empty ranges visible by underlining the character at their position
+            if start == end && end < line_length {
+                end += 1;
+            }
+            let start = self.buffer.byte_index(Position::new(start, line));
+            let end = self.buffer.byte_index(Position::new(end, line));
+            groups[start..end].fill(Some(diagnostic.severity.highlight_group("Underline")));
+        }
+
+        let mut x = x;
+        let mut start = range.start;
+        while start < range.end {
+            let group = &groups[start];
+            let mut end = start + 1;
+            while end < range.end && (&groups[end] == group || !text.is_char_boundary(end)) {
+                end += 1;
+            }
+            highlights.apply(screen, group.as_deref().unwrap_or("Normal"));
+            screen.print_at(x, row, &text[start..end]);
+            x += text[start..end].chars().count() as u16;
+            start = end;
+        }
+        highlights.apply(screen, "Normal");
+    }
+
+    /// Renders the sign of the most severe diagnostic on a line
+    fn render_sign(&self, screen: &mut Screen, highlights: &Highlights, line: usize, row: u16) {
+        if let Some(diagnostic) = self.buffer.diagnostics.worst_on_line(line) {
+            highlights.apply(screen, &diagnostic.severity.highlight_group("Sign"));
+            screen.print_at(0, row, diagnostic.severity.sign());
+            highlights.apply(screen, "Normal");
+        }
+    }
+
+    /// Renders the message of the most severe diagnostic on a line behind its end
+    fn render_virtual_text(
+        &self,
+        screen: &mut Screen,
+        highlights: &Highlights,
+        line: usize,
+        row: u16,
+        line_length: usize,
+    ) {
+        let Some(diagnostic) = self.buffer.diagnostics.worst_on_line(line) else {
+            return;
+        };
+        let column = (line_length + 2).saturating_sub(self.scroll.x);
+        let available = self.text_width().saturating_sub(column);
+        if available == 0 {
+            return;
+        }
+        let message = diagnostic.message.lines().next().unwrap_or_default();
+        let text = format!("■ {}", message)
+            .chars()
+            .take(available)
+            .collect::<String>();
+        highlights.apply(screen, &diagnostic.severity.highlight_group("VirtualText"));
+        screen.print_at((self.gutter_width() + column) as u16, row, text);
+        highlights.apply(screen, "Normal");
+    }
+
+    fn render_line_number(
+        &self,
+        screen: &mut Screen,
+        highlights: &Highlights,
+        line: usize,
+        row: u16,
+        x: usize,
+        width: usize,
+    ) {
+        let number = if !self.options.get_bool("relativenumber") {
+            line + 1
+        } else if line == self.cursor.y {
+            // Like vim, show the absolute number on the cursor line if both options are set
+            if self.options.get_bool("number") {
+                line + 1
+            } else {
+                0
+            }
+        } else {
+            line.abs_diff(self.cursor.y)
+        };
+
+        let group = if line == self.cursor.y {
+            "CursorLineNr"
+        } else {
+            "LineNr"
+        };
+        highlights.apply(screen, group);
+        screen.print_at(
+            x as u16,
+            row,
+            format!("{:>width$} ", number, width = width - 1),
+        );
+        highlights.apply(screen, "Normal");
+    }
+
     pub fn move_up(&mut self) {
         if self.cursor.y > 0 {
             self.cursor.y -= 1;
@@ -150,8 +411,8 @@ impl Window {
             // Move cursor to the end of the new line
             self.cursor.x = self.buffer.line_length(self.cursor.y);
             // Scroll right if necessary
-            if self.cursor.x >= self.scroll.x + self.bounds.width as usize {
-                self.scroll.x = self.cursor.x - self.bounds.width as usize + 1;
+            if self.cursor.x >= self.scroll.x + self.text_width() {
+                self.scroll.x = self.cursor.x - self.text_width() + 1;
             }
             // Scroll up if necessary
             if self.cursor.y < self.scroll.y {
@@ -164,7 +425,7 @@ impl Window {
         if self.cursor.x < self.buffer.line_length(self.cursor.y) {
             self.cursor.x += 1;
             // Scroll right if necessary
-            if self.cursor.x >= self.scroll.x + self.bounds.width as usize {
+            if self.cursor.x >= self.scroll.x + self.text_width() {
                 self.scroll.x += 1;
             }
         } else if self.cursor.y < self.buffer.lines.len() - 1 {
@@ -198,8 +459,8 @@ impl Window {
             self.scroll.x = self.cursor.x;
         }
         // Scroll right if necessary
-        if self.cursor.x >= self.scroll.x + self.bounds.width as usize {
-            self.scroll.x = self.cursor.x - self.bounds.width as usize + 1;
+        if self.cursor.x >= self.scroll.x + self.text_width() {
+            self.scroll.x = self.cursor.x - self.text_width() + 1;
         }
     }
 
@@ -207,27 +468,18 @@ impl Window {
         self.cursor.x = self.buffer.line_length(self.cursor.y);
 
         // Scroll right if necessary
-        if self.cursor.x >= self.scroll.x + self.bounds.width as usize {
-            self.scroll.x = self.cursor.x - self.bounds.width as usize + 1;
+        if self.cursor.x >= self.scroll.x + self.text_width() {
+            self.scroll.x = self.cursor.x - self.text_width() + 1;
         }
     }
 
     pub fn insert_char(&mut self, c: char) {
+        let mut buffer = [0; 4];
+        self.buffer
+            .insert_text(self.cursor, c.encode_utf8(&mut buffer));
+
         match c {
             '\n' => {
-                let new_line = if self.cursor.x == 0 {
-                    std::mem::replace(&mut self.buffer.lines[self.cursor.y], String::new())
-                } else {
-                    let index = self.buffer.lines[self.cursor.y]
-                        .char_indices()
-                        .nth(self.cursor.x);
-                    match index {
-                        Some((index, _)) => self.buffer.lines[self.cursor.y].split_off(index),
-                        None => String::new(),
-                    }
-                };
-                self.buffer.lines.insert(self.cursor.y + 1, new_line);
-
                 self.cursor.y += 1;
                 self.cursor.x = 0;
                 self.scroll.x = 0;
@@ -237,57 +489,51 @@ impl Window {
                     self.scroll.y += 1;
                 }
             }
-            c => {
-                let index = self.buffer.lines[self.cursor.y]
-                    .char_indices()
-                    .nth(self.cursor.x);
-                let index = if let Some((index, _)) = index {
-                    index
-                } else {
-                    self.buffer.lines[self.cursor.y].len()
-                };
-                self.buffer.lines[self.cursor.y].insert(index, c);
+            _ => {
                 self.cursor.x += 1;
 
                 // Scroll right if necessary
-                if self.cursor.x >= self.scroll.x + self.bounds.width as usize {
+                if self.cursor.x >= self.scroll.x + self.text_width() {
                     self.scroll.x += 1;
                 }
             }
         }
-        self.buffer.changed = true;
+    }
+
+    pub fn insert_tab(&mut self) {
+        if self.buffer.options.get_bool("expandtab") {
+            let tabstop = (self.buffer.options.get_int("tabstop") as usize).max(1);
+            for _ in 0..(tabstop - self.cursor.x % tabstop) {
+                self.insert_char(' ');
+            }
+        } else {
+            self.insert_char('\t');
+        }
     }
 
     pub fn remove_char(&mut self) {
         if self.cursor.x == 0 {
             if self.cursor.y > 0 {
-                let line = self.buffer.lines.remove(self.cursor.y);
-                // Move the cursor first because we have to append to the line above anyways
+                let end = self.cursor;
                 self.cursor.y -= 1;
                 self.cursor.x = self.buffer.line_length(self.cursor.y);
-                self.buffer.lines[self.cursor.y].push_str(line.as_str());
-                self.buffer.changed = true;
+                self.buffer.remove_text(self.cursor, end);
 
                 // Scroll up if necessary
                 if self.cursor.y < self.scroll.y {
                     self.scroll.y -= 1;
                 }
                 // Scroll right if necessary
-                if self.cursor.x >= self.scroll.x + self.bounds.width as usize {
-                    self.scroll.x = self.cursor.x - self.bounds.width as usize + 1;
+                if self.cursor.x >= self.scroll.x + self.text_width() {
+                    self.scroll.x = self.cursor.x - self.text_width() + 1;
                 }
             }
         } else {
             // Remove the character IN FRONT of the cursor
             // Therefore move first, then remove
+            let end = self.cursor;
             self.cursor.x -= 1;
-            let index = self.buffer.lines[self.cursor.y]
-                .char_indices()
-                .nth(self.cursor.x)
-                .unwrap()
-                .0;
-            self.buffer.lines[self.cursor.y].remove(index);
-            self.buffer.changed = true;
+            self.buffer.remove_text(self.cursor, end);
 
             // Scroll left if necessary
             if self.cursor.x < self.scroll.x {
@@ -297,26 +543,21 @@ impl Window {
     }
 
     pub fn delete_char(&mut self) {
-        if self.cursor.x == self.buffer.line_length(self.cursor.y) {
-            if self.cursor.y < self.buffer.lines.len() - 2 {
-                let line = self.buffer.lines.remove(self.cursor.y + 1);
-                self.buffer.lines[self.cursor.y].push_str(line.as_str());
-                self.buffer.changed = true;
+        let end = if self.cursor.x == self.buffer.line_length(self.cursor.y) {
+            if self.cursor.y + 1 >= self.buffer.lines.len() {
+                return;
             }
+            Position::new(0, self.cursor.y + 1)
         } else {
-            let index = self.buffer.lines[self.cursor.y]
-                .char_indices()
-                .nth(self.cursor.x)
-                .unwrap()
-                .0;
-            self.buffer.lines[self.cursor.y].remove(index);
-            self.buffer.changed = true;
-        }
+            Position::new(self.cursor.x + 1, self.cursor.y)
+        };
+        self.buffer.remove_text(self.cursor, end);
     }
 
     pub fn mouse_down(&mut self, button: MouseButton, row: u16, column: u16) {
         let line = self.scroll.y + row as usize - self.bounds.y as usize;
-        let clicked_column = self.scroll.x + column as usize - self.bounds.x as usize;
+        let clicked_column = self.scroll.x
+            + (column as usize - self.bounds.x as usize).saturating_sub(self.gutter_width());
         match button {
             MouseButton::Left => {
                 self.cursor.y = line;